@@ -13,3 +13,6 @@ fn main() {
         res.compile().unwrap();
     }
 }
+
+#[cfg(not(windows))]
+fn main() {}
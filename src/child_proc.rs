@@ -1,34 +1,219 @@
-use crate::logger::log;
+use crate::crash_report::{self, Crash};
+use crate::file_watch::FileWatcher;
+use crate::journal::{self, Event, EventKind};
+use crate::logger::{log, verbose};
+use crate::output::{Capture, OutputTail};
 use crate::proc_config::*;
-use std::process::{Child, Command};
+use crate::readiness::Readiness;
+use crate::resource_watch::Watchdog;
+use crate::runtime_state::Survivor;
+use crate::sockets;
+use crate::units::{self, HumanDuration};
+use chrono::{DateTime, Local, Utc};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use std::process::Child;
 use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
 use std::sync::{Arc, Mutex};
 use std::thread::{self, JoinHandle};
-use std::time::Duration;
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum ProcessState {
+    /// Not spawned yet, or not ready yet per `ready`.
+    Starting,
+    Running,
+    /// Paused per `pause_mode`, not restarted until resumed.
+    Paused,
+    /// A job waiting for its next run.
+    Scheduled,
+    /// Started on the next connection to its sockets, per `lazy`.
+    Listening,
+    /// Exited or failed to spawn, waiting for `restart_delay`.
+    Restarting,
+    /// Exited and not restarted per `restart`.
+    Exited,
+    /// Exited with one of `fatal_exit_codes`, not restarted until requested.
+    Fatal,
+    /// Stopped by the supervisor.
+    Stopped,
+    /// Disabled or without a program.
+    Disabled,
+}
+
+/// What the supervisor knows about one process, shared with its thread.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProcessStatus {
+    pub name: String,
+    pub state: ProcessState,
+    pub pid: u32,
+    pub restarts: u32,
+    /// Restarts per `max_lifetime` and `restart_schedule`, not counted in `restarts`.
+    #[serde(default)]
+    pub planned_restarts: u32,
+    /// Last exit or spawn error, e.g. `exit status: 1`.
+    pub last_exit: Option<String>,
+    /// Whether the last exit was a failure.
+    pub failed: bool,
+    /// What the last exit means, per `success_exit_codes` and the like.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exit_class: Option<ExitClass>,
+    /// When the current or last run of a job started.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_run: Option<DateTime<Utc>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub next_run: Option<DateTime<Utc>>,
+}
+
+impl ProcessStatus {
+    fn new(name: &str) -> ProcessStatus {
+        ProcessStatus {
+            name: name.to_string(),
+            state: ProcessState::Starting,
+            pid: 0,
+            restarts: 0,
+            planned_restarts: 0,
+            last_exit: None,
+            failed: false,
+            exit_class: None,
+            last_run: None,
+            next_run: None,
+        }
+    }
+}
+
+/// Where a process thread sends the outcome of a command.
+pub type Reply = Sender<Result<String, String>>;
+
+/// Requests handled by a process thread between polls.
+#[derive(Debug)]
+pub enum ProcessCommand {
+    Pause(Reply),
+    Resume(Reply),
+    Restart(Reply),
+    /// Sends a signal, by name.
+    Signal(String, Reply),
+    /// Runs one of `actions`, by name.
+    Action(String, Reply),
+}
 
 pub struct ChildProcess {
     pub config: ProcessConfig,
+    pub status: Arc<Mutex<ProcessStatus>>,
     child: Option<Child>,
+    exited_at: Option<Instant>,
+    started_at: Option<Instant>,
+    /// Exited and not to be restarted per `restart`.
+    finished: bool,
+    /// Started a run that `post_stop` hasn't run for yet.
+    needs_post_stop: bool,
+    /// Failed runs in a row, for `restart_backoff`.
+    failures: u32,
+    paused: bool,
+    /// What the process was doing when paused, restored by `resume`.
+    paused_from: Option<ProcessState>,
+    /// Left running by a previous supervisor, watched instead of `child`.
+    adopted: Option<Survivor>,
+    output: OutputTail,
+    capture: Option<Capture>,
+    watchdog: Option<Watchdog>,
+    /// Next run of a scheduled job.
+    next_run: Option<DateTime<Local>>,
+    /// Another run is due once the current one exits, per `overlap`.
+    queued: bool,
+    /// Why the supervisor ended the current run, reported instead of its exit status.
+    stop_reason: Option<String>,
+    /// Changes to the `watch` paths, once the thread has started watching them.
+    files: Option<FileWatcher>,
+    readiness: Option<Readiness>,
+    /// What has to happen before the first start.
+    wait_for: Vec<Dependency>,
+    /// When the current run reaches its `max_lifetime`.
+    expires_at: Option<Instant>,
+    /// Next restart per `restart_schedule`.
+    next_planned: Option<DateTime<Local>>,
+}
+
+/// A process another one waits for before its first start.
+struct Dependency {
+    status: Arc<Mutex<ProcessStatus>>,
+    /// A job run once, waited for to finish rather than to be ready.
+    once: bool,
+}
+
+impl Dependency {
+    /// Fatal counts as done: it won't change until someone restarts the dependency.
+    fn is_done(&self) -> bool {
+        let state = self.status.lock().unwrap().state;
+        match self.once {
+            true => matches!(
+                state,
+                ProcessState::Exited | ProcessState::Fatal | ProcessState::Disabled
+            ),
+            false => matches!(
+                state,
+                ProcessState::Running
+                    | ProcessState::Listening
+                    | ProcessState::Fatal
+                    | ProcessState::Disabled
+            ),
+        }
+    }
+}
+
+/// A supervised process as seen from outside its thread.
+pub struct ProcessHandle {
+    pub status: Arc<Mutex<ProcessStatus>>,
+    pub commands: Sender<ProcessCommand>,
+    pub thread: JoinHandle<()>,
 }
 
 impl ChildProcess {
     pub fn _new(program: &str, args: Vec<String>, workdir: String) -> ChildProcess {
-        ChildProcess {
-            config: ProcessConfig {
-                program: program.to_string(),
-                args: args,
-                cwd: workdir,
-                state: ProcessConfigState::Enabled,
-                pid: 0,
-            },
-            child: None,
-        }
+        ChildProcess::from_config(ProcessConfig::_new(program.to_string(), args, workdir))
+    }
+
+    /// One `ChildProcess` per instance of each config, dependencies first.
+    pub fn from_configs(list: Vec<ProcessConfig>) -> Vec<ChildProcess> {
+        let order = start_order(&list).unwrap_or_else(|_| (0..list.len()).collect());
+        order
+            .into_iter()
+            .flat_map(|index| list[index].instances())
+            .map(ChildProcess::from_config)
+            .collect()
     }
 
     pub fn from_config(config: ProcessConfig) -> ChildProcess {
+        let readiness = config.ready.as_ref().and_then(|ready| {
+            Readiness::new(ready, Path::new(&config.cwd))
+                .map_err(|err| log!("Invalid ready.stdout of {}: {}", &config.name, err))
+                .ok()
+        });
         ChildProcess {
-            config: config,
+            readiness,
+            status: Arc::new(Mutex::new(ProcessStatus::new(&config.name))),
+            output: OutputTail::new(config.output_lines),
+            watchdog: config.watchdog.clone().map(Watchdog::new),
+            config,
             child: None,
+            exited_at: None,
+            started_at: None,
+            finished: false,
+            needs_post_stop: false,
+            failures: 0,
+            paused: false,
+            paused_from: None,
+            adopted: None,
+            capture: None,
+            next_run: None,
+            queued: false,
+            stop_reason: None,
+            wait_for: vec![],
+            files: None,
+            expires_at: None,
+            next_planned: None,
         }
     }
 
@@ -53,16 +238,180 @@ impl ChildProcess {
     }
 
     pub fn start(&mut self) {
+        if self.config.job.is_some() {
+            self.update_status(|status| status.last_run = Some(Utc::now()));
+        }
+        if let Err(err) = self.run_hooks("pre_start", &self.config.pre_start, None) {
+            match self.config.pre_start_failure {
+                HookFailure::Fail => {
+                    log!("Can't start {}: {}", &self.config.name, err);
+                    return self.fail_start(err);
+                }
+                HookFailure::Ignore => log!("{}: {}, starting anyway", &self.config.name, err),
+            }
+        }
         self.child = match self.config.spawn_new() {
-            Ok(child) => {
+            Ok(mut child) => {
                 self.config.pid = child.id();
+                self.started_at = Some(Instant::now());
+                self.output.clear();
+                let watch = self.readiness.as_mut().and_then(Readiness::restart);
+                self.capture = Some(Capture::start(&mut child, &self.output, watch));
+                if let Some(watchdog) = self.watchdog.as_mut() {
+                    watchdog.reset();
+                }
+                let state = match self.readiness {
+                    Some(_) => ProcessState::Starting,
+                    None => ProcessState::Running,
+                };
+                self.update_status(|status| {
+                    status.state = state;
+                    status.pid = child.id();
+                });
+                journal::record(self.event(EventKind::Started, Some(child.id())));
+                self.needs_post_stop = true;
+                self.expires_at = self
+                    .config
+                    .max_lifetime
+                    .map(|lifetime| Instant::now() + jittered(lifetime.0));
                 Some(child)
             }
             Err(err) => {
                 log!("Can't start {:?}: {:?}", &self.config, &err);
-                None
+                return self.fail_start(err.to_string());
             }
         };
+        // Otherwise once ready
+        if self.readiness.is_none() {
+            self.post_start();
+        }
+    }
+
+    fn fail_start(&mut self, err: String) {
+        self.child = None;
+        self.update_status(|status| {
+            status.state = ProcessState::Restarting;
+            status.pid = 0;
+            status.last_exit = Some(err.clone());
+            status.failed = true;
+            status.exit_class = Some(ExitClass::Failure);
+        });
+        journal::record(Event {
+            status: Some(err),
+            failed: true,
+            ..self.event(EventKind::SpawnFailed, None)
+        });
+    }
+
+    /// Runs the commands of a hook in order, up to the first that fails.
+    fn run_hooks(
+        &self,
+        hook: &str,
+        commands: &[Vec<String>],
+        pid: Option<u32>,
+    ) -> Result<(), String> {
+        for command in commands {
+            verbose!(
+                "Running {} of {}: {}",
+                hook,
+                &self.config.name,
+                command.join(" ")
+            );
+            self.run_command(command, pid, self.config.hook_timeout)
+                .map_err(|err| format!("{} `{}` failed: {}", hook, command.join(" "), err))?;
+        }
+        Ok(())
+    }
+
+    fn post_start(&self) {
+        if let Err(err) = self.run_hooks("post_start", &self.config.post_start, self.pid()) {
+            log!("{}: {}", &self.config.name, err);
+        }
+    }
+
+    /// Runs `post_stop` once per run that started.
+    fn post_stop(&mut self) {
+        if !std::mem::take(&mut self.needs_post_stop) {
+            return;
+        }
+        if let Err(err) = self.run_hooks("post_stop", &self.config.post_stop, None) {
+            log!("{}: {}", &self.config.name, err);
+        }
+    }
+
+    /// Takes over a process left running by a previous supervisor instead of starting one.
+    pub fn adopt(&mut self, survivor: Survivor) {
+        log!("Adopting {} ({})", &self.config.name, survivor.pid);
+        self.config.pid = survivor.pid;
+        self.update_status(|status| {
+            status.state = ProcessState::Running;
+            status.pid = survivor.pid;
+        });
+        journal::record(self.event(EventKind::Adopted, Some(survivor.pid)));
+        self.needs_post_stop = true;
+        self.adopted = Some(survivor);
+    }
+
+    fn event(&self, event: EventKind, pid: Option<u32>) -> Event {
+        Event {
+            pid,
+            ..Event::new(&self.config.name, event)
+        }
+    }
+
+    /// Returns the report's file name.
+    fn write_crash_report(
+        &self,
+        status: &ProcessStatus,
+        uptime: Option<HumanDuration>,
+    ) -> Option<String> {
+        let crash = Crash {
+            config: &self.config,
+            pid: self.config.pid,
+            exit: status.last_exit.clone().unwrap_or_default(),
+            uptime: uptime.map(|uptime| uptime.0),
+            restarts: status.restarts,
+            output: self.output.lines(),
+        };
+        match crash_report::write(&crash_report::reports_dir(), &crash) {
+            Ok(path) => {
+                log!(
+                    "{} crashed ({}), see {}",
+                    &self.config.name,
+                    &crash.exit,
+                    path.display()
+                );
+                path.file_name()
+                    .map(|name| name.to_string_lossy().into_owned())
+            }
+            Err(err) => {
+                log!(
+                    "Can't write a crash report for {}: {:?}",
+                    &self.config.name,
+                    err
+                );
+                None
+            }
+        }
+    }
+
+    /// How long the current run lasted, for exits and stops.
+    fn uptime(&mut self) -> Option<HumanDuration> {
+        self.started_at
+            .take()
+            .map(|started| HumanDuration::from_secs(started.elapsed().as_secs()))
+    }
+
+    fn pid(&self) -> Option<u32> {
+        match (&self.child, &self.adopted) {
+            (Some(child), _) => Some(child.id()),
+            (None, Some(survivor)) => Some(survivor.pid),
+            (None, None) => None,
+        }
+    }
+
+    fn update_status<F: FnOnce(&mut ProcessStatus)>(&self, update: F) {
+        update(&mut self.status.lock().unwrap());
     }
 
     pub fn _start_restart_loop(&mut self) {
@@ -81,91 +430,1031 @@ impl ChildProcess {
         }
     }
 
+    /// Notices the exit of the current run and records it, returning what it means.
+    /// `None` while it's still running.
+    fn reap(&mut self) -> Option<ExitClass> {
+        let pid = self.pid();
+        let class = match self.child.as_mut().map(|child| child.try_wait()) {
+            Some(Ok(Some(status))) => {
+                let reason = self.stop_reason.take();
+                let class = match reason {
+                    Some(_) => ExitClass::Failure,
+                    None => self.config.classify(&status),
+                };
+                verbose!("{} exited: {} ({})", &self.config.program, status, class);
+                self.update_status(|s| {
+                    s.last_exit = Some(reason.unwrap_or_else(|| status.to_string()));
+                    s.failed = class != ExitClass::Success;
+                    s.exit_class = Some(class);
+                });
+                class
+            }
+            Some(Err(e)) => {
+                verbose!("Can't wait for {}: {:?}", &self.config.program, e);
+                self.update_status(|s| {
+                    s.last_exit = Some(e.to_string());
+                    s.failed = true;
+                    s.exit_class = Some(ExitClass::Failure);
+                });
+                ExitClass::Failure
+            }
+            Some(Ok(None)) => return None,
+            None => match self.adopted.take() {
+                Some(survivor) if survivor.is_alive() => {
+                    self.adopted = Some(survivor);
+                    return None;
+                }
+                // Not our child, so there is no exit status
+                Some(_) => {
+                    verbose!("Adopted {} exited", &self.config.name);
+                    self.update_status(|s| {
+                        s.last_exit = Some("exited after adoption".to_string());
+                        s.failed = true;
+                        s.exit_class = Some(ExitClass::Failure);
+                    });
+                    ExitClass::Failure
+                }
+                // Failed to start last time
+                None => ExitClass::Failure,
+            },
+        };
+
+        let ran = self.started_at.map(|started| started.elapsed());
+        self.failures = match (class, self.config.restart_backoff) {
+            (ExitClass::Success | ExitClass::Fatal, _) => 0,
+            (_, Some(backoff)) if ran.is_some_and(|ran| ran > backoff.0) => 1,
+            _ => self.failures + 1,
+        };
+        if pid.is_some() {
+            let status = self.status.lock().unwrap().clone();
+            let uptime = self.uptime();
+            // Expected to happen now and then, unlike a crash
+            let crashed = status.failed && class != ExitClass::Temporary;
+            let report = match (crashed, self.capture.take()) {
+                (true, Some(capture)) => {
+                    capture.finish(Duration::from_millis(500));
+                    self.write_crash_report(&status, uptime)
+                }
+                _ => None,
+            };
+            journal::record(Event {
+                status: status.last_exit,
+                failed: status.failed,
+                class: Some(class),
+                uptime,
+                report,
+                ..self.event(EventKind::Exited, pid)
+            });
+        }
+        self.post_stop();
+        Some(class)
+    }
+
+    /// `restart_delay` (a second if none), doubled per `restart_backoff` for each failure in a
+    /// row.
+    fn restart_delay(&self) -> Duration {
+        let delay = self.config.restart_delay.0;
+        match self.config.restart_backoff {
+            Some(backoff) if self.failures > 0 => {
+                let first = match delay.is_zero() {
+                    true => Duration::from_secs(1),
+                    false => delay,
+                };
+                first
+                    .saturating_mul(1 << (self.failures - 1).min(20))
+                    .min(backoff.0)
+            }
+            _ => delay,
+        }
+    }
+
+    /// Starts the process again once it has exited and `restart_delay` has passed.
     pub fn try_restart(&mut self) -> bool {
-        match self.child.as_mut().unwrap().try_wait() {
-            Ok(Some(status)) => {
-                dbg!(status);
-                self.start();
-                true
+        if self.finished || self.paused {
+            return false;
+        }
+        // Started by `check_connections` instead
+        if self.status.lock().unwrap().state == ProcessState::Listening {
+            return false;
+        }
+
+        if self.exited_at.is_none() {
+            let class = match self.reap() {
+                Some(class) => class,
+                None => return false,
+            };
+            let last_exit = self
+                .status
+                .lock()
+                .unwrap()
+                .last_exit
+                .clone()
+                .unwrap_or_default();
+
+            let restart = match (class, self.config.restart) {
+                (ExitClass::Fatal, _) => false,
+                (ExitClass::Temporary, _) => true,
+                // A job run once is done when it exits
+                (ExitClass::Success, RestartPolicy::Always) => self.config.job.is_none(),
+                (ExitClass::Failure, RestartPolicy::Always | RestartPolicy::OnFailure) => true,
+                _ => false,
+            };
+            if class == ExitClass::Fatal {
+                log!(
+                    "{} exited ({}, fatal), not restarting",
+                    &self.config.name,
+                    last_exit
+                );
+                self.child = None;
+                self.finished = true;
+                self.update_status(|s| {
+                    s.state = ProcessState::Fatal;
+                    s.pid = 0;
+                });
+                return false;
+            }
+            if !restart && self.config.lazy {
+                log!(
+                    "{} exited, starting on the next connection",
+                    &self.config.name
+                );
+                self.listen();
+                return false;
+            }
+            if !restart {
+                match self.config.job {
+                    Some(_) => log!("{} finished", &self.config.name),
+                    None => log!(
+                        "{} exited ({}, {}), not restarting ({:?})",
+                        &self.config.name,
+                        last_exit,
+                        class,
+                        self.config.restart
+                    ),
+                }
+                self.child = None;
+                self.finished = true;
+                self.update_status(|s| {
+                    s.state = ProcessState::Exited;
+                    s.pid = 0;
+                });
+                return false;
+            }
+            if class != ExitClass::Success {
+                log!(
+                    "{} exited ({}, {}), restarting in {}",
+                    &self.config.name,
+                    last_exit,
+                    class,
+                    HumanDuration(self.restart_delay())
+                );
+            }
+            self.exited_at = Some(Instant::now());
+            self.update_status(|s| {
+                s.state = ProcessState::Restarting;
+                s.pid = 0;
+            });
+        }
+
+        if self.exited_at.unwrap().elapsed() < self.restart_delay() {
+            return false;
+        }
+        self.exited_at = None;
+        self.update_status(|s| s.restarts += 1);
+        self.start();
+        true
+    }
+
+    /// Stops the process gracefully and starts it again right away.
+    pub fn restart(&mut self, reason: &str) {
+        self.replace_run(reason, false);
+    }
+
+    /// Restarts the process like `restart`, counted and journaled as `planned` or not.
+    fn replace_run(&mut self, reason: &str, planned: bool) {
+        log!("Restarting {}: {}", &self.config.name, reason);
+        let pid = self.pid();
+        self.stop_child();
+        self.child = None;
+        self.capture = None;
+        let event = match planned {
+            true => EventKind::PlannedRestart,
+            false => EventKind::Restarted,
+        };
+        journal::record(Event {
+            status: Some(reason.to_string()),
+            uptime: self.uptime(),
+            ..self.event(event, pid)
+        });
+        self.exited_at = None;
+        self.update_status(|s| match planned {
+            true => s.planned_restarts += 1,
+            false => s.restarts += 1,
+        });
+        self.start();
+    }
+
+    /// Works out the next restart per `restart_schedule`.
+    pub fn plan_restarts(&mut self) {
+        self.next_planned = self
+            .config
+            .restart_schedule
+            .as_ref()
+            .and_then(|cron| cron.next_after(Local::now()));
+    }
+
+    /// Restarts the process once it has reached its `max_lifetime`, or when it's due per
+    /// `restart_schedule`. One that isn't running then is left alone.
+    pub fn check_lifetime(&mut self) {
+        let due = self.next_planned.is_some_and(|next| next <= Local::now());
+        if due {
+            self.plan_restarts();
+        }
+        if self.paused || self.status.lock().unwrap().state != ProcessState::Running {
+            return;
+        }
+        if due {
+            let cron = self.config.restart_schedule.as_ref().unwrap();
+            let reason = format!("scheduled restart ({})", cron);
+            self.replace_run(&reason, true);
+        } else if self
+            .expires_at
+            .is_some_and(|expires| expires <= Instant::now())
+        {
+            let lifetime = self.config.max_lifetime.unwrap_or_default();
+            let reason = format!("max_lifetime of {} reached", lifetime);
+            self.replace_run(&reason, true);
+        }
+    }
+
+    /// Whether this is a job run on `cron` or `every`, rather than kept running.
+    fn is_scheduled(&self) -> bool {
+        self.config.job.as_ref().is_some_and(|job| !job.is_once())
+    }
+
+    /// Works out when a scheduled job runs next.
+    fn schedule(&mut self) {
+        let job = match &self.config.job {
+            Some(job) => job,
+            None => return,
+        };
+        let now = Local::now();
+        self.next_run = match (&job.cron, job.every) {
+            (Some(cron), _) => cron.next_after(now),
+            (None, Some(every)) => chrono::Duration::from_std(every.0).ok().map(|every| {
+                // Keeps the pace of the previous runs unless they fell behind
+                match self.next_run.map(|last| last + every) {
+                    Some(next) if next > now => next,
+                    _ => now + every,
+                }
+            }),
+            (None, None) => None,
+        };
+        let next_run = self.next_run.map(|next| next.with_timezone(&Utc));
+        let idle = self.child.is_none() && self.adopted.is_none();
+        self.update_status(|s| {
+            if idle {
+                s.state = ProcessState::Scheduled;
+            }
+            s.next_run = next_run;
+        });
+    }
+
+    /// Starts a scheduled job once it is due, per `overlap` if the previous run is still
+    /// going.
+    pub fn run_job(&mut self) {
+        if self.paused || self.finished || !self.is_scheduled() {
+            return;
+        }
+        let overlap = self
+            .config
+            .job
+            .as_ref()
+            .map(|job| job.overlap)
+            .unwrap_or_default();
+
+        if self.child.is_some() || self.adopted.is_some() {
+            match self.reap() {
+                Some(ExitClass::Fatal) => {
+                    log!(
+                        "{} exited with a fatal status, no more runs",
+                        &self.config.name
+                    );
+                    self.child = None;
+                    self.finished = true;
+                    self.queued = false;
+                    self.next_run = None;
+                    self.update_status(|s| {
+                        s.state = ProcessState::Fatal;
+                        s.pid = 0;
+                        s.next_run = None;
+                    });
+                    return;
+                }
+                Some(_) => {
+                    self.child = None;
+                    self.update_status(|s| {
+                        s.state = ProcessState::Scheduled;
+                        s.pid = 0;
+                    });
+                }
+                None => (),
+            }
+        }
+        let running = self.child.is_some() || self.adopted.is_some();
+        let due = self.next_run.is_some_and(|next| next <= Local::now());
+        if due {
+            self.schedule();
+        }
+
+        if due && running {
+            match overlap {
+                OverlapPolicy::Skip => {
+                    log!("{} is still running, skipping a run", &self.config.name);
+                    return;
+                }
+                OverlapPolicy::Queue => {
+                    if self.queued {
+                        log!(
+                            "{} already has a run queued, skipping one",
+                            &self.config.name
+                        );
+                    }
+                    self.queued = true;
+                    return;
+                }
+                OverlapPolicy::Kill => {
+                    log!(
+                        "{} is still running, stopping it for the next run",
+                        &self.config.name
+                    );
+                    self.stop_reason = Some("stopped for the next run".to_string());
+                    self.stop_child();
+                    self.reap();
+                    self.child = None;
+                }
+            }
+        } else if !due && (running || !self.queued) {
+            // Neither due nor a queued run that can start now
+            return;
+        }
+
+        self.queued = false;
+        self.start();
+        if self.child.is_none() {
+            self.update_status(|s| s.state = ProcessState::Scheduled);
+        }
+    }
+
+    /// Stops a job run that has taken longer than `job.timeout`, as a failure.
+    pub fn check_timeout(&mut self) {
+        let timeout = match self.config.job.as_ref().and_then(|job| job.timeout) {
+            Some(timeout) => timeout,
+            None => return,
+        };
+        if self.paused || self.stop_reason.is_some() {
+            return;
+        }
+        match (self.started_at, self.child.as_mut()) {
+            (Some(started), Some(child)) if started.elapsed() >= timeout.0 => {
+                if let Ok(Some(_)) = child.try_wait() {
+                    return;
+                }
+            }
+            _ => return,
+        }
+        log!(
+            "{} timed out after {}, stopping it",
+            &self.config.name,
+            timeout
+        );
+        self.stop_reason = Some(format!("timed out after {}", timeout));
+        self.stop_child();
+    }
+
+    /// Marks a started process running once its `ready` conditions hold, or stops it as a
+    /// failed start when they don't within `ready.timeout`.
+    pub fn check_ready(&mut self) {
+        let readiness = match &self.readiness {
+            Some(readiness) => readiness,
+            None => return,
+        };
+        if self.paused
+            || self.stop_reason.is_some()
+            || self.status.lock().unwrap().state != ProcessState::Starting
+        {
+            return;
+        }
+        // Exits are for `try_restart`
+        match self.child.as_mut().map(|child| child.try_wait()) {
+            Some(Ok(None)) => (),
+            _ => return,
+        }
+        let waiting = match readiness.waiting_for() {
+            Some(waiting) => waiting,
+            None => {
+                let elapsed = readiness.elapsed();
+                log!(
+                    "{} is ready after {}",
+                    &self.config.name,
+                    units::format_elapsed(elapsed)
+                );
+                self.update_status(|s| s.state = ProcessState::Running);
+                journal::record(Event {
+                    uptime: Some(HumanDuration(elapsed)),
+                    ..self.event(EventKind::Ready, self.pid())
+                });
+                self.post_start();
+                return;
+            }
+        };
+        let timeout = HumanDuration(readiness.timeout());
+        if readiness.elapsed() < timeout.0 {
+            return;
+        }
+        log!(
+            "{} isn't ready after {} ({}), stopping it",
+            &self.config.name,
+            timeout,
+            waiting
+        );
+        self.stop_reason = Some(format!("not ready after {}: {}", timeout, waiting));
+        self.stop_child();
+    }
+
+    /// Waits for the jobs run once that this process depends on, and for the dependencies
+    /// with `ready` conditions to be ready, handling commands meanwhile. `false` if the
+    /// supervisor is stopping instead.
+    fn wait_for_dependencies(
+        &mut self,
+        exit_flag: &AtomicBool,
+        receiver: &Receiver<ProcessCommand>,
+        poll_interval: Duration,
+    ) -> bool {
+        let mut logged = false;
+        loop {
+            let waiting: Vec<String> = self
+                .wait_for
+                .iter()
+                .filter(|dependency| !dependency.is_done())
+                .map(|dependency| dependency.status.lock().unwrap().name.clone())
+                .collect();
+            if waiting.is_empty() {
+                for dependency in &self.wait_for {
+                    let status = dependency.status.lock().unwrap();
+                    if status.state == ProcessState::Fatal {
+                        log!(
+                            "{} starts anyway, {} failed fatally ({})",
+                            &self.config.name,
+                            &status.name,
+                            status.last_exit.as_deref().unwrap_or("exited")
+                        );
+                    }
+                }
+                return true;
+            }
+            if !logged {
+                log!("{} waits for {}", &self.config.name, waiting.join(", "));
+                logged = true;
+            }
+            if exit_flag.load(Ordering::Relaxed) {
+                return false;
+            }
+            match receiver.recv_timeout(poll_interval) {
+                Ok(command) => self.handle(command),
+                Err(RecvTimeoutError::Timeout) => (),
+                Err(RecvTimeoutError::Disconnected) => thread::sleep(poll_interval),
+            }
+        }
+    }
+
+    /// Waits for a connection to start the process, per `lazy`.
+    pub fn listen(&mut self) {
+        self.child = None;
+        self.update_status(|s| {
+            s.state = ProcessState::Listening;
+            s.pid = 0;
+        });
+    }
+
+    /// Starts a `lazy` process once a connection is waiting for it.
+    pub fn check_connections(&mut self) {
+        if self.paused || self.status.lock().unwrap().state != ProcessState::Listening {
+            return;
+        }
+        if sockets::has_connection(&self.config) {
+            log!("Starting {} on a connection", &self.config.name);
+            self.start();
+        }
+    }
+
+    /// Starts watching the `watch` paths; a process that can't be watched runs regardless.
+    pub fn watch_files(&mut self) {
+        let watch = match &self.config.watch {
+            Some(watch) => watch,
+            None => return,
+        };
+        match FileWatcher::new(watch, Path::new(&self.config.cwd)) {
+            Ok(files) => self.files = Some(files),
+            Err(err) => log!("Can't watch files of {}: {}", &self.config.name, err),
+        }
+    }
+
+    /// Restarts the process once its watched files have changed and settled. Changes
+    /// while it isn't running are dropped, as it starts with them anyway.
+    pub fn check_files(&mut self) {
+        let reason = match self.files.as_mut().and_then(|files| files.poll()) {
+            Some(reason) => reason,
+            None => return,
+        };
+        if self.paused || self.status.lock().unwrap().state != ProcessState::Running {
+            return;
+        }
+        self.restart(&reason);
+    }
+
+    /// Restarts the process once its `watchdog` limits are exceeded.
+    pub fn check_resources(&mut self) {
+        if self.paused || self.status.lock().unwrap().state != ProcessState::Running {
+            return;
+        }
+        let pid = match self.pid() {
+            Some(pid) => pid,
+            None => return,
+        };
+        let reason = match self
+            .watchdog
+            .as_mut()
+            .and_then(|watchdog| watchdog.check(pid))
+        {
+            Some(reason) => reason,
+            None => return,
+        };
+        self.restart(&reason);
+    }
+
+    pub fn pause(&mut self) -> Result<String, String> {
+        if self.paused {
+            return Ok(format!("{} is already paused", self.config.name));
+        }
+
+        match self.config.pause_mode {
+            PauseMode::Ignore => return Ok(format!("{} ignores pause", self.config.name)),
+            #[cfg(unix)]
+            PauseMode::Suspend => {
+                if let Some(pid) = self.pid() {
+                    crate::signals::send(pid, libc::SIGSTOP)
+                        .map_err(|err| format!("Can't suspend {}: {}", self.config.name, err))?;
+                }
+            }
+            _ => self.stop_child(),
+        }
+
+        self.paused = true;
+        self.paused_from = Some(self.status.lock().unwrap().state);
+        self.update_status(|s| s.state = ProcessState::Paused);
+        journal::record(self.event(EventKind::Paused, self.pid()));
+        log!(
+            "Paused {} ({:?})",
+            &self.config.name,
+            self.config.pause_mode
+        );
+        Ok(format!("{} paused", self.config.name))
+    }
+
+    pub fn resume(&mut self) -> Result<String, String> {
+        if !self.paused {
+            return Ok(format!("{} is not paused", self.config.name));
+        }
+        self.paused = false;
+        let paused_from = self.paused_from.take();
+
+        #[cfg(unix)]
+        if self.config.pause_mode == PauseMode::Suspend {
+            if let Some(pid) = self.pid() {
+                crate::signals::send(pid, libc::SIGCONT)
+                    .map_err(|err| format!("Can't resume {}: {}", self.config.name, err))?;
+                // Still waiting for `ready` if it was starting
+                let state = match paused_from {
+                    Some(ProcessState::Starting) => ProcessState::Starting,
+                    _ => ProcessState::Running,
+                };
+                self.update_status(|s| s.state = state);
+                journal::record(self.event(EventKind::Resumed, Some(pid)));
+                log!("Resumed {}", &self.config.name);
+                return Ok(format!("{} resumed", self.config.name));
             }
-            Err(e) => {
-                dbg!(e);
-                self.start();
-                true
+        }
+
+        // Stopped while paused, or exited while suspended
+        if paused_from == Some(ProcessState::Listening) {
+            self.listen();
+        } else if self.is_scheduled() {
+            self.update_status(|s| s.state = ProcessState::Scheduled);
+        } else if !self.finished {
+            self.exited_at = None;
+            self.start();
+        }
+        journal::record(self.event(EventKind::Resumed, self.pid()));
+        log!("Resumed {}", &self.config.name);
+        Ok(format!("{} resumed", self.config.name))
+    }
+
+    /// `servicers restart`: also starts a process that has stopped or finished.
+    pub fn restart_on_request(&mut self) -> Result<String, String> {
+        if self.paused {
+            return Err(format!("{} is paused", self.config.name));
+        }
+        if self.status.lock().unwrap().state == ProcessState::Disabled {
+            return Err(format!("{} is disabled", self.config.name));
+        }
+        self.finished = false;
+        self.failures = 0;
+        if self.is_scheduled() && self.next_run.is_none() {
+            self.schedule();
+        }
+        self.restart("requested");
+        let status = self.status.lock().unwrap().clone();
+        match status.state {
+            ProcessState::Running | ProcessState::Starting => {
+                Ok(format!("{} restarted", self.config.name))
             }
-            Ok(None) => false,
+            _ => Err(format!(
+                "{} failed to start: {}",
+                self.config.name,
+                status.last_exit.unwrap_or_default()
+            )),
+        }
+    }
+
+    /// The pid of the current run, unless it has exited.
+    fn running_pid(&self) -> Option<u32> {
+        let state = self.status.lock().unwrap().state;
+        match state {
+            ProcessState::Starting | ProcessState::Running | ProcessState::Paused => self.pid(),
+            _ => None,
         }
     }
 
+    fn signal(&self, signal: &str) -> Result<u32, String> {
+        let number =
+            crate::signals::parse(signal).ok_or_else(|| format!("Unknown signal {:?}", signal))?;
+        let pid = self
+            .running_pid()
+            .ok_or_else(|| format!("{} isn't running", self.config.name))?;
+        crate::signals::send(pid, number)
+            .map_err(|err| format!("Can't signal {}: {}", self.config.name, err))?;
+        Ok(pid)
+    }
+
+    pub fn signal_on_request(&mut self, signal: &str) -> Result<String, String> {
+        let pid = self.signal(signal)?;
+        log!("Sent {} to {} ({})", signal, &self.config.name, pid);
+        journal::record(Event {
+            status: Some(signal.to_string()),
+            ..self.event(EventKind::Signaled, Some(pid))
+        });
+        Ok(format!("Sent {} to {}", signal, self.config.name))
+    }
+
+    /// Runs one of `actions`: sends its signal or runs its command to completion.
+    pub fn run_action(&mut self, name: &str) -> Result<String, String> {
+        let action = match self.config.actions.get(name) {
+            Some(action) => action.clone(),
+            None => return Err(format!("{} has no action {:?}", self.config.name, name)),
+        };
+        let pid = self.running_pid();
+        let result = match &action {
+            Action::Signal(signal) => self.signal(signal).map(|_| ()),
+            Action::Command(command) => self.run_command(command, pid, self.config.stop_timeout),
+        };
+        match &result {
+            Ok(()) => log!("Ran {} of {} ({})", name, &self.config.name, action),
+            Err(err) => log!(
+                "{} of {} ({}) failed: {}",
+                name,
+                &self.config.name,
+                action,
+                err
+            ),
+        }
+        journal::record(Event {
+            status: Some(format!("{} ({})", name, action)),
+            failed: result.is_err(),
+            ..self.event(EventKind::Signaled, pid)
+        });
+        match result {
+            Ok(()) => Ok(format!("Ran {} of {}", name, self.config.name)),
+            Err(err) => Err(format!("{} of {} failed: {}", name, self.config.name, err)),
+        }
+    }
+
+    /// Runs an action or hook command like the process itself.
+    fn run_command(
+        &self,
+        command: &[String],
+        pid: Option<u32>,
+        timeout: HumanDuration,
+    ) -> Result<(), String> {
+        let mut config = ProcessConfig {
+            program: command[0].clone(),
+            args: command[1..].to_vec(),
+            limits: None,
+            sockets: vec![],
+            ..self.config.clone()
+        };
+        if let Some(pid) = pid {
+            config
+                .env
+                .insert("SERVICERS_PID".to_string(), pid.to_string());
+        }
+        let mut child = config.spawn_new().map_err(|err| err.to_string())?;
+        let output = OutputTail::new(1);
+        let capture = Capture::start(&mut child, &output, None);
+
+        let deadline = Instant::now() + timeout.0;
+        let status = loop {
+            match child.try_wait() {
+                Ok(Some(status)) => break status,
+                Ok(None) if Instant::now() < deadline => thread::sleep(Duration::from_millis(20)),
+                Ok(None) => {
+                    child.kill().ok();
+                    child.wait().ok();
+                    return Err(format!("didn't finish within {}", timeout));
+                }
+                Err(err) => return Err(err.to_string()),
+            }
+        };
+        if status.success() {
+            return Ok(());
+        }
+        capture.finish(Duration::from_millis(200));
+        match output.lines().pop() {
+            Some(line) => Err(format!("{} ({})", status, line.text.trim())),
+            None => Err(status.to_string()),
+        }
+    }
+
+    pub fn handle(&mut self, command: ProcessCommand) {
+        let (result, reply) = match command {
+            ProcessCommand::Pause(reply) => (self.pause(), reply),
+            ProcessCommand::Resume(reply) => (self.resume(), reply),
+            ProcessCommand::Restart(reply) => (self.restart_on_request(), reply),
+            ProcessCommand::Signal(signal, reply) => (self.signal_on_request(&signal), reply),
+            ProcessCommand::Action(action, reply) => (self.run_action(&action), reply),
+        };
+        // The requester may have given up waiting
+        reply.send(result).ok();
+    }
+
     pub fn kill(&mut self) {
         if let Some(child) = self.child.as_mut() {
             match child.kill() {
-                Ok(e) => {
-                    dbg!(e);
-                }
-                Err(err) => {
-                    dbg!(err);
+                Ok(()) => verbose!("{} killed", &self.config.program),
+                Err(err) => verbose!("Can't kill {}: {:?}", &self.config.program, err),
+            }
+        }
+    }
+
+    /// Sends `stop_signal` and gives the process `stop_timeout` to exit before killing it.
+    pub fn stop(&mut self) {
+        let state = self.status.lock().unwrap().state;
+        let pid = self.pid();
+        self.stop_child();
+        if matches!(state, ProcessState::Running | ProcessState::Paused) {
+            journal::record(Event {
+                uptime: self.uptime(),
+                ..self.event(EventKind::Stopped, pid)
+            });
+        }
+        self.child = None;
+        self.capture = None;
+        self.update_status(|s| {
+            if s.state != ProcessState::Disabled {
+                s.state = ProcessState::Stopped;
+            }
+            s.pid = 0;
+        });
+    }
+
+    /// Stops the current run with the `pre_stop` and `post_stop` hooks around it.
+    fn stop_child(&mut self) {
+        let alive = match self.child.as_mut() {
+            Some(child) => matches!(child.try_wait(), Ok(None)),
+            None => self.adopted.as_ref().is_some_and(Survivor::is_alive),
+        };
+        if alive {
+            if let Err(err) = self.run_hooks("pre_stop", &self.config.pre_stop, self.pid()) {
+                log!("{}: {}", &self.config.name, err);
+            }
+        }
+        self.terminate();
+        self.post_stop();
+    }
+
+    fn terminate(&mut self) {
+        if let Some(survivor) = self.adopted.take() {
+            #[cfg(unix)]
+            if self.paused && self.config.pause_mode == PauseMode::Suspend {
+                crate::signals::send(survivor.pid, libc::SIGCONT).ok();
+            }
+            let signal = crate::signals::parse(&self.config.stop_signal).unwrap_or(15);
+            survivor.terminate(signal, self.config.stop_timeout.0);
+            return;
+        }
+
+        let child = match self.child.as_mut() {
+            Some(child) => child,
+            None => return,
+        };
+        if let Ok(Some(_)) = child.try_wait() {
+            return;
+        }
+
+        // A suspended process can't act on the stop signal
+        #[cfg(unix)]
+        if self.paused && self.config.pause_mode == PauseMode::Suspend {
+            crate::signals::send(child.id(), libc::SIGCONT).ok();
+        }
+
+        #[cfg(unix)]
+        if let Some(signal) = crate::signals::parse(&self.config.stop_signal) {
+            match crate::signals::send(child.id(), signal) {
+                Ok(()) => {
+                    let deadline = Instant::now() + self.config.stop_timeout.0;
+                    while Instant::now() < deadline {
+                        if let Ok(Some(status)) = child.try_wait() {
+                            verbose!("{} stopped: {}", &self.config.name, status);
+                            return;
+                        }
+                        thread::sleep(Duration::from_millis(50));
+                    }
+                    log!(
+                        "{} didn't stop within {}, killing",
+                        &self.config.name,
+                        self.config.stop_timeout
+                    );
                 }
+                Err(err) => log!("Can't signal {}: {:?}", &self.config.name, err),
             }
         }
+
+        self.kill();
+        if let Some(child) = self.child.as_mut() {
+            child.wait().ok();
+        }
     }
 }
 
-pub fn run_processes(list: Vec<ChildProcess>, exit_flag: &Arc<AtomicBool>) -> Vec<JoinHandle<()>> {
-    let mut threads = Vec::<JoinHandle<()>>::new();
+pub fn run_processes(
+    list: Vec<ChildProcess>,
+    exit_flag: &Arc<AtomicBool>,
+    poll_interval: Duration,
+) -> Vec<ProcessHandle> {
+    // Jobs run once are waited for to finish, processes with `ready` to be ready
+    let awaited: Vec<(String, Arc<Mutex<ProcessStatus>>, bool)> = list
+        .iter()
+        .filter_map(|proc| {
+            let once = proc.config.job.as_ref().is_some_and(JobConfig::is_once);
+            let awaited = once || proc.config.ready.is_some();
+            awaited.then(|| (proc.config.name.clone(), proc.status.clone(), once))
+        })
+        .collect();
+
+    let mut handles = Vec::<ProcessHandle>::new();
     for mut proc in list {
+        proc.wait_for = awaited
+            .iter()
+            .filter(|(name, _, _)| {
+                proc.config
+                    .depends_on
+                    .iter()
+                    .any(|dependency| crate::control_socket::matches(dependency, name))
+            })
+            .map(|(_, status, once)| Dependency {
+                status: status.clone(),
+                once: *once,
+            })
+            .collect();
         // Для каждого копирую ссылку
         let exit_flag = exit_flag.clone();
+        let status = proc.status.clone();
+        let (commands, receiver): (Sender<ProcessCommand>, Receiver<ProcessCommand>) =
+            mpsc::channel();
 
-        threads.push(thread::spawn(move || {
+        let thread = thread::spawn(move || {
             if !proc.config.is_valid() {
                 log!("Invalid config: {:?}", &proc.config);
+                proc.update_status(|s| s.state = ProcessState::Disabled);
                 return;
             }
 
             log!("Starting: {:?}", &proc.config);
 
-            /*if proc.config.program.contains("nginx") {
-                Command::new(&NGINX_PATH)
-                    .args(&NGINX_STOP_ARGS)
-                    .current_dir(&NGINX_CWD)
-                    .spawn()
-                    .ok();
-            }*/
-            proc.start();
+            proc.watch_files();
+            proc.plan_restarts();
+            if !proc.wait_for_dependencies(&exit_flag, &receiver, poll_interval) {
+                proc.stop();
+                return;
+            }
+            if proc.is_scheduled() {
+                proc.schedule();
+            } else if proc.config.lazy && proc.adopted.is_none() {
+                proc.listen();
+            } else if proc.adopted.is_none() && !proc.paused {
+                proc.start();
+            }
 
             loop {
-                if exit_flag.load(Ordering::Relaxed) == true {
-                    log!("Killing: {:?}", &proc.config);
-                    proc.kill();
+                if exit_flag.load(Ordering::Relaxed) {
+                    log!("Stopping: {:?}", &proc.config);
+                    proc.stop();
                     break;
                 }
 
-                if proc.config.is_valid() {
-                    if proc.try_restart() {
-                        log!("Restarting: {:?}", &proc.config);
-                    }
+                if proc.is_scheduled() {
+                    proc.run_job();
+                } else if proc.config.is_valid() && proc.try_restart() {
+                    log!("Restarting: {:?}", &proc.config);
                 }
+                proc.check_timeout();
+                proc.check_ready();
+                proc.check_resources();
+                proc.check_files();
+                proc.check_lifetime();
+                proc.check_connections();
 
-                thread::sleep(Duration::from_millis(100));
+                match receiver.recv_timeout(poll_interval) {
+                    Ok(command) => proc.handle(command),
+                    Err(RecvTimeoutError::Timeout) => (),
+                    // Nobody left to send commands
+                    Err(RecvTimeoutError::Disconnected) => thread::sleep(poll_interval),
+                }
             }
-        }));
+        });
+        handles.push(ProcessHandle {
+            status,
+            commands,
+            thread,
+        });
     }
 
-    threads
+    handles
+}
+
+/// Up to a tenth less than `lifetime`, at random.
+fn jittered(lifetime: Duration) -> Duration {
+    use std::collections::hash_map::RandomState;
+    use std::hash::{BuildHasher, Hasher};
+
+    // Randomly keyed, so an empty hash is random enough to spread copies out
+    let random = RandomState::new().build_hasher().finish();
+    lifetime.saturating_sub(lifetime.mul_f64((random % 1000) as f64 / 10_000.0))
+}
+
+/// None of the processes is still starting: all spawned and, per `ready`, ready.
+pub fn all_started(handles: &[ProcessHandle]) -> bool {
+    handles
+        .iter()
+        .all(|handle| handle.status.lock().unwrap().state != ProcessState::Starting)
 }
 
+/// `2 running, 1 failed` for status lines.
+pub fn summarize(handles: &[ProcessHandle]) -> String {
+    let statuses: Vec<ProcessStatus> = handles
+        .iter()
+        .map(|handle| handle.status.lock().unwrap().clone())
+        .collect();
+    let running = statuses
+        .iter()
+        .filter(|s| s.state == ProcessState::Running)
+        .count();
+    let failed = statuses
+        .iter()
+        .filter(|s| {
+            s.failed
+                && matches!(
+                    s.state,
+                    ProcessState::Restarting
+                        | ProcessState::Exited
+                        | ProcessState::Fatal
+                        | ProcessState::Scheduled
+                )
+        })
+        .count();
+    format!("{} running, {} failed", running, failed)
+}
+
+#[cfg(windows)]
 #[test]
 fn test_run() {
     use std::process::Command;
 
-    let mut list = Vec::<ChildProcess>::new();
-    for cfg in super::proc_config::load() {
-        list.push(ChildProcess::from_config(cfg));
-    }
+    let config = super::proc_config::load().unwrap();
+    let list = ChildProcess::from_configs(config.processes);
 
     let need_exit = Arc::new(AtomicBool::new(false));
-    let threads = run_processes(list, &need_exit);
+    let handles = run_processes(list, &need_exit, config.service.poll_interval.0);
 
     thread::sleep(Duration::from_secs(5));
     need_exit.store(true, Ordering::Relaxed);
@@ -179,5 +1468,328 @@ fn test_run() {
         .unwrap();
 
     thread::sleep(Duration::from_secs(10));
-    while !threads.iter().all(|t| t.is_finished()) {}
+    while !handles.iter().all(|h| h.thread.is_finished()) {}
+}
+
+/// `sh -c script`, run from `/`.
+#[cfg(all(test, unix))]
+fn shell(name: &str, script: &str) -> ProcessConfig {
+    ProcessConfig {
+        name: name.to_string(),
+        program: "sh".to_string(),
+        args: vec!["-c".to_string(), script.to_string()],
+        cwd: "/".to_string(),
+        ..Default::default()
+    }
+}
+
+/// `sleep 30`, run from `/`.
+#[cfg(all(test, unix))]
+fn sleeper(name: &str) -> ProcessConfig {
+    ProcessConfig {
+        program: "sleep".to_string(),
+        args: vec!["30".to_string()],
+        ..shell(name, "")
+    }
+}
+
+/// Supervises `configs` as `run` does, polling every 20ms.
+#[cfg(all(test, unix))]
+fn spawn_all(configs: Vec<ProcessConfig>) -> (Vec<ProcessHandle>, Arc<AtomicBool>) {
+    let need_exit = Arc::new(AtomicBool::new(false));
+    let handles = run_processes(
+        ChildProcess::from_configs(configs),
+        &need_exit,
+        Duration::from_millis(20),
+    );
+    (handles, need_exit)
+}
+
+#[cfg(all(test, unix))]
+fn status_of(handles: &[ProcessHandle], name: &str) -> ProcessStatus {
+    let handle = handles
+        .iter()
+        .find(|h| h.status.lock().unwrap().name == name)
+        .unwrap();
+    handle.status.lock().unwrap().clone()
+}
+
+/// Stops every process and waits for their threads.
+#[cfg(all(test, unix))]
+fn stop_all(handles: Vec<ProcessHandle>, need_exit: &AtomicBool) {
+    need_exit.store(true, Ordering::Relaxed);
+    for handle in handles {
+        handle.thread.join().unwrap();
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn test_jobs() {
+    let dir = tempfile::tempdir().unwrap();
+    let marker = dir.path().join("setup-done");
+    let setup = ProcessConfig {
+        job: Some(JobConfig::default()),
+        ..shell("setup", &format!("sleep 0.3; touch {}", marker.display()))
+    };
+    // Ends fatally, which shouldn't hold up `app` forever
+    let migrate = ProcessConfig {
+        job: Some(JobConfig::default()),
+        fatal_exit_codes: vec![ExitMatch::Code(3)],
+        ..shell("migrate", "exit 3")
+    };
+    // Fails unless started after `setup`
+    let mut app = shell("app", &format!("test -f {} && sleep 30", marker.display()));
+    app.depends_on = vec!["setup".to_string(), "migrate".to_string()];
+    let tick = ProcessConfig {
+        job: Some(JobConfig {
+            every: Some(HumanDuration(Duration::from_millis(200))),
+            timeout: Some(HumanDuration(Duration::from_millis(100))),
+            ..Default::default()
+        }),
+        ..shell("tick", "sleep 5")
+    };
+
+    let (handles, need_exit) = spawn_all(vec![app, setup, migrate, tick]);
+    let status = |name: &str| status_of(&handles, name);
+
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while (status("app").state != ProcessState::Running || status("tick").last_exit.is_none())
+        && Instant::now() < deadline
+    {
+        thread::sleep(Duration::from_millis(20));
+    }
+    let setup = status("setup");
+    assert_eq!((setup.state, setup.failed), (ProcessState::Exited, false));
+    assert!(setup.last_run.is_some());
+    assert_eq!(status("migrate").state, ProcessState::Fatal);
+    let app = status("app");
+    assert_eq!((app.state, app.restarts), (ProcessState::Running, 0));
+    let tick = status("tick");
+    assert_eq!(tick.last_exit.as_deref(), Some("timed out after 100ms"));
+    assert!(tick.failed && tick.next_run.is_some());
+
+    stop_all(handles, &need_exit);
+}
+
+#[cfg(unix)]
+#[test]
+fn test_ready() {
+    let dir = tempfile::tempdir().unwrap();
+    let marker = dir.path().join("db-ready");
+    let ready = |stdout: &str, timeout: u64| ReadyConfig {
+        port: None,
+        stdout: Some(stdout.to_string()),
+        file: None,
+        min_uptime: None,
+        timeout: HumanDuration(Duration::from_millis(timeout)),
+    };
+    let script = format!(
+        "sleep 0.3; touch {}; echo accepting connections; exec sleep 30",
+        marker.display()
+    );
+    let db = ProcessConfig {
+        ready: Some(ready("^accepting", 5000)),
+        ..shell("db", &script)
+    };
+    // Fails unless started once `db` is ready
+    let mut app = shell("app", &format!("test -f {} && sleep 30", marker.display()));
+    app.depends_on = vec!["db".to_string()];
+    let stuck = ProcessConfig {
+        ready: Some(ready("^never$", 200)),
+        restart: RestartPolicy::Never,
+        ..sleeper("stuck")
+    };
+
+    let (handles, need_exit) = spawn_all(vec![app, db, stuck]);
+    let status = |name: &str| status_of(&handles, name);
+
+    thread::sleep(Duration::from_millis(100));
+    assert_eq!(status("db").state, ProcessState::Starting);
+    assert!(status("db").pid != 0);
+    assert!(!all_started(&handles));
+
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while !all_started(&handles) && Instant::now() < deadline {
+        thread::sleep(Duration::from_millis(20));
+    }
+    assert_eq!(status("db").state, ProcessState::Running);
+    let app = status("app");
+    assert_eq!((app.state, app.restarts), (ProcessState::Running, 0));
+    let stuck = status("stuck");
+    assert_eq!((stuck.state, stuck.failed), (ProcessState::Exited, true));
+    assert_eq!(
+        stuck.last_exit.as_deref(),
+        Some("not ready after 200ms: no line of stdout matches \"^never$\"")
+    );
+
+    stop_all(handles, &need_exit);
+}
+
+#[cfg(unix)]
+#[test]
+fn test_exit_codes() {
+    let shell = |name: &str, script: &str| ProcessConfig {
+        restart: RestartPolicy::OnFailure,
+        ..shell(name, script)
+    };
+    let mut done = shell("done", "exit 3");
+    done.success_exit_codes = vec![ExitMatch::Code(3)];
+    let mut killed = shell("killed", "kill -TERM $$");
+    killed.success_exit_codes = vec![ExitMatch::Signal("SIGTERM".to_string())];
+    let mut config = shell("config", "exit 78");
+    config.restart = RestartPolicy::Always;
+    config.fatal_exit_codes = vec![ExitMatch::Code(78)];
+    let mut busy = shell("busy", "exit 75");
+    busy.restart = RestartPolicy::Never;
+    busy.restart_on_exit_codes = vec![ExitMatch::Code(75)];
+    busy.restart_delay = HumanDuration::from_millis(100);
+    busy.restart_backoff = Some(HumanDuration::from_millis(400));
+
+    let mut backoff = ChildProcess::from_config(busy.clone());
+    let delays: Vec<u128> = (0..5)
+        .map(|failures| {
+            backoff.failures = failures;
+            backoff.restart_delay().as_millis()
+        })
+        .collect();
+    assert_eq!(delays, [100, 100, 200, 400, 400]);
+
+    let (handles, need_exit) = spawn_all(vec![done, killed, config, busy]);
+    let status = |name: &str| status_of(&handles, name);
+    thread::sleep(Duration::from_millis(1000));
+
+    for name in ["done", "killed"] {
+        let done = status(name);
+        assert_eq!(
+            (done.state, done.failed),
+            (ProcessState::Exited, false),
+            "{}",
+            name
+        );
+        assert_eq!(done.exit_class, Some(ExitClass::Success));
+    }
+    let config = status("config");
+    assert_eq!((config.state, config.restarts), (ProcessState::Fatal, 0));
+    assert_eq!(
+        (config.failed, config.exit_class),
+        (true, Some(ExitClass::Fatal))
+    );
+    // Restarted after 100, 100, 200 and 400ms
+    let busy = status("busy");
+    assert!((2..=4).contains(&busy.restarts), "{:?}", busy);
+    assert_eq!(
+        (busy.failed, busy.exit_class),
+        (true, Some(ExitClass::Temporary))
+    );
+
+    stop_all(handles, &need_exit);
+}
+
+#[cfg(unix)]
+#[test]
+fn test_hooks() {
+    let dir = tempfile::tempdir().unwrap();
+    let hook = |script: &str| vec!["sh".to_string(), "-c".to_string(), script.to_string()];
+    let sleeper = |name: &str| ProcessConfig {
+        cwd: dir.path().to_string_lossy().into_owned(),
+        restart: RestartPolicy::Never,
+        ..sleeper(name)
+    };
+    let mut app = sleeper("app");
+    app.pre_start = vec![hook("echo pre_start >> hooks"), hook("echo mkdir >> hooks")];
+    app.post_start = vec![hook("echo post_start $SERVICERS_PID >> hooks")];
+    app.pre_stop = vec![hook("echo pre_stop $SERVICERS_PID >> hooks")];
+    app.post_stop = vec![hook("echo post_stop >> hooks")];
+    let mut blocked = sleeper("blocked");
+    blocked.pre_start = vec![hook("exit 1")];
+    let mut ignored = sleeper("ignored");
+    ignored.pre_start = blocked.pre_start.clone();
+    ignored.pre_start_failure = HookFailure::Ignore;
+
+    let (handles, need_exit) = spawn_all(vec![app, blocked, ignored]);
+    let status = |name: &str| status_of(&handles, name);
+    thread::sleep(Duration::from_millis(300));
+
+    let pid = status("app").pid;
+    assert_eq!(status("app").state, ProcessState::Running);
+    let blocked = status("blocked");
+    assert_eq!((blocked.state, blocked.pid), (ProcessState::Exited, 0));
+    assert_eq!(
+        blocked.last_exit.as_deref(),
+        Some("pre_start `sh -c exit 1` failed: exit status: 1")
+    );
+    assert_eq!(status("ignored").state, ProcessState::Running);
+
+    stop_all(handles, &need_exit);
+    assert_eq!(
+        std::fs::read_to_string(dir.path().join("hooks")).unwrap(),
+        format!(
+            "pre_start\nmkdir\npost_start {pid}\npre_stop {pid}\npost_stop\n",
+            pid = pid
+        )
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn test_planned_restarts() {
+    for _ in 0..100 {
+        let lifetime = jittered(Duration::from_secs(100));
+        assert!(lifetime > Duration::from_secs(90) && lifetime <= Duration::from_secs(100));
+    }
+
+    let mut config = ProcessConfig {
+        restart_schedule: Some("0 3 * * *".parse().unwrap()),
+        ..sleeper("leaky")
+    };
+    let mut leaky = ChildProcess::from_config(config.clone());
+    leaky.plan_restarts();
+    assert!(leaky.next_planned.is_some_and(|next| next > Local::now()));
+    leaky.start();
+    let pid = leaky.pid();
+    leaky.check_lifetime();
+    assert_eq!(leaky.pid(), pid);
+
+    // Due
+    leaky.next_planned = Some(Local::now() - chrono::Duration::seconds(1));
+    leaky.check_lifetime();
+    assert!(leaky.pid().is_some() && leaky.pid() != pid);
+    assert!(leaky.next_planned.is_some_and(|next| next > Local::now()));
+    let status = leaky.status.lock().unwrap().clone();
+    assert_eq!((status.restarts, status.planned_restarts), (0, 1));
+    leaky.stop();
+
+    config.restart_schedule = None;
+    config.max_lifetime = Some(HumanDuration::from_millis(300));
+    let (handles, need_exit) = spawn_all(vec![config]);
+    thread::sleep(Duration::from_millis(1000));
+    let status = status_of(&handles, "leaky");
+    assert_eq!(status.state, ProcessState::Running);
+    // After 270 to 300ms each, plus the time to stop
+    assert_eq!(status.restarts, 0);
+    assert!((2..=3).contains(&status.planned_restarts), "{:?}", status);
+
+    stop_all(handles, &need_exit);
+}
+
+#[cfg(unix)]
+#[test]
+fn test_resume() {
+    let lazy = |pause_mode| ProcessConfig {
+        lazy: true,
+        pause_mode,
+        ..sleeper("app")
+    };
+    for mut proc in
+        ChildProcess::from_configs(vec![lazy(PauseMode::Suspend), lazy(PauseMode::Stop)])
+    {
+        // Still waiting for a connection, not started by the resume
+        proc.listen();
+        proc.pause().unwrap();
+        assert_eq!(proc.status.lock().unwrap().state, ProcessState::Paused);
+        proc.resume().unwrap();
+        assert_eq!(proc.status.lock().unwrap().state, ProcessState::Listening);
+        assert!(proc.child.is_none());
+    }
 }
@@ -15,10 +15,12 @@ pub struct ChildServiceControl {
     _request_access: ServiceManagerAccess,
     _service_access: ServiceAccess,
     service: Service,
+    wait: Duration,
 }
 
 impl ChildServiceControl {
-    pub fn new(name: &str) -> windows_service::Result<ChildServiceControl> {
+    /// `wait` is how long start/stop/pause/resume give the service to change state.
+    pub fn new(name: &str, wait: Duration) -> windows_service::Result<ChildServiceControl> {
         let service_manager =
             ServiceManager::local_computer(None::<&str>, ServiceManagerAccess::CONNECT).unwrap();
         let service_access = ServiceAccess::QUERY_STATUS
@@ -32,6 +34,7 @@ impl ChildServiceControl {
             _request_access: ServiceManagerAccess::CONNECT,
             _service_access: service_access,
             service: service,
+            wait,
         })
     }
 
@@ -39,7 +42,7 @@ impl ChildServiceControl {
         let service_status = self.service.query_status()?;
         if service_status.current_state != ServiceState::Running {
             self.service.start(&Vec::<OsString>::new())?;
-            thread::sleep(Duration::from_secs(1));
+            thread::sleep(self.wait);
         }
 
         Ok(())
@@ -49,7 +52,7 @@ impl ChildServiceControl {
         let service_status = self.service.query_status()?;
         if service_status.current_state != ServiceState::Stopped {
             self.service.stop()?;
-            thread::sleep(Duration::from_secs(1));
+            thread::sleep(self.wait);
         }
 
         Ok(())
@@ -59,7 +62,7 @@ impl ChildServiceControl {
         let service_status = self.service.query_status()?;
         if service_status.current_state != ServiceState::Paused {
             self.service.pause()?;
-            thread::sleep(Duration::from_secs(1));
+            thread::sleep(self.wait);
         }
 
         Ok(())
@@ -69,7 +72,7 @@ impl ChildServiceControl {
         let service_status = self.service.query_status()?;
         if service_status.current_state != ServiceState::Running {
             self.service.resume()?;
-            thread::sleep(Duration::from_secs(1));
+            thread::sleep(self.wait);
         }
 
         Ok(())
@@ -80,16 +83,16 @@ impl ChildServiceControl {
     }
 }
 
-fn get_services() -> Vec<ChildServiceControl>{
+fn get_services(wait: Duration) -> Vec<ChildServiceControl>{
     
     let mut child_services = vec![];
 
-    let apache = ChildServiceControl::new(APACHE_SERVICE_NAME);
+    let apache = ChildServiceControl::new(APACHE_SERVICE_NAME, wait);
     if apache.is_ok() {
         child_services.push(apache.unwrap());
     }
 
-    let mysql = ChildServiceControl::new(MYSQL_SERVICE_NAME);
+    let mysql = ChildServiceControl::new(MYSQL_SERVICE_NAME, wait);
     if mysql.is_ok() {
         child_services.push(mysql.unwrap());
     }
@@ -99,8 +102,10 @@ fn get_services() -> Vec<ChildServiceControl>{
 
 pub fn run_services(
     exit_flag: &Arc<AtomicBool>,
+    poll_interval: Duration,
+    status_wait: Duration,
 ) -> Vec<JoinHandle<()>> {
-    let list = get_services();
+    let list = get_services(status_wait);
     let mut threads = Vec::<JoinHandle<()>>::new();
 
     for mut serv in list {
@@ -139,7 +144,7 @@ pub fn run_services(
                     Err(err) => log!("Can't get status for service {:?}: {}", &serv.name, err),
                 };
 
-                thread::sleep(Duration::from_millis(100));
+                thread::sleep(poll_interval);
             }
         }));
     }
@@ -0,0 +1,265 @@
+use std::path::PathBuf;
+
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use clap_complete::Shell;
+
+use chrono::{DateTime, Utc};
+
+use crate::config_format::Format;
+use crate::import::ImportFormat;
+use crate::journal;
+use crate::units::HumanDuration;
+
+#[derive(Debug, Parser)]
+#[command(
+    name = "servicers",
+    version,
+    about = "Keeps Appro processes and services running"
+)]
+pub struct Cli {
+    /// Process configuration file [default: servicers.json next to the executable]
+    #[arg(long, global = true, value_name = "PATH", env = "SERVICERS_CONFIG")]
+    pub config: Option<PathBuf>,
+
+    /// Directory for servicers.log [default: next to the executable]
+    #[arg(long, global = true, value_name = "DIR")]
+    pub log_dir: Option<PathBuf>,
+
+    /// Log process lifecycle details
+    #[arg(short, long, global = true)]
+    pub verbose: bool,
+
+    #[command(subcommand)]
+    pub command: Command,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Register servicers as a system service
+    Install {
+        #[command(flatten)]
+        service: ServiceOptions,
+
+        #[command(flatten)]
+        install: InstallOptions,
+    },
+    /// Stop and remove the system service
+    Uninstall {
+        #[command(flatten)]
+        service: ServiceOptions,
+    },
+    /// Start the system service
+    Start {
+        #[command(flatten)]
+        service: ServiceOptions,
+    },
+    /// Stop the system service
+    Stop {
+        #[command(flatten)]
+        service: ServiceOptions,
+    },
+    /// Pause processes per their pause_mode, or the whole system service
+    Pause {
+        /// Process or group to pause [default: all of them]
+        name: Option<String>,
+    },
+    /// Resume paused processes, or the whole system service
+    Resume {
+        /// Process or group to resume [default: all of them]
+        name: Option<String>,
+    },
+    /// Restart processes through the running supervisor
+    Restart {
+        /// Process or group to restart [default: all of them]
+        name: Option<String>,
+
+        /// One at a time, each once the previous one is up; stops at the first that isn't
+        #[arg(long)]
+        rolling: bool,
+
+        /// How long a restarted process has to keep running to count as up
+        #[arg(
+            long,
+            value_name = "DURATION",
+            default_value = "2s",
+            requires = "rolling"
+        )]
+        settle: HumanDuration,
+    },
+    /// Send a signal to processes through the running supervisor (Unix only)
+    Signal {
+        /// Process or group
+        name: String,
+
+        /// e.g. HUP, SIGUSR1 or 10
+        #[arg(value_parser = parse_signal)]
+        signal: String,
+    },
+    /// Run one of the `actions` configured for processes, e.g. `reload`
+    Action {
+        /// Process or group
+        name: String,
+
+        action: String,
+    },
+    /// Show process states, or the system service status if the supervisor isn't reachable
+    Status {
+        #[command(flatten)]
+        service: ServiceOptions,
+    },
+    /// Show process lifecycle events recorded in servicers.journal
+    History {
+        /// Process or group [default: all of them]
+        name: Option<String>,
+
+        /// Only events after this: an age like 7d, a date, or an RFC 3339 time
+        #[arg(long, value_parser = journal::parse_since)]
+        since: Option<DateTime<Utc>>,
+    },
+    /// Run the configured processes, one supervisor per config
+    Run {
+        /// Detach and log to servicers.log instead of the terminal (Unix only)
+        #[arg(long)]
+        daemon: bool,
+
+        /// Lock file holding the supervisor's pid [default: the config path with .pid] (Unix only)
+        #[arg(long, value_name = "PATH")]
+        pidfile: Option<PathBuf>,
+    },
+    /// Entry point used by the Windows service control manager
+    #[command(hide = true)]
+    Runservice,
+    /// Inspect the configuration
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommand,
+    },
+    /// Convert supervisord, Procfile or pm2 definitions into a servicers config
+    Import {
+        #[arg(value_enum)]
+        format: ImportFormat,
+
+        file: PathBuf,
+
+        /// Format of the generated config
+        #[arg(long, value_enum, default_value_t = Format::Json)]
+        to: Format,
+
+        /// Write the config here instead of stdout
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Render the configured processes for another process manager
+    Export {
+        #[command(subcommand)]
+        command: ExportCommand,
+    },
+    /// Crash reports of processes that exited abnormally
+    Crashes {
+        #[command(subcommand)]
+        command: CrashCommand,
+    },
+    /// Print a shell completion script to stdout
+    Completions {
+        #[arg(value_enum)]
+        shell: Shell,
+    },
+}
+
+/// Which service manager to talk to. Windows always uses the service control manager.
+#[derive(Debug, Args)]
+pub struct ServiceOptions {
+    /// Init system on Linux [default: detected]
+    #[arg(long, value_enum)]
+    pub init: Option<InitSystem>,
+
+    /// Directory standing in for `/` when writing service files on Linux
+    #[arg(long, value_name = "DIR", default_value = "/")]
+    pub root: PathBuf,
+}
+
+#[derive(Debug, Args)]
+pub struct InstallOptions {
+    /// Account to run the supervisor as on Linux [default: root]
+    #[arg(long)]
+    pub user: Option<String>,
+
+    /// When the init system restarts the supervisor on Linux
+    #[arg(long, value_enum, default_value_t = Restart::OnFailure)]
+    pub restart: Restart,
+
+    /// Working directory of the supervisor on Linux
+    #[arg(long, value_name = "DIR")]
+    pub workdir: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum InitSystem {
+    Systemd,
+    Openrc,
+    Sysvinit,
+}
+
+/// `Restart=` of the generated systemd unit; OpenRC and sysvinit only tell `no` apart.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Restart {
+    Always,
+    OnFailure,
+    No,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum ConfigCommand {
+    /// Print the effective config after merging fragments and environment overrides
+    Show {
+        #[arg(long, value_enum, default_value_t = Format::Json)]
+        format: Format,
+    },
+    /// Rewrite a config file in another format
+    Convert {
+        /// File to convert [default: the current config file]
+        file: Option<PathBuf>,
+
+        #[arg(long, value_enum)]
+        to: Format,
+
+        /// Output file [default: FILE with the new extension]
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum ExportCommand {
+    /// One systemd `.service` unit per process plus a target grouping them
+    Systemd {
+        /// Write unit files here instead of printing them
+        #[arg(long)]
+        dir: Option<PathBuf>,
+
+        /// Prefix of unit names
+        #[arg(long, default_value = "servicers-")]
+        prefix: String,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum CrashCommand {
+    /// List crash reports, oldest first
+    List {
+        /// Process or group [default: all of them]
+        name: Option<String>,
+    },
+    /// Print a crash report
+    Show {
+        /// Report file name, or a process to show its latest report
+        report: String,
+    },
+}
+
+fn parse_signal(signal: &str) -> Result<String, String> {
+    match crate::signals::parse(signal) {
+        Some(_) => Ok(signal.to_string()),
+        None => Err(format!("unknown signal {:?}", signal)),
+    }
+}
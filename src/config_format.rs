@@ -0,0 +1,89 @@
+use std::fmt::{self, Display};
+use std::path::Path;
+
+use serde::{de::DeserializeOwned, Serialize};
+
+/// Config file syntax, picked by file extension.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Format {
+    Json,
+    Toml,
+    Yaml,
+}
+
+/// What a config file contains at the top level.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Shape {
+    /// A bare list of processes, the original `servicers.json` layout.
+    List,
+    /// A table with a `processes` list.
+    Document,
+    /// A single process, used by fragments.
+    Process,
+}
+
+impl Format {
+    pub fn from_path(path: &Path) -> Option<Format> {
+        match path.extension()?.to_str()?.to_ascii_lowercase().as_str() {
+            "json" => Some(Format::Json),
+            "toml" => Some(Format::Toml),
+            "yaml" | "yml" => Some(Format::Yaml),
+            _ => None,
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            Format::Json => "json",
+            Format::Toml => "toml",
+            Format::Yaml => "yaml",
+        }
+    }
+
+    /// Errors keep the parser's own location report (`line 3 column 7`, TOML snippets).
+    pub fn parse<T: DeserializeOwned>(self, text: &str) -> Result<T, String> {
+        match self {
+            Format::Json => serde_json::from_str(text).map_err(|err| err.to_string()),
+            Format::Toml => toml::from_str(text).map_err(|err| err.to_string()),
+            Format::Yaml => serde_yaml::from_str(text).map_err(|err| err.to_string()),
+        }
+    }
+
+    pub fn serialize<T: Serialize>(self, value: &T) -> Result<String, String> {
+        match self {
+            Format::Json => serde_json::to_string_pretty(value).map_err(|err| err.to_string()),
+            Format::Toml => toml::to_string_pretty(value).map_err(|err| err.to_string()),
+            Format::Yaml => serde_yaml::to_string(value).map_err(|err| err.to_string()),
+        }
+    }
+
+    /// Looks at the top level only, so that the typed parse afterwards reports errors
+    /// against the original text.
+    pub fn shape(self, text: &str) -> Result<Shape, String> {
+        let value: serde_json::Value = match self {
+            Format::Json => serde_json::from_str(text).map_err(|err| err.to_string())?,
+            Format::Toml => {
+                let table: toml::Table = toml::from_str(text).map_err(|err| err.to_string())?;
+                serde_json::to_value(table).map_err(|err| err.to_string())?
+            }
+            Format::Yaml => {
+                let value: serde_yaml::Value =
+                    serde_yaml::from_str(text).map_err(|err| err.to_string())?;
+                serde_json::to_value(value).map_err(|err| err.to_string())?
+            }
+        };
+
+        match value {
+            serde_json::Value::Array(_) => Ok(Shape::List),
+            serde_json::Value::Object(map) if map.contains_key("program") => Ok(Shape::Process),
+            serde_json::Value::Object(_) => Ok(Shape::Document),
+            _ => Err("expected a list of processes or a table with `processes`".to_string()),
+        }
+    }
+}
+
+impl Display for Format {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.extension())
+    }
+}
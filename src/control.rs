@@ -26,53 +26,53 @@ pub fn get_service(
     )
 }
 
-pub fn start() -> windows_service::Result<()> {
+pub fn start(wait: Duration) -> windows_service::Result<()> {
     let service = get_service(ServiceManagerAccess::CONNECT, ServiceAccess::START)?;
 
     let service_status = service.query_status()?;
     if service_status.current_state != ServiceState::Running {
         service.start(&[OsStr::new("runservice")])?;
         // Wait for service to stop
-        thread::sleep(Duration::from_secs(1));
+        thread::sleep(wait);
     }
 
     Ok(())
 }
 
-pub fn stop() -> windows_service::Result<()> {
+pub fn stop(wait: Duration) -> windows_service::Result<()> {
     let service = get_service(ServiceManagerAccess::CONNECT, ServiceAccess::STOP)?;
 
     let service_status = service.query_status()?;
     if service_status.current_state != ServiceState::Stopped {
         service.stop()?;
         // Wait for service to stop
-        thread::sleep(Duration::from_secs(1));
+        thread::sleep(wait);
     }
 
     Ok(())
 }
 
-pub fn pause() -> windows_service::Result<()> {
+pub fn pause(wait: Duration) -> windows_service::Result<()> {
     let service = get_service(ServiceManagerAccess::CONNECT, ServiceAccess::PAUSE_CONTINUE)?;
 
     let service_status = service.query_status()?;
     if service_status.current_state != ServiceState::Paused {
         service.pause()?;
         // Wait for service to stop
-        thread::sleep(Duration::from_secs(1));
+        thread::sleep(wait);
     }
 
     Ok(())
 }
 
-pub fn resume() -> windows_service::Result<()> {
+pub fn resume(wait: Duration) -> windows_service::Result<()> {
     let service = get_service(ServiceManagerAccess::CONNECT, ServiceAccess::PAUSE_CONTINUE)?;
 
     let service_status = service.query_status()?;
     if service_status.current_state != ServiceState::Running {
         service.resume()?;
         // Wait for service to stop
-        thread::sleep(Duration::from_secs(1));
+        thread::sleep(wait);
     }
 
     Ok(())
@@ -87,10 +87,14 @@ pub fn status() -> windows_service::Result<()> {
     Ok(())
 }
 
-pub fn install() -> windows_service::Result<()> {
+/// `options` are global command line options passed to the service ahead of `runservice`.
+pub fn install(options: Vec<OsString>) -> windows_service::Result<()> {
     let manager_access = ServiceManagerAccess::CONNECT | ServiceManagerAccess::CREATE_SERVICE;
     let service_manager = ServiceManager::local_computer(None::<&str>, manager_access)?;
 
+    let mut launch_arguments = options;
+    launch_arguments.push(OsString::from("runservice"));
+
     let service_info = ServiceInfo {
         name: OsString::from(super::SERVICE_NAME),
         display_name: OsString::from(SERVICE_DISPLAY_NAME),
@@ -98,7 +102,7 @@ pub fn install() -> windows_service::Result<()> {
         start_type: ServiceStartType::AutoStart,
         error_control: ServiceErrorControl::Normal,
         executable_path: std::env::current_exe().unwrap(),
-        launch_arguments: vec![OsString::from("runservice")],
+        launch_arguments,
         dependencies: vec![],
         account_name: None, // run as System
         account_password: None,
@@ -109,7 +113,7 @@ pub fn install() -> windows_service::Result<()> {
     Ok(())
 }
 
-pub fn uninstall() -> windows_service::Result<()> {
+pub fn uninstall(wait: Duration) -> windows_service::Result<()> {
     let manager_access = ServiceManagerAccess::CONNECT;
     let service_manager = ServiceManager::local_computer(None::<&str>, manager_access)?;
 
@@ -120,9 +124,9 @@ pub fn uninstall() -> windows_service::Result<()> {
     if service_status.current_state != ServiceState::Stopped {
         service.stop()?;
         // Wait for service to stop
-        thread::sleep(Duration::from_secs(1));
+        thread::sleep(wait);
     }
 
     service.delete()?;
     Ok(())
-}
\ No newline at end of file
+}
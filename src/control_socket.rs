@@ -0,0 +1,901 @@
+//! Commands to a running supervisor: one JSON request per connection, answered with JSON
+//! lines ending in `Done`. Listens on a Unix socket, or on a named pipe on Windows that only
+//! administrators and SYSTEM can open.
+
+use serde::{Deserialize, Serialize};
+use std::io::{self, BufRead, BufReader, Write};
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::child_proc::{ProcessCommand, ProcessHandle, ProcessState, ProcessStatus, Reply};
+use crate::journal::{self, Event, EventKind};
+use crate::logger::{log, verbose};
+use crate::units::HumanDuration;
+
+#[cfg(unix)]
+type Stream = std::os::unix::net::UnixStream;
+#[cfg(not(unix))]
+type Stream = std::fs::File;
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "command", rename_all = "snake_case")]
+pub enum Request {
+    Status,
+    /// Pauses the named process, every instance of a group, or everything.
+    Pause {
+        name: Option<String>,
+    },
+    Resume {
+        name: Option<String>,
+    },
+    /// With `rolling`, one process at a time, each once the previous one has kept running
+    /// for `settle`.
+    Restart {
+        name: Option<String>,
+        #[serde(default)]
+        rolling: bool,
+        #[serde(default)]
+        settle: HumanDuration,
+    },
+    /// Sends a signal such as `"HUP"` to the matching processes.
+    Signal {
+        name: String,
+        signal: String,
+    },
+    /// Runs one of their `actions` for the matching processes.
+    Action {
+        name: String,
+        action: String,
+    },
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Response {
+    Status {
+        processes: Vec<ProcessStatus>,
+    },
+    Message {
+        text: String,
+    },
+    Error {
+        text: String,
+    },
+    /// Last line of every answer.
+    Done,
+}
+
+struct Entry {
+    status: Arc<Mutex<ProcessStatus>>,
+    commands: mpsc::Sender<ProcessCommand>,
+}
+
+/// The processes the supervisor currently runs, replaced on every (re)load.
+#[derive(Default)]
+pub struct Registry {
+    entries: Mutex<Vec<Entry>>,
+}
+
+impl Registry {
+    pub fn set(&self, handles: &[ProcessHandle]) {
+        *self.entries.lock().unwrap() = handles
+            .iter()
+            .map(|handle| Entry {
+                status: handle.status.clone(),
+                commands: handle.commands.clone(),
+            })
+            .collect();
+    }
+
+    pub fn status(&self) -> Vec<ProcessStatus> {
+        let entries = self.entries.lock().unwrap();
+        entries
+            .iter()
+            .map(|entry| entry.status.lock().unwrap().clone())
+            .collect()
+    }
+
+    /// Names of the disabled processes, which have no thread to take commands.
+    fn disabled(&self) -> Vec<String> {
+        self.status()
+            .into_iter()
+            .filter(|status| status.state == ProcessState::Disabled)
+            .map(|status| status.name)
+            .collect()
+    }
+
+    /// The matching processes, in start order. Without a name, every process that isn't
+    /// disabled; named, a disabled one is still a target, to be told it's disabled.
+    fn targets(&self, name: Option<&str>) -> Result<Vec<Target>, String> {
+        let targets: Vec<Target> = {
+            let entries = self.entries.lock().unwrap();
+            entries
+                .iter()
+                .map(|entry| Target {
+                    name: entry.status.lock().unwrap().name.clone(),
+                    status: entry.status.clone(),
+                    commands: entry.commands.clone(),
+                })
+                .filter(|target| match name {
+                    Some(name) => matches(name, &target.name),
+                    None => target.status.lock().unwrap().state != ProcessState::Disabled,
+                })
+                .collect()
+        };
+        match (name, targets.is_empty()) {
+            (Some(name), true) => Err(format!("No process named {}", name)),
+            _ => Ok(targets),
+        }
+    }
+
+    /// Sends a command to the matching processes and waits for each of them to carry it out.
+    pub fn command(
+        &self,
+        name: Option<&str>,
+        command: impl Fn(Reply) -> ProcessCommand,
+    ) -> Vec<Result<String, String>> {
+        // Not holding the lock while processes stop
+        match self.targets(name) {
+            Ok(targets) => targets.iter().map(|target| target.send(&command)).collect(),
+            Err(err) => vec![Err(err)],
+        }
+    }
+
+    /// Restarts the matching processes one at a time, each once the previous one has kept
+    /// running for `settle`. The first one that doesn't stops the rollout, leaving the rest
+    /// as they were. Reports each step to `progress` as it happens.
+    pub fn restart_rolling(
+        &self,
+        name: Option<&str>,
+        settle: Duration,
+        mut progress: impl FnMut(Result<String, String>),
+    ) {
+        let targets = match self.targets(name) {
+            Ok(targets) => targets,
+            Err(err) => return progress(Err(err)),
+        };
+        let disabled = match name {
+            Some(_) => vec![],
+            None => self.disabled(),
+        };
+        if !disabled.is_empty() {
+            log!("Rolling restart skips disabled {}", disabled.join(", "));
+            progress(Ok(format!("Skipping disabled {}", disabled.join(", "))));
+        }
+        let rollout = |status: String, failed: bool| Event {
+            status: Some(status),
+            failed,
+            ..Event::new(name.unwrap_or("all"), EventKind::RollingRestart)
+        };
+        journal::record(rollout(
+            format!("started, {} to restart", targets.len()),
+            false,
+        ));
+
+        for (index, target) in targets.iter().enumerate() {
+            progress(Ok(format!(
+                "Restarting {} ({}/{})",
+                target.name,
+                index + 1,
+                targets.len()
+            )));
+            match target
+                .send(ProcessCommand::Restart)
+                .and_then(|_| target.wait_up(settle))
+            {
+                Ok(pid) => progress(Ok(format!("{} is up (pid {})", target.name, pid))),
+                Err(err) => {
+                    let left: Vec<&str> = targets[index + 1..]
+                        .iter()
+                        .map(|target| target.name.as_str())
+                        .collect();
+                    let err = match left.is_empty() {
+                        true => err,
+                        false => format!("{}; not restarting {}", err, left.join(", ")),
+                    };
+                    log!("Rolling restart aborted: {}", err);
+                    journal::record(rollout(format!("aborted: {}", err), true));
+                    return progress(Err(err));
+                }
+            }
+        }
+        journal::record(rollout("finished".to_string(), false));
+        progress(Ok(format!("Restarted {}", targets.len())));
+    }
+}
+
+/// A process commands are sent to.
+struct Target {
+    name: String,
+    status: Arc<Mutex<ProcessStatus>>,
+    commands: mpsc::Sender<ProcessCommand>,
+}
+
+impl Target {
+    /// Waits for the process to carry out `command`.
+    fn send(&self, command: impl Fn(Reply) -> ProcessCommand) -> Result<String, String> {
+        let (reply, result) = mpsc::channel();
+        self.commands
+            .send(command(reply))
+            .map_err(|_| format!("{} is not supervised", self.name))?;
+        result
+            .recv()
+            .map_err(|_| format!("{} is not supervised", self.name))?
+    }
+
+    /// The pid of the process once it is ready and has kept running for `settle`.
+    fn wait_up(&self, settle: Duration) -> Result<u32, String> {
+        let pid = self.status.lock().unwrap().pid;
+        let mut up_at = None;
+        loop {
+            let status = self.status.lock().unwrap().clone();
+            let starting = status.state == ProcessState::Starting;
+            if !(starting || status.state == ProcessState::Running) || status.pid != pid {
+                return Err(format!(
+                    "{} didn't stay up: {}",
+                    self.name,
+                    status.last_exit.unwrap_or_else(|| "exited".to_string())
+                ));
+            }
+            if !starting && Instant::now() >= *up_at.get_or_insert(Instant::now() + settle) {
+                return Ok(pid);
+            }
+            thread::sleep(Duration::from_millis(50));
+        }
+    }
+}
+
+/// `php` names every `php:N` instance as well as a process called `php`.
+pub fn matches(name: &str, process: &str) -> bool {
+    process == name
+        || process
+            .split_once(':')
+            .is_some_and(|(group, _)| group == name)
+}
+
+/// Accepts commands on `address` in a background thread.
+#[cfg(unix)]
+pub fn serve(address: &str, registry: Arc<Registry>) -> io::Result<()> {
+    // Left behind by a supervisor that didn't exit cleanly
+    if Stream::connect(address).is_err() {
+        std::fs::remove_file(address).ok();
+    }
+    let listener = std::os::unix::net::UnixListener::bind(address)?;
+
+    verbose!("Listening for commands on {}", address);
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(err) => {
+                    log!("Control connection failed: {:?}", &err);
+                    continue;
+                }
+            };
+            spawn_handler(stream, &registry);
+        }
+    });
+    Ok(())
+}
+
+/// Accepts commands on the named pipe `address` in a background thread.
+#[cfg(not(unix))]
+pub fn serve(address: &str, registry: Arc<Registry>) -> io::Result<()> {
+    // Fails if the name is taken, so nobody else can listen in our place
+    let mut pipe = pipe::Listener::new(address)?;
+
+    verbose!("Listening for commands on {}", address);
+    thread::spawn(move || loop {
+        match pipe.accept() {
+            Ok(stream) => spawn_handler(stream, &registry),
+            Err(err) => {
+                log!("Control connection failed: {:?}", &err);
+                thread::sleep(Duration::from_secs(1));
+            }
+        }
+    });
+    Ok(())
+}
+
+fn spawn_handler(stream: Stream, registry: &Arc<Registry>) {
+    let registry = registry.clone();
+    thread::spawn(move || {
+        if let Err(err) = handle(stream, &registry) {
+            verbose!("Control connection closed: {:?}", &err);
+        }
+    });
+}
+
+#[cfg(not(unix))]
+mod pipe {
+    use std::fs::File;
+    use std::io;
+    use std::os::windows::io::FromRawHandle;
+    use std::thread;
+    use std::time::Duration;
+    use windows::core::HSTRING;
+    use windows::Win32::Foundation::{GetLastError, ERROR_PIPE_CONNECTED, HANDLE};
+    use windows::Win32::Security::Authorization::{
+        ConvertStringSecurityDescriptorToSecurityDescriptorW, SDDL_REVISION_1,
+    };
+    use windows::Win32::Security::{PSECURITY_DESCRIPTOR, SECURITY_ATTRIBUTES};
+    use windows::Win32::Storage::FileSystem::{FILE_FLAG_FIRST_PIPE_INSTANCE, PIPE_ACCESS_DUPLEX};
+    use windows::Win32::System::Pipes::{
+        ConnectNamedPipe, CreateNamedPipeW, PIPE_READMODE_BYTE, PIPE_REJECT_REMOTE_CLIENTS,
+        PIPE_TYPE_BYTE, PIPE_UNLIMITED_INSTANCES, PIPE_WAIT,
+    };
+
+    /// Full access for SYSTEM and the Administrators group, nothing inherited, nobody else.
+    const ADMIN_ONLY: &str = "D:P(A;;GA;;;SY)(A;;GA;;;BA)";
+
+    const ERROR_PIPE_BUSY: i32 = 231;
+
+    pub struct Listener {
+        name: HSTRING,
+        security: PSECURITY_DESCRIPTOR,
+        /// The instance waiting for the next client.
+        next: HANDLE,
+    }
+
+    // The security descriptor is only read, and lives as long as the listener
+    unsafe impl Send for Listener {}
+
+    impl Listener {
+        pub fn new(address: &str) -> io::Result<Listener> {
+            let mut security = PSECURITY_DESCRIPTOR::default();
+            let converted = unsafe {
+                ConvertStringSecurityDescriptorToSecurityDescriptorW(
+                    &HSTRING::from(ADMIN_ONLY),
+                    SDDL_REVISION_1,
+                    &mut security,
+                    None,
+                )
+            };
+            if !converted.as_bool() {
+                return Err(io::Error::last_os_error());
+            }
+            let mut listener = Listener {
+                name: HSTRING::from(address),
+                security,
+                next: HANDLE::default(),
+            };
+            listener.next = listener.create(true)?;
+            Ok(listener)
+        }
+
+        fn create(&self, first: bool) -> io::Result<HANDLE> {
+            let attributes = SECURITY_ATTRIBUTES {
+                nLength: std::mem::size_of::<SECURITY_ATTRIBUTES>() as u32,
+                lpSecurityDescriptor: self.security.0,
+                bInheritHandle: false.into(),
+            };
+            let mut mode = PIPE_ACCESS_DUPLEX;
+            if first {
+                mode |= FILE_FLAG_FIRST_PIPE_INSTANCE;
+            }
+            let handle = unsafe {
+                CreateNamedPipeW(
+                    &self.name,
+                    mode,
+                    PIPE_TYPE_BYTE | PIPE_READMODE_BYTE | PIPE_WAIT | PIPE_REJECT_REMOTE_CLIENTS,
+                    PIPE_UNLIMITED_INSTANCES,
+                    4096,
+                    4096,
+                    0,
+                    Some(&attributes),
+                )
+            };
+            if handle.is_invalid() {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(handle)
+        }
+
+        /// Waits for a client, and has the next instance ready before handing this one over.
+        pub fn accept(&mut self) -> io::Result<File> {
+            let connected = unsafe { ConnectNamedPipe(self.next, None) };
+            if !connected.as_bool() && unsafe { GetLastError() } != ERROR_PIPE_CONNECTED {
+                let err = io::Error::last_os_error();
+                // Dropping the file closes the broken instance
+                let _ = unsafe { File::from_raw_handle(self.next.0 as _) };
+                self.next = self.create(false)?;
+                return Err(err);
+            }
+            let stream = unsafe { File::from_raw_handle(self.next.0 as _) };
+            self.next = self.create(false)?;
+            Ok(stream)
+        }
+    }
+
+    /// Opens the pipe, waiting a little while every instance is taken.
+    pub fn connect(address: &str) -> io::Result<File> {
+        let mut attempts = 0;
+        loop {
+            match File::options().read(true).write(true).open(address) {
+                Err(err) if err.raw_os_error() == Some(ERROR_PIPE_BUSY) && attempts < 50 => {
+                    attempts += 1;
+                    thread::sleep(Duration::from_millis(20));
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+fn handle(stream: Stream, registry: &Registry) -> io::Result<()> {
+    let mut line = String::new();
+    BufReader::new(stream.try_clone()?).read_line(&mut line)?;
+    let mut writer = stream;
+    let mut respond = |response: Response| -> io::Result<()> {
+        writeln!(writer, "{}", serde_json::to_string(&response)?)
+    };
+
+    let request: Request = match serde_json::from_str(&line) {
+        Ok(request) => request,
+        Err(err) => {
+            respond(Response::Error {
+                text: format!("Invalid request: {}", err),
+            })?;
+            return respond(Response::Done);
+        }
+    };
+    verbose!("Control request: {:?}", &request);
+
+    let results = match request {
+        Request::Status => {
+            respond(Response::Status {
+                processes: registry.status(),
+            })?;
+            vec![]
+        }
+        Request::Pause { name } => registry.command(name.as_deref(), ProcessCommand::Pause),
+        Request::Resume { name } => registry.command(name.as_deref(), ProcessCommand::Resume),
+        Request::Restart {
+            name,
+            rolling: false,
+            ..
+        } => registry.command(name.as_deref(), ProcessCommand::Restart),
+        Request::Signal { name, signal } => registry.command(Some(&name), |reply| {
+            ProcessCommand::Signal(signal.clone(), reply)
+        }),
+        Request::Action { name, action } => registry.command(Some(&name), |reply| {
+            ProcessCommand::Action(action.clone(), reply)
+        }),
+        Request::Restart {
+            name,
+            rolling: true,
+            settle,
+        } => {
+            // Streamed as it goes; a client that went away doesn't stop the rollout
+            let mut closed = false;
+            registry.restart_rolling(name.as_deref(), settle.0, |result| {
+                if !closed {
+                    closed = respond(match result {
+                        Ok(text) => Response::Message { text },
+                        Err(text) => Response::Error { text },
+                    })
+                    .is_err();
+                }
+            });
+            vec![]
+        }
+    };
+    for result in results {
+        respond(match result {
+            Ok(text) => Response::Message { text },
+            Err(text) => Response::Error { text },
+        })?;
+    }
+    respond(Response::Done)
+}
+
+/// Sends `request` to the supervisor at `address`, passing each response to `on_response`.
+pub fn call(
+    address: &str,
+    request: &Request,
+    mut on_response: impl FnMut(Response),
+) -> io::Result<()> {
+    #[cfg(unix)]
+    let mut stream = Stream::connect(address)?;
+    #[cfg(not(unix))]
+    let mut stream = pipe::connect(address)?;
+    writeln!(stream, "{}", serde_json::to_string(request)?)?;
+
+    for line in BufReader::new(stream).lines() {
+        match serde_json::from_str(&line?)? {
+            Response::Done => return Ok(()),
+            response => on_response(response),
+        }
+    }
+    Err(io::Error::new(
+        io::ErrorKind::UnexpectedEof,
+        "supervisor closed the connection",
+    ))
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+    use crate::child_proc::{run_processes, ChildProcess, ProcessState};
+    use crate::proc_config::{ProcessConfig, ProcessConfigState};
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::time::Duration;
+
+    /// Whether `/proc/<pid>/stat` shows the process stopped (`T`), giving the signal a
+    /// moment to land when it isn't `expected` yet.
+    fn is_stopped(pid: u32, expected: bool) -> bool {
+        let mut stopped = !expected;
+        for _ in 0..50 {
+            let stat = std::fs::read_to_string(format!("/proc/{}/stat", pid)).unwrap();
+            let (_, rest) = stat.rsplit_once(')').unwrap();
+            stopped = rest.trim_start().starts_with('T');
+            if stopped == expected {
+                break;
+            }
+            thread::sleep(Duration::from_millis(20));
+        }
+        stopped
+    }
+
+    fn statuses(address: &str) -> Vec<ProcessStatus> {
+        let mut processes = vec![];
+        call(address, &Request::Status, |response| {
+            if let Response::Status { processes: list } = response {
+                processes = list;
+            }
+        })
+        .unwrap();
+        processes
+    }
+
+    #[test]
+    fn test_pause() {
+        let dir = tempfile::tempdir().unwrap();
+        let address = dir.path().join("test.sock").to_string_lossy().into_owned();
+
+        let config = ProcessConfig {
+            name: "sleep".to_string(),
+            program: "sleep".to_string(),
+            args: vec!["30".to_string()],
+            cwd: "/".to_string(),
+            numprocs: 2,
+            ..Default::default()
+        };
+        let disabled = ProcessConfig {
+            name: "off".to_string(),
+            state: ProcessConfigState::Disabled,
+            numprocs: 1,
+            ..config.clone()
+        };
+        let need_exit = Arc::new(AtomicBool::new(false));
+        let handles = run_processes(
+            ChildProcess::from_configs(vec![config, disabled]),
+            &need_exit,
+            Duration::from_millis(20),
+        );
+        let registry = Arc::new(Registry::default());
+        registry.set(&handles);
+        serve(&address, registry).unwrap();
+
+        while statuses(&address)
+            .iter()
+            .any(|s| s.state != ProcessState::Running && s.state != ProcessState::Disabled)
+        {
+            thread::sleep(Duration::from_millis(20));
+        }
+
+        let mut messages = vec![];
+        call(
+            &address,
+            &Request::Pause {
+                name: Some("sleep:1".to_string()),
+            },
+            |response| messages.push(format!("{:?}", response)),
+        )
+        .unwrap();
+        assert_eq!(messages, ["Message { text: \"sleep:1 paused\" }"]);
+
+        let processes = statuses(&address);
+        assert_eq!(processes[0].state, ProcessState::Running);
+        assert_eq!(processes[1].state, ProcessState::Paused);
+        assert!(is_stopped(processes[1].pid, true));
+
+        let mut errors = 0;
+        call(
+            &address,
+            &Request::Resume {
+                name: Some("sleep".to_string()),
+            },
+            |response| {
+                if let Response::Error { .. } = response {
+                    errors += 1;
+                }
+            },
+        )
+        .unwrap();
+        assert_eq!(errors, 0);
+        let processes = statuses(&address);
+        assert!(processes[..2]
+            .iter()
+            .all(|s| s.state == ProcessState::Running));
+        assert!(!is_stopped(processes[1].pid, false));
+
+        // Everything, past the disabled process
+        for (request, state) in [
+            (Request::Pause { name: None }, ProcessState::Paused),
+            (Request::Resume { name: None }, ProcessState::Running),
+        ] {
+            let mut messages = vec![];
+            call(&address, &request, |response| {
+                messages.push(format!("{:?}", response))
+            })
+            .unwrap();
+            assert_eq!(messages.len(), 2, "{:?}", messages);
+            assert!(
+                messages.iter().all(|m| m.starts_with("Message")),
+                "{:?}",
+                messages
+            );
+            let processes = statuses(&address);
+            assert!(processes[..2].iter().all(|s| s.state == state));
+            assert_eq!(processes[2].state, ProcessState::Disabled);
+        }
+
+        call(
+            &address,
+            &Request::Pause {
+                name: Some("nginx".to_string()),
+            },
+            |response| {
+                assert!(matches!(response, Response::Error { .. }));
+            },
+        )
+        .unwrap();
+
+        need_exit.store(true, Ordering::Relaxed);
+        for handle in handles {
+            handle.thread.join().unwrap();
+        }
+    }
+
+    #[test]
+    fn test_rolling_restart() {
+        let dir = tempfile::tempdir().unwrap();
+        let address = dir.path().join("test.sock").to_string_lossy().into_owned();
+
+        // Fails to come back once `broken` exists
+        let config = ProcessConfig {
+            name: "worker".to_string(),
+            program: "sh".to_string(),
+            args: vec![
+                "-c".to_string(),
+                "[ -e broken ] && exit 3; exec sleep 30".to_string(),
+            ],
+            cwd: dir.path().to_string_lossy().into_owned(),
+            numprocs: 3,
+            ..Default::default()
+        };
+        let disabled = ProcessConfig {
+            name: "off".to_string(),
+            state: ProcessConfigState::Disabled,
+            numprocs: 1,
+            ..config.clone()
+        };
+        let need_exit = Arc::new(AtomicBool::new(false));
+        let handles = run_processes(
+            ChildProcess::from_configs(vec![config, disabled]),
+            &need_exit,
+            Duration::from_millis(20),
+        );
+        let registry = Arc::new(Registry::default());
+        registry.set(&handles);
+        serve(&address, registry).unwrap();
+
+        let workers = |address: &str| -> Vec<ProcessStatus> {
+            statuses(address)
+                .into_iter()
+                .filter(|s| s.name.starts_with("worker"))
+                .collect()
+        };
+        while workers(&address)
+            .iter()
+            .any(|s| s.state != ProcessState::Running)
+        {
+            thread::sleep(Duration::from_millis(20));
+        }
+        let pids = |address: &str| -> Vec<u32> { workers(address).iter().map(|s| s.pid).collect() };
+        let settle = HumanDuration(Duration::from_millis(200));
+
+        // Everything, past the disabled process
+        let before = pids(&address);
+        let mut messages = vec![];
+        let all = Request::Restart {
+            name: None,
+            rolling: true,
+            settle,
+        };
+        call(&address, &all, |response| {
+            messages.push(format!("{:?}", response))
+        })
+        .unwrap();
+        let after = pids(&address);
+        assert_eq!(messages.len(), 8, "{:?}", messages);
+        assert_eq!(messages[0], "Message { text: \"Skipping disabled off\" }");
+        assert_eq!(
+            messages[1],
+            "Message { text: \"Restarting worker:0 (1/3)\" }"
+        );
+        assert_eq!(
+            messages[2],
+            format!("Message {{ text: \"worker:0 is up (pid {})\" }}", after[0])
+        );
+        assert_eq!(messages[7], "Message { text: \"Restarted 3\" }");
+        assert!(before
+            .iter()
+            .zip(&after)
+            .all(|(before, after)| before != after));
+
+        // All at once, past the disabled process as well
+        let before = after;
+        let mut messages = vec![];
+        let at_once = Request::Restart {
+            name: None,
+            rolling: false,
+            settle,
+        };
+        call(&address, &at_once, |response| {
+            messages.push(format!("{:?}", response))
+        })
+        .unwrap();
+        assert_eq!(messages.len(), 3, "{:?}", messages);
+        assert!(
+            messages.iter().all(|m| m.starts_with("Message")),
+            "{:?}",
+            messages
+        );
+        // Past the check for `broken`, which is about to be created
+        let sleeping = |s: &ProcessStatus| {
+            std::fs::read_to_string(format!("/proc/{}/comm", s.pid))
+                .is_ok_and(|comm| comm.trim() == "sleep")
+        };
+        while workers(&address)
+            .iter()
+            .any(|s| s.state != ProcessState::Running || !sleeping(s))
+        {
+            thread::sleep(Duration::from_millis(20));
+        }
+        let after = pids(&address);
+        assert!(before
+            .iter()
+            .zip(&after)
+            .all(|(before, after)| before != after));
+
+        let rolling = Request::Restart {
+            name: Some("worker".to_string()),
+            rolling: true,
+            settle,
+        };
+
+        std::fs::write(dir.path().join("broken"), "").unwrap();
+        let mut messages = vec![];
+        call(&address, &rolling, |response| {
+            messages.push(format!("{:?}", response))
+        })
+        .unwrap();
+        assert_eq!(
+            messages[1..],
+            ["Error { text: \"worker:0 didn't stay up: exit status: 3; not restarting worker:1, worker:2\" }"]
+        );
+        // The rest keep running as they were
+        assert_eq!(pids(&address)[1..], after[1..]);
+
+        need_exit.store(true, Ordering::Relaxed);
+        for handle in handles {
+            handle.thread.join().unwrap();
+        }
+    }
+
+    #[test]
+    fn test_signal() {
+        use crate::proc_config::Action;
+
+        let dir = tempfile::tempdir().unwrap();
+        let address = dir.path().join("test.sock").to_string_lossy().into_owned();
+
+        let shell = |script: &str| {
+            Action::Command(vec!["sh".to_string(), "-c".to_string(), script.to_string()])
+        };
+        let mut config = ProcessConfig {
+            name: "app".to_string(),
+            program: "sh".to_string(),
+            args: vec![
+                "-c".to_string(),
+                "trap 'echo usr1 >> signals' USR1; touch trapped; while :; do sleep 0.05; done"
+                    .to_string(),
+            ],
+            cwd: dir.path().to_string_lossy().into_owned(),
+            ..Default::default()
+        };
+        config
+            .actions
+            .insert("reopen".to_string(), Action::Signal("USR1".to_string()));
+        config
+            .actions
+            .insert("notify".to_string(), shell("kill -USR1 $SERVICERS_PID"));
+        config
+            .actions
+            .insert("broken".to_string(), shell("echo oops >&2; exit 3"));
+        let need_exit = Arc::new(AtomicBool::new(false));
+        let handles = run_processes(
+            ChildProcess::from_configs(vec![config]),
+            &need_exit,
+            Duration::from_millis(20),
+        );
+        let registry = Arc::new(Registry::default());
+        registry.set(&handles);
+        serve(&address, registry).unwrap();
+        // Running is too early: USR1 would kill it before the trap is set
+        while !dir.path().join("trapped").exists() {
+            thread::sleep(Duration::from_millis(20));
+        }
+
+        let send = |request: Request| {
+            let mut messages = vec![];
+            call(&address, &request, |response| {
+                messages.push(format!("{:?}", response))
+            })
+            .unwrap();
+            messages
+        };
+        let action = |action: &str| {
+            send(Request::Action {
+                name: "app".to_string(),
+                action: action.to_string(),
+            })
+        };
+        // Waits for the trap to run: signals sent while one is pending are merged into it
+        let received = |count: usize| {
+            let mut lines = 0;
+            for _ in 0..50 {
+                let signals = std::fs::read_to_string(dir.path().join("signals"));
+                lines = signals.unwrap_or_default().lines().count();
+                if lines == count {
+                    break;
+                }
+                thread::sleep(Duration::from_millis(20));
+            }
+            lines
+        };
+        let signal = Request::Signal {
+            name: "app".to_string(),
+            signal: "SIGUSR1".to_string(),
+        };
+        assert_eq!(send(signal), ["Message { text: \"Sent SIGUSR1 to app\" }"]);
+        assert_eq!(received(1), 1);
+        assert_eq!(
+            action("reopen"),
+            ["Message { text: \"Ran reopen of app\" }"]
+        );
+        assert_eq!(received(2), 2);
+        assert_eq!(
+            action("notify"),
+            ["Message { text: \"Ran notify of app\" }"]
+        );
+        assert_eq!(received(3), 3);
+        assert_eq!(
+            action("broken"),
+            ["Error { text: \"broken of app failed: exit status: 3 (oops)\" }"]
+        );
+        assert_eq!(
+            action("reload"),
+            ["Error { text: \"app has no action \\\"reload\\\"\" }"]
+        );
+
+        need_exit.store(true, Ordering::Relaxed);
+        for handle in handles {
+            handle.thread.join().unwrap();
+        }
+    }
+}
@@ -0,0 +1,196 @@
+//! Crash reports: how a process exited abnormally and what it printed last, as text files in
+//! `crashes/` next to `servicers.log`. Only the newest reports of each process are kept.
+
+use chrono::Utc;
+use std::fmt::Write as _;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::control_socket::matches;
+use crate::logger;
+use crate::output::{Line, Stream};
+use crate::proc_config::ProcessConfig;
+use crate::units::format_elapsed;
+
+const KEEP_PER_PROCESS: usize = 10;
+
+pub struct Crash<'a> {
+    pub config: &'a ProcessConfig,
+    pub pid: u32,
+    /// e.g. `exit status: 1` or `signal: 11 (SIGSEGV) (core dumped)`.
+    pub exit: String,
+    pub uptime: Option<Duration>,
+    pub restarts: u32,
+    pub output: Vec<Line>,
+}
+
+/// A report as listed, from its header.
+#[derive(Debug)]
+pub struct ReportInfo {
+    pub file: String,
+    pub process: String,
+    pub time: String,
+    pub exit: String,
+}
+
+pub fn reports_dir() -> PathBuf {
+    logger::log_dir().join("crashes")
+}
+
+/// Writes a report and removes the process's oldest ones, returning the new file's path.
+pub fn write(dir: &Path, crash: &Crash) -> io::Result<PathBuf> {
+    let now = Utc::now();
+    let config = crash.config;
+
+    let mut text = String::new();
+    writeln!(text, "process: {}", config.name).unwrap();
+    writeln!(text, "time: {}", now.format("%F %T UTC")).unwrap();
+    writeln!(text, "exit: {}", crash.exit).unwrap();
+    writeln!(text, "pid: {}", crash.pid).unwrap();
+    if let Some(uptime) = crash.uptime {
+        writeln!(text, "uptime: {}", format_elapsed(uptime)).unwrap();
+    }
+    writeln!(text, "restarts: {}", crash.restarts).unwrap();
+    writeln!(text, "program: {}", config.program).unwrap();
+    writeln!(text, "args: {:?}", config.args).unwrap();
+    writeln!(text, "cwd: {}", config.cwd).unwrap();
+    // Names only, values may be secrets
+    let env: Vec<&str> = config.env.keys().map(String::as_str).collect();
+    writeln!(text, "env: {}", env.join(", ")).unwrap();
+
+    writeln!(text, "\nlast {} lines of output:", crash.output.len()).unwrap();
+    for line in &crash.output {
+        let stream = match line.stream {
+            Stream::Stdout => "out",
+            Stream::Stderr => "err",
+        };
+        writeln!(text, "{}| {}", stream, line.text).unwrap();
+    }
+
+    std::fs::create_dir_all(dir)?;
+    // `:` of instance names isn't allowed in Windows file names
+    let stem = format!(
+        "{}-{}",
+        config.name.replace(':', "_"),
+        now.format("%Y%m%dT%H%M%S%.3fZ")
+    );
+    let mut path = dir.join(format!("{}.txt", stem));
+    for n in 1.. {
+        if !path.exists() {
+            break;
+        }
+        path = dir.join(format!("{}-{}.txt", stem, n));
+    }
+    std::fs::write(&path, text)?;
+
+    let old: Vec<ReportInfo> = list(dir, Some(&config.name))?
+        .into_iter()
+        .filter(|report| report.process == config.name)
+        .collect();
+    for report in old.iter().rev().skip(KEEP_PER_PROCESS) {
+        std::fs::remove_file(dir.join(&report.file)).ok();
+    }
+    Ok(path)
+}
+
+fn read_info(path: &Path) -> Option<ReportInfo> {
+    let text = std::fs::read_to_string(path).ok()?;
+    let field = |name: &str| {
+        text.lines()
+            .take_while(|line| !line.is_empty())
+            .find_map(|line| line.strip_prefix(name)?.strip_prefix(": "))
+            .map(str::to_string)
+    };
+    Some(ReportInfo {
+        file: path.file_name()?.to_string_lossy().into_owned(),
+        process: field("process")?,
+        time: field("time")?,
+        exit: field("exit").unwrap_or_default(),
+    })
+}
+
+/// Reports of `name` (a process or group, all when `None`), oldest first.
+pub fn list(dir: &Path, name: Option<&str>) -> io::Result<Vec<ReportInfo>> {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+        Err(err) => return Err(err),
+    };
+    let mut reports: Vec<ReportInfo> = entries
+        .filter_map(|entry| read_info(&entry.ok()?.path()))
+        .filter(|report| name.is_none_or(|name| matches(name, &report.process)))
+        .collect();
+    reports.sort_by(|a, b| (&a.time, &a.file).cmp(&(&b.time, &b.file)));
+    Ok(reports)
+}
+
+/// A report by file name, or the newest one of a process or group.
+pub fn find(dir: &Path, report: &str) -> Result<PathBuf, String> {
+    let path = dir.join(report);
+    if !report.contains(['/', '\\']) && path.is_file() {
+        return Ok(path);
+    }
+    match list(dir, Some(report))
+        .map_err(|err| err.to_string())?
+        .pop()
+    {
+        Some(info) => Ok(dir.join(info.file)),
+        None => Err(format!("No crash report {} in {}", report, dir.display())),
+    }
+}
+
+#[test]
+fn test_reports() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut config = ProcessConfig {
+        name: "php-cgi:1".to_string(),
+        program: "php-cgi".to_string(),
+        args: vec!["-b".to_string(), "127.0.0.1:9001".to_string()],
+        ..Default::default()
+    };
+    config
+        .env
+        .insert("PHP_FCGI_CHILDREN".to_string(), "4".to_string());
+
+    let output = vec![Line {
+        stream: Stream::Stderr,
+        text: "PHP Fatal error: out of memory".to_string(),
+    }];
+    for _ in 0..KEEP_PER_PROCESS + 2 {
+        write(
+            dir.path(),
+            &Crash {
+                config: &config,
+                pid: 42,
+                exit: "exit status: 255".to_string(),
+                uptime: Some(Duration::from_secs(75)),
+                restarts: 3,
+                output: output.clone(),
+            },
+        )
+        .unwrap();
+    }
+
+    let reports = list(dir.path(), Some("php-cgi")).unwrap();
+    assert_eq!(reports.len(), KEEP_PER_PROCESS);
+    assert_eq!(reports[0].process, "php-cgi:1");
+    assert_eq!(reports[0].exit, "exit status: 255");
+    assert!(list(dir.path(), Some("nginx")).unwrap().is_empty());
+
+    let newest = find(dir.path(), "php-cgi:1").unwrap();
+    assert_eq!(
+        newest.file_name().unwrap().to_string_lossy(),
+        reports[9].file
+    );
+    assert_eq!(
+        find(dir.path(), &reports[0].file).unwrap(),
+        dir.path().join(&reports[0].file)
+    );
+    assert!(find(dir.path(), "nginx").is_err());
+
+    let text = std::fs::read_to_string(newest).unwrap();
+    assert!(text.contains("uptime: 1m 15s\n"));
+    assert!(text.contains("env: PHP_FCGI_CHILDREN\n"));
+    assert!(text.ends_with("\nlast 1 lines of output:\nerr| PHP Fatal error: out of memory\n"));
+}
@@ -0,0 +1,135 @@
+//! `run --daemon`: detaching from the terminal, and the pidfile lock that keeps a second
+//! supervisor from running the same config.
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, Write};
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+
+use crate::logger::{self, log};
+
+/// An exclusively locked pidfile, emptied when dropped.
+pub struct Pidfile {
+    file: File,
+}
+
+impl Pidfile {
+    /// Fails if another supervisor holds the lock. The lock dies with its process, so a
+    /// pidfile left behind by a crashed supervisor is taken over.
+    pub fn lock(path: &Path) -> Result<Pidfile, String> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)
+            .map_err(|err| format!("can't open {}: {}", path.display(), err))?;
+        let mut previous = String::new();
+        file.read_to_string(&mut previous).ok();
+        let previous = previous.trim();
+
+        if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } != 0 {
+            let err = io::Error::last_os_error();
+            if err.raw_os_error() == Some(libc::EWOULDBLOCK) {
+                return Err(format!(
+                    "already running as pid {} (locked {})",
+                    previous,
+                    path.display()
+                ));
+            }
+            return Err(format!("can't lock {}: {}", path.display(), err));
+        }
+        if !previous.is_empty() {
+            log!(
+                "Taking over stale pidfile {} of pid {}",
+                path.display(),
+                previous
+            );
+        }
+
+        Ok(Pidfile { file })
+    }
+
+    /// Records the current process; called after `daemonize` as that changes it.
+    pub fn write_pid(&mut self) -> io::Result<()> {
+        self.file.set_len(0)?;
+        self.file.rewind()?;
+        writeln!(self.file, "{}", std::process::id())
+    }
+}
+
+impl Drop for Pidfile {
+    fn drop(&mut self) {
+        // Emptied rather than removed: another supervisor may have opened it already and be
+        // waiting for the lock, which it would then hold on a file nobody else can find
+        self.file.set_len(0).ok();
+    }
+}
+
+fn fork() -> io::Result<bool> {
+    match unsafe { libc::fork() } {
+        -1 => Err(io::Error::last_os_error()),
+        0 => Ok(true),
+        _ => Ok(false),
+    }
+}
+
+/// Continues in a background process in its own session, with stdin from `/dev/null` and
+/// stdout/stderr appended to the log. The calling process exits. Must be called before any
+/// threads are started. The working directory and umask are kept: `--config`, `--log-dir`
+/// and process `cwd`s may be relative to the former, and processes get their file
+/// permissions from the latter.
+pub fn daemonize() -> io::Result<()> {
+    let log = OpenOptions::new()
+        .append(true)
+        .create(true)
+        .open(logger::log_path())?;
+    let null = File::open("/dev/null")?;
+
+    if !fork()? {
+        unsafe { libc::_exit(0) };
+    }
+    if unsafe { libc::setsid() } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    // No longer a session leader, so a terminal can't become the controlling one
+    if !fork()? {
+        unsafe { libc::_exit(0) };
+    }
+
+    for (from, to) in [(&null, 0), (&log, 1), (&log, 2)] {
+        if unsafe { libc::dup2(from.as_raw_fd(), to) } == -1 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    logger::set_console(false);
+    log!("Running in the background as pid {}", std::process::id());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pidfile() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("servicers.pid");
+
+        // Left behind by a supervisor that crashed
+        std::fs::write(&path, "999999\n").unwrap();
+        let mut pidfile = Pidfile::lock(&path).unwrap();
+        pidfile.write_pid().unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            format!("{}\n", std::process::id())
+        );
+
+        let err = Pidfile::lock(&path).err().unwrap();
+        assert!(err.starts_with(&format!("already running as pid {}", std::process::id())));
+
+        drop(pidfile);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "");
+        assert!(Pidfile::lock(&path).is_ok());
+    }
+}
@@ -0,0 +1,334 @@
+use std::fmt::Write;
+
+use crate::proc_config::{
+    replace_instance, ExitMatch, HookFailure, ProcessConfig, ProcessConfigState, RestartPolicy,
+};
+
+/// Target grouping the exported units.
+pub fn target_name(prefix: &str) -> String {
+    format!("{}.target", prefix.trim_end_matches('-'))
+}
+
+/// Unit file name; replicated processes become templates (`php@.service`).
+pub fn unit_name(prefix: &str, process: &ProcessConfig) -> String {
+    match process.numprocs > 1 {
+        true => format!("{}{}@.service", prefix, process.name),
+        false => format!("{}{}.service", prefix, process.name),
+    }
+}
+
+/// Names the target starts: every instance of a template.
+fn instance_names(prefix: &str, process: &ProcessConfig) -> Vec<String> {
+    let width = instance_width(process);
+    match process.numprocs > 1 {
+        true => (0..process.numprocs)
+            .map(|n| {
+                format!(
+                    "{}{}@{:0width$}.service",
+                    prefix,
+                    process.name,
+                    n,
+                    width = width
+                )
+            })
+            .collect(),
+        false => vec![unit_name(prefix, process)],
+    }
+}
+
+/// Quotes a word for `ExecStart=` when needed and escapes `%` specifiers and `$` expansion.
+pub fn escape_word(word: &str) -> String {
+    quote(word, word.replace('%', "%%").replace('$', "$$"))
+}
+
+/// Escapes `%` specifiers, for settings taken as they are, such as `WorkingDirectory=` and
+/// `User=`, where systemd neither unquotes nor expands `$`.
+pub fn escape_specifiers(value: &str) -> String {
+    value.replace('%', "%%")
+}
+
+/// Quotes an `Environment=` assignment when needed. `$` isn't expanded there.
+fn escape_assignment(assignment: &str) -> String {
+    quote(assignment, escape_specifiers(assignment))
+}
+
+/// `escaped` in double quotes if `word` has whitespace, quotes or backslashes, or is empty.
+fn quote(word: &str, escaped: String) -> String {
+    let needs_quotes = word.is_empty()
+        || word
+            .chars()
+            .any(|c| c.is_whitespace() || matches!(c, '"' | '\'' | '\\' | ';'));
+
+    match needs_quotes {
+        true => format!("\"{}\"", escaped.replace('\\', "\\\\").replace('"', "\\\"")),
+        false => escaped,
+    }
+}
+
+/// Refers to the instance with `%i` in a word already escaped.
+fn with_instance(escaped: &str) -> String {
+    replace_instance(escaped, |_| "%i".to_string())
+}
+
+fn escape_instance(word: &str) -> String {
+    with_instance(&escape_word(word))
+}
+
+/// The widest `{instance:0N}` padding the process uses. `%i` can't be padded, so the
+/// instance names are instead.
+fn instance_width(process: &ProcessConfig) -> usize {
+    let hooks = [
+        &process.pre_start,
+        &process.post_start,
+        &process.pre_stop,
+        &process.post_stop,
+    ];
+    let words = hooks.into_iter().flatten().flatten();
+    let mut width = 0;
+    for word in process.args.iter().chain(process.env.values()).chain(words) {
+        replace_instance(word, |padding| {
+            width = width.max(padding);
+            String::new()
+        });
+    }
+    width
+}
+
+pub fn render_unit(prefix: &str, process: &ProcessConfig, processes: &[ProcessConfig]) -> String {
+    let mut unit = String::new();
+    let target = target_name(prefix);
+
+    writeln!(unit, "[Unit]").unwrap();
+    match process.numprocs > 1 {
+        true => writeln!(
+            unit,
+            "Description=servicers: {} (instance %i)",
+            process.name
+        )
+        .unwrap(),
+        false => writeln!(unit, "Description=servicers: {}", process.name).unwrap(),
+    }
+    writeln!(unit, "PartOf={}", target).unwrap();
+    let dependencies: Vec<String> = process
+        .depends_on
+        .iter()
+        .filter_map(|name| processes.iter().find(|p| &p.name == name))
+        .flat_map(|dependency| instance_names(prefix, dependency))
+        .collect();
+    if !dependencies.is_empty() {
+        writeln!(unit, "After={}", dependencies.join(" ")).unwrap();
+        writeln!(unit, "Requires={}", dependencies.join(" ")).unwrap();
+    }
+
+    writeln!(unit).unwrap();
+    writeln!(unit, "[Service]").unwrap();
+    writeln!(unit, "Type=simple").unwrap();
+    if !process.cwd.is_empty() {
+        writeln!(unit, "WorkingDirectory={}", escape_specifiers(&process.cwd)).unwrap();
+    }
+    if let Some(user) = &process.user {
+        writeln!(unit, "User={}", escape_specifiers(user)).unwrap();
+    }
+    if let Some(group) = &process.group {
+        writeln!(unit, "Group={}", escape_specifiers(group)).unwrap();
+    }
+    if !process.groups.is_empty() {
+        let groups: Vec<String> = process
+            .groups
+            .iter()
+            .map(|group| escape_specifiers(group))
+            .collect();
+        writeln!(unit, "SupplementaryGroups={}", groups.join(" ")).unwrap();
+    }
+    for (key, value) in &process.env {
+        let assignment = format!("{}={}", key, value);
+        writeln!(
+            unit,
+            "Environment={}",
+            with_instance(&escape_assignment(&assignment))
+        )
+        .unwrap();
+    }
+    if process.numprocs > 1 {
+        writeln!(unit, "Environment=SERVICERS_INSTANCE=%i").unwrap();
+    }
+
+    let command: Vec<String> = std::iter::once(&process.program)
+        .chain(process.args.iter())
+        .map(|word| escape_instance(word))
+        .collect();
+    writeln!(unit, "ExecStart={}", command.join(" ")).unwrap();
+    // `-` lets the start go on when the command fails
+    let ignore = match process.pre_start_failure {
+        HookFailure::Fail => "",
+        HookFailure::Ignore => "-",
+    };
+    // systemd sends `KillSignal=` once the `ExecStop=` commands are done, as `pre_stop` runs
+    let hooks = [
+        ("ExecStartPre", ignore, &process.pre_start),
+        ("ExecStartPost", "-", &process.post_start),
+        ("ExecStop", "-", &process.pre_stop),
+        ("ExecStopPost", "-", &process.post_stop),
+    ];
+    for (key, prefix, commands) in hooks {
+        for command in commands {
+            let command: Vec<String> = command.iter().map(|word| escape_instance(word)).collect();
+            writeln!(unit, "{}={}{}", key, prefix, command.join(" ")).unwrap();
+        }
+    }
+
+    let restart = match process.restart {
+        RestartPolicy::Always => "always",
+        RestartPolicy::OnFailure => "on-failure",
+        RestartPolicy::Never => "no",
+    };
+    writeln!(unit, "Restart={}", restart).unwrap();
+    if !process.restart_delay.is_zero() {
+        writeln!(unit, "RestartSec={}", process.restart_delay).unwrap();
+    }
+    let exit_codes = [
+        ("SuccessExitStatus", &process.success_exit_codes),
+        ("RestartForceExitStatus", &process.restart_on_exit_codes),
+        ("RestartPreventExitStatus", &process.fatal_exit_codes),
+    ];
+    for (key, codes) in exit_codes {
+        // 0 is implied, and can't be taken out
+        let codes: Vec<String> = codes
+            .iter()
+            .filter(|code| **code != ExitMatch::Code(0))
+            .map(|code| match code {
+                ExitMatch::Signal(name) if !name.to_ascii_uppercase().starts_with("SIG") => {
+                    format!("SIG{}", name.to_ascii_uppercase())
+                }
+                code => code.to_string().to_ascii_uppercase(),
+            })
+            .collect();
+        if !codes.is_empty() {
+            writeln!(unit, "{}={}", key, codes.join(" ")).unwrap();
+        }
+    }
+    let signal = process.stop_signal.to_ascii_uppercase();
+    match signal.starts_with("SIG") || signal.parse::<u32>().is_ok() {
+        true => writeln!(unit, "KillSignal={}", signal).unwrap(),
+        false => writeln!(unit, "KillSignal=SIG{}", signal).unwrap(),
+    }
+    writeln!(unit, "TimeoutStopSec={}", process.stop_timeout).unwrap();
+
+    writeln!(unit).unwrap();
+    writeln!(unit, "[Install]").unwrap();
+    writeln!(unit, "WantedBy={}", target).unwrap();
+
+    unit
+}
+
+/// Groups all enabled processes, so `systemctl start servicers.target` starts them.
+pub fn render_target(prefix: &str, processes: &[ProcessConfig]) -> String {
+    let wants: Vec<String> = processes
+        .iter()
+        .filter(|process| process.is_valid() && process.state == ProcessConfigState::Enabled)
+        .flat_map(|process| instance_names(prefix, process))
+        .collect();
+
+    let mut unit = String::new();
+    writeln!(unit, "[Unit]").unwrap();
+    writeln!(unit, "Description=servicers processes").unwrap();
+    if !wants.is_empty() {
+        writeln!(unit, "Wants={}", wants.join(" ")).unwrap();
+    }
+    writeln!(unit).unwrap();
+    writeln!(unit, "[Install]").unwrap();
+    writeln!(unit, "WantedBy=multi-user.target").unwrap();
+    unit
+}
+
+/// File name and contents of every unit, target last.
+pub fn render_systemd(prefix: &str, processes: &[ProcessConfig]) -> Vec<(String, String)> {
+    let mut units: Vec<(String, String)> = processes
+        .iter()
+        .filter(|process| !process.program.is_empty())
+        .map(|process| {
+            (
+                unit_name(prefix, process),
+                render_unit(prefix, process, processes),
+            )
+        })
+        .collect();
+    units.push((target_name(prefix), render_target(prefix, processes)));
+    units
+}
+
+#[test]
+fn test_render_systemd() {
+    use crate::units::HumanDuration;
+
+    let mut php = ProcessConfig::_new(
+        "/usr/bin/php-cgi".to_string(),
+        vec!["-b".to_string(), "127.0.0.1:900{instance}".to_string()],
+        "/srv/www".to_string(),
+    );
+    php.numprocs = 2;
+    php.env.insert(
+        "PHP_INI_SCAN_DIR".to_string(),
+        "/etc/php/conf.d".to_string(),
+    );
+    // Passed on as is: systemd doesn't expand `$` in `Environment=`
+    php.env
+        .insert("PATH".to_string(), "$HOME/bin:/usr/bin".to_string());
+    php.restart = RestartPolicy::OnFailure;
+    php.restart_delay = HumanDuration::from_secs(2);
+    php.stop_signal = "QUIT".to_string();
+    php.success_exit_codes = vec![ExitMatch::Code(0), ExitMatch::Signal("QUIT".to_string())];
+    php.fatal_exit_codes = vec![ExitMatch::Code(78)];
+    php.pre_start = vec![vec![
+        "mkdir".to_string(),
+        "-p".to_string(),
+        "/run/php".to_string(),
+    ]];
+
+    let mut nginx = ProcessConfig::_new(
+        "/usr/sbin/nginx".to_string(),
+        vec!["-g".to_string(), "daemon off;".to_string()],
+        "/etc/nginx".to_string(),
+    );
+    nginx.depends_on = vec!["php-cgi".to_string()];
+    nginx
+        .env
+        .insert("GREETING".to_string(), "100% \"ready\"".to_string());
+    nginx.pre_stop = vec![vec![
+        "/usr/sbin/nginx".to_string(),
+        "-s".to_string(),
+        "quit".to_string(),
+    ]];
+
+    let mut padded = php.clone();
+    padded.args[1] = "127.0.0.1:90{instance:02}".to_string();
+    assert_eq!(
+        instance_names("servicers-", &padded),
+        [
+            "servicers-php-cgi@00.service",
+            "servicers-php-cgi@01.service"
+        ]
+    );
+    assert!(render_unit("servicers-", &padded, &[]).contains("-b 127.0.0.1:90%i\n"));
+
+    let units = render_systemd("servicers-", &[php, nginx]);
+    let names: Vec<&str> = units.iter().map(|(name, _)| name.as_str()).collect();
+    assert_eq!(
+        names,
+        [
+            "servicers-php-cgi@.service",
+            "servicers-nginx.service",
+            "servicers.target"
+        ]
+    );
+
+    assert_eq!(
+        units[0].1,
+        include_str!("../test/systemd/servicers-php-cgi@.service")
+    );
+    assert_eq!(
+        units[1].1,
+        include_str!("../test/systemd/servicers-nginx.service")
+    );
+    assert_eq!(units[2].1, include_str!("../test/systemd/servicers.target"));
+}
@@ -0,0 +1,430 @@
+//! `watch`: restarts a process when files under its watched paths change, once they have
+//! been quiet for `debounce`. Changes are read from inotify, so only on Linux.
+
+#[cfg(target_os = "linux")]
+pub use linux::FileWatcher;
+
+#[cfg(not(target_os = "linux"))]
+pub struct FileWatcher;
+
+#[cfg(not(target_os = "linux"))]
+impl FileWatcher {
+    pub fn new(
+        _config: &crate::proc_config::WatchConfig,
+        _cwd: &std::path::Path,
+    ) -> std::io::Result<FileWatcher> {
+        Err(std::io::Error::new(
+            std::io::ErrorKind::Unsupported,
+            "watch is only supported on Linux",
+        ))
+    }
+
+    pub fn poll(&mut self) -> Option<String> {
+        None
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod linux {
+    use std::collections::HashMap;
+    use std::ffi::{CString, OsStr};
+    use std::io;
+    use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+    use std::os::unix::ffi::OsStrExt;
+    use std::path::{Component, Path, PathBuf};
+    use std::time::Instant;
+
+    use crate::logger::log;
+    use crate::proc_config::WatchConfig;
+
+    /// `*` and `?` within a path component, `**` across them, `[a-z]` and `[!a-z]` classes.
+    fn glob_match(pattern: &[char], path: &[char]) -> bool {
+        match pattern {
+            [] => path.is_empty(),
+            // `**/` stands for any number of whole directories
+            ['*', '*', '/', rest @ ..] => (0..=path.len())
+                .filter(|&i| i == 0 || path[i - 1] == '/')
+                .any(|i| glob_match(rest, &path[i..])),
+            ['*', '*', rest @ ..] => (0..=path.len()).any(|i| glob_match(rest, &path[i..])),
+            ['*', rest @ ..] => (0..=path.len())
+                .take_while(|&i| i == 0 || path[i - 1] != '/')
+                .any(|i| glob_match(rest, &path[i..])),
+            ['?', rest @ ..] => {
+                path.first().is_some_and(|c| *c != '/') && glob_match(rest, &path[1..])
+            }
+            ['[', class @ ..] => match class.iter().skip(1).position(|c| *c == ']') {
+                Some(end) => {
+                    let (class, rest) = (&class[..end + 1], &class[end + 2..]);
+                    let (negated, class) = match class {
+                        ['!' | '^', class @ ..] => (true, class),
+                        _ => (false, class),
+                    };
+                    let c = match path.first() {
+                        Some(c) if *c != '/' => *c,
+                        _ => return false,
+                    };
+                    let mut found = false;
+                    let mut i = 0;
+                    while i < class.len() {
+                        if i + 2 < class.len() && class[i + 1] == '-' {
+                            found |= (class[i]..=class[i + 2]).contains(&c);
+                            i += 3;
+                        } else {
+                            found |= class[i] == c;
+                            i += 1;
+                        }
+                    }
+                    found != negated && glob_match(rest, &path[1..])
+                }
+                // No closing bracket, so a literal one
+                None => path.first() == Some(&'[') && glob_match(class, &path[1..]),
+            },
+            [c, rest @ ..] => path.first() == Some(c) && glob_match(rest, &path[1..]),
+        }
+    }
+
+    /// Include and exclude globs over paths relative to a watched path. A pattern without a `/`
+    /// matches any one component, so `*.php` is any PHP file and `cache` anything in a `cache`
+    /// directory.
+    struct Filter {
+        include: Vec<Vec<char>>,
+        exclude: Vec<Vec<char>>,
+    }
+
+    impl Filter {
+        fn new(config: &WatchConfig) -> Filter {
+            let chars =
+                |patterns: &[String]| patterns.iter().map(|p| p.chars().collect()).collect();
+            Filter {
+                include: chars(&config.include),
+                exclude: chars(&config.exclude),
+            }
+        }
+
+        fn pattern_matches(pattern: &[char], relative: &Path) -> bool {
+            let path: Vec<char> = relative
+                .to_string_lossy()
+                .replace('\\', "/")
+                .chars()
+                .collect();
+            if pattern.contains(&'/') {
+                return glob_match(pattern, &path);
+            }
+            relative.components().any(|component| match component {
+                Component::Normal(name) => {
+                    let name: Vec<char> = name.to_string_lossy().chars().collect();
+                    glob_match(pattern, &name)
+                }
+                _ => false,
+            })
+        }
+
+        fn is_excluded(&self, relative: &Path) -> bool {
+            self.exclude
+                .iter()
+                .any(|pattern| Filter::pattern_matches(pattern, relative))
+        }
+
+        fn matches(&self, relative: &Path) -> bool {
+            (self.include.is_empty()
+                || self
+                    .include
+                    .iter()
+                    .any(|pattern| Filter::pattern_matches(pattern, relative)))
+                && !self.is_excluded(relative)
+        }
+    }
+
+    /// Watches the paths of one process, relative ones under its `cwd`.
+    pub struct FileWatcher {
+        roots: Vec<PathBuf>,
+        filter: Filter,
+        debounce: std::time::Duration,
+        inotify: Inotify,
+        /// Changed files since the last restart, the first few of them.
+        changed: Vec<PathBuf>,
+        last_change: Option<Instant>,
+    }
+
+    impl FileWatcher {
+        pub fn new(config: &WatchConfig, cwd: &Path) -> io::Result<FileWatcher> {
+            let roots: Vec<PathBuf> = config.paths.iter().map(|path| cwd.join(path)).collect();
+            let filter = Filter::new(config);
+            let mut inotify = Inotify::new()?;
+            for root in &roots {
+                inotify.add_root(root, &filter)?;
+            }
+            Ok(FileWatcher {
+                roots,
+                filter,
+                debounce: config.debounce.0,
+                inotify,
+                changed: vec![],
+                last_change: None,
+            })
+        }
+
+        /// Whether a changed file counts, relative to the watched path it's under.
+        fn is_relevant(&self, path: &Path) -> bool {
+            self.roots.iter().any(|root| match path.strip_prefix(root) {
+                // The watched path is the file itself
+                Ok(relative) if relative.as_os_str().is_empty() => root
+                    .file_name()
+                    .is_some_and(|name| self.filter.matches(Path::new(name))),
+                Ok(relative) => self.filter.matches(relative),
+                Err(_) => false,
+            })
+        }
+
+        /// Collects changes, returning what changed once nothing has for `debounce`.
+        pub fn poll(&mut self) -> Option<String> {
+            self.poll_at(Instant::now())
+        }
+
+        fn poll_at(&mut self, now: Instant) -> Option<String> {
+            for path in self.inotify.read_changes(&self.filter) {
+                if self.is_relevant(&path) {
+                    if self.changed.len() < 10 && !self.changed.contains(&path) {
+                        self.changed.push(path);
+                    }
+                    self.last_change = Some(now);
+                }
+            }
+
+            match self.last_change {
+                Some(last) if now.duration_since(last) >= self.debounce => {
+                    self.last_change = None;
+                    let changed = std::mem::take(&mut self.changed);
+                    Some(match changed.len() {
+                        1 => format!("{} changed", changed[0].display()),
+                        n => format!("{} and {} more changed", changed[0].display(), n - 1),
+                    })
+                }
+                _ => None,
+            }
+        }
+    }
+
+    const MASK: u32 = libc::IN_CREATE
+        | libc::IN_MODIFY
+        | libc::IN_ATTRIB
+        | libc::IN_CLOSE_WRITE
+        | libc::IN_DELETE
+        | libc::IN_MOVED_FROM
+        | libc::IN_MOVED_TO;
+
+    struct Inotify {
+        fd: OwnedFd,
+        /// Watched directories by watch descriptor.
+        dirs: HashMap<i32, PathBuf>,
+        /// Directories watched for the sake of the watched path under them, to filter on.
+        roots: Vec<PathBuf>,
+    }
+
+    impl Inotify {
+        fn new() -> io::Result<Inotify> {
+            let fd = unsafe { libc::inotify_init1(libc::IN_NONBLOCK | libc::IN_CLOEXEC) };
+            if fd < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(Inotify {
+                fd: unsafe { OwnedFd::from_raw_fd(fd) },
+                dirs: HashMap::new(),
+                roots: vec![],
+            })
+        }
+
+        fn add_dir(&mut self, dir: &Path) -> io::Result<()> {
+            let path = CString::new(dir.as_os_str().as_bytes())?;
+            let wd = unsafe { libc::inotify_add_watch(self.fd.as_raw_fd(), path.as_ptr(), MASK) };
+            if wd < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            self.dirs.insert(wd, dir.to_path_buf());
+            Ok(())
+        }
+
+        /// Watches `dir` and the directories under it that aren't excluded.
+        fn add_tree(&mut self, root: &Path, dir: &Path, filter: &Filter) -> io::Result<()> {
+            self.add_dir(dir)?;
+            for entry in std::fs::read_dir(dir)?.flatten() {
+                let path = entry.path();
+                let relative = path.strip_prefix(root).unwrap_or(&path);
+                if entry.file_type().is_ok_and(|t| t.is_dir()) && !filter.is_excluded(relative) {
+                    // It may be gone already
+                    if let Err(err) = self.add_tree(root, &path, filter) {
+                        log!("Can't watch {}: {:?}", path.display(), err);
+                    }
+                }
+            }
+            Ok(())
+        }
+
+        /// A directory is watched with everything under it, a file through its directory
+        /// so that it's still seen when an editor replaces it.
+        fn add_root(&mut self, root: &Path, filter: &Filter) -> io::Result<()> {
+            let with_path = |err: io::Error| {
+                io::Error::new(
+                    err.kind(),
+                    format!("can't watch {}: {}", root.display(), err),
+                )
+            };
+            if root.is_dir() {
+                self.roots.push(root.to_path_buf());
+                self.add_tree(root, root, filter).map_err(with_path)
+            } else {
+                let parent = root
+                    .parent()
+                    .filter(|p| !p.as_os_str().is_empty())
+                    .unwrap_or(Path::new("."));
+                self.add_dir(parent).map_err(with_path)
+            }
+        }
+
+        /// Paths of the changes so far, watching directories created meanwhile too.
+        fn read_changes(&mut self, filter: &Filter) -> Vec<PathBuf> {
+            let mut changes = vec![];
+            // Aligned for `inotify_event`
+            let mut buffer = [0u64; 512];
+            loop {
+                let read = unsafe {
+                    libc::read(
+                        self.fd.as_raw_fd(),
+                        buffer.as_mut_ptr().cast(),
+                        std::mem::size_of_val(&buffer),
+                    )
+                };
+                if read <= 0 {
+                    // EAGAIN: nothing more for now
+                    return changes;
+                }
+                let bytes = unsafe {
+                    std::slice::from_raw_parts(buffer.as_ptr().cast::<u8>(), read as usize)
+                };
+
+                let mut offset = 0;
+                while offset + std::mem::size_of::<libc::inotify_event>() <= bytes.len() {
+                    let event: libc::inotify_event =
+                        unsafe { std::ptr::read_unaligned(bytes[offset..].as_ptr().cast()) };
+                    let name_start = offset + std::mem::size_of::<libc::inotify_event>();
+                    let name = &bytes[name_start..name_start + event.len as usize];
+                    let name =
+                        OsStr::from_bytes(name.split(|b| *b == 0).next().unwrap_or_default());
+                    offset = name_start + event.len as usize;
+
+                    if event.mask & libc::IN_Q_OVERFLOW != 0 {
+                        log!("Too many file changes to tell them apart");
+                        changes.extend(self.roots.first().cloned());
+                        continue;
+                    }
+                    if event.mask & libc::IN_IGNORED != 0 {
+                        self.dirs.remove(&event.wd);
+                        continue;
+                    }
+                    let dir = match self.dirs.get(&event.wd) {
+                        Some(dir) => dir.clone(),
+                        None => continue,
+                    };
+                    let path = dir.join(name);
+                    let created = libc::IN_CREATE | libc::IN_MOVED_TO;
+                    if event.mask & libc::IN_ISDIR != 0 && event.mask & created != 0 {
+                        let root = self
+                            .roots
+                            .iter()
+                            .find(|root| path.starts_with(root))
+                            .cloned();
+                        if let Some(root) = root {
+                            let relative = path.strip_prefix(&root).unwrap_or(&path);
+                            if !filter.is_excluded(relative) {
+                                if let Err(err) = self.add_tree(&root, &path, filter) {
+                                    log!("Can't watch {}: {:?}", path.display(), err);
+                                }
+                            }
+                        }
+                    }
+                    changes.push(path);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_glob() {
+        let matches = |pattern: &str, path: &str| {
+            glob_match(
+                &pattern.chars().collect::<Vec<_>>(),
+                &path.chars().collect::<Vec<_>>(),
+            )
+        };
+        assert!(matches("*.php", "index.php"));
+        assert!(!matches("*.php", "lib/index.php"));
+        assert!(matches("**/*.php", "index.php"));
+        assert!(matches("**/*.php", "lib/a/index.php"));
+        assert!(matches("lib/**", "lib/a/index.php"));
+        assert!(matches("conf/?.ini", "conf/a.ini"));
+        assert!(!matches("conf/?.ini", "conf/ab.ini"));
+        assert!(matches("[a-c]x[!0-9]", "bxy"));
+        assert!(!matches("[a-c]x[!0-9]", "bx1"));
+        assert!(matches("a[b", "a[b"));
+
+        let filter = Filter::new(&WatchConfig {
+            paths: vec![],
+            include: vec!["*.php".to_string(), "conf/*.ini".to_string()],
+            exclude: vec!["cache".to_string(), "*.tmp.php".to_string()],
+            debounce: Default::default(),
+        });
+        assert!(filter.matches(Path::new("lib/index.php")));
+        assert!(filter.matches(Path::new("conf/php.ini")));
+        assert!(!filter.matches(Path::new("php.ini")));
+        assert!(!filter.matches(Path::new("cache/views/page.php")));
+        assert!(!filter.matches(Path::new("edit.tmp.php")));
+    }
+
+    #[test]
+    fn test_watch() {
+        use crate::units::HumanDuration;
+        use std::fs;
+        use std::time::Duration;
+
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir(dir.path().join("cache")).unwrap();
+        fs::write(dir.path().join("php.ini"), "").unwrap();
+        let config = WatchConfig {
+            paths: vec!["src".to_string(), "php.ini".to_string()],
+            include: vec!["*.php".to_string(), "*.ini".to_string()],
+            exclude: vec!["cache".to_string()],
+            debounce: HumanDuration(Duration::from_millis(100)),
+        };
+        fs::create_dir(dir.path().join("src")).unwrap();
+        let mut watcher = FileWatcher::new(&config, dir.path()).unwrap();
+        let start = Instant::now();
+        let at = |millis: u64| start + Duration::from_millis(millis);
+
+        fs::write(dir.path().join("src/notes.txt"), "").unwrap();
+        fs::write(dir.path().join("cache.php"), "").unwrap();
+        fs::create_dir_all(dir.path().join("src/cache")).unwrap();
+        fs::write(dir.path().join("src/cache/page.php"), "").unwrap();
+        assert_eq!(watcher.poll_at(at(0)), None);
+        assert_eq!(watcher.poll_at(at(500)), None);
+
+        // Created after the watch started
+        fs::create_dir(dir.path().join("src/lib")).unwrap();
+        assert_eq!(watcher.poll_at(at(600)), None);
+        fs::write(dir.path().join("src/lib/index.php"), "").unwrap();
+        assert_eq!(watcher.poll_at(at(650)), None);
+        let reason = watcher.poll_at(at(800)).unwrap();
+        assert!(reason.ends_with("src/lib/index.php changed"), "{}", reason);
+
+        // Touched
+        let ini = fs::File::options()
+            .write(true)
+            .open(dir.path().join("php.ini"))
+            .unwrap();
+        ini.set_modified(std::time::SystemTime::now()).unwrap();
+        assert_eq!(watcher.poll_at(at(900)), None);
+        assert!(watcher
+            .poll_at(at(1000))
+            .unwrap()
+            .ends_with("php.ini changed"));
+    }
+}
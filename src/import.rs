@@ -0,0 +1,578 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use serde_json::Value;
+
+use crate::proc_config::{
+    ExitMatch, ProcessConfig, ProcessConfigState, RestartPolicy, INSTANCE_PLACEHOLDER,
+};
+use crate::units::HumanDuration;
+
+/// Formats `servicers import` understands.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum ImportFormat {
+    /// supervisord `.ini` / `.conf` with `[program:x]` sections
+    Supervisord,
+    /// Heroku-style `Procfile`
+    Procfile,
+    /// pm2 `ecosystem.config.json`
+    Pm2,
+}
+
+/// Converted processes plus everything that had no servicers equivalent.
+#[derive(Debug, Default)]
+pub struct Import {
+    pub processes: Vec<ProcessConfig>,
+    pub warnings: Vec<String>,
+}
+
+pub fn import(format: ImportFormat, text: &str, file_path: &Path) -> Result<Import, String> {
+    // Relative paths in all three formats are relative to the file itself.
+    let dir = file_path
+        .parent()
+        .filter(|dir| !dir.as_os_str().is_empty())
+        .unwrap_or(Path::new("."))
+        .to_string_lossy()
+        .into_owned();
+
+    match format {
+        ImportFormat::Supervisord => import_supervisord(text, &dir),
+        ImportFormat::Procfile => import_procfile(text, &dir),
+        ImportFormat::Pm2 => import_pm2(text, &dir),
+    }
+}
+
+fn new_process(name: &str, command: Vec<String>, cwd: &str) -> ProcessConfig {
+    let mut words = command.into_iter();
+    ProcessConfig {
+        name: name.to_string(),
+        program: words.next().unwrap_or_default(),
+        args: words.collect(),
+        cwd: cwd.to_string(),
+        ..Default::default()
+    }
+}
+
+/// Splits a command line the way `sh` would, minus expansions.
+pub fn split_command(line: &str) -> Result<Vec<String>, String> {
+    let mut words = vec![];
+    let mut word = String::new();
+    let mut in_word = false;
+    let mut chars = line.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\'' => {
+                in_word = true;
+                loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some(c) => word.push(c),
+                        None => return Err(format!("unterminated quote in {:?}", line)),
+                    }
+                }
+            }
+            '"' => {
+                in_word = true;
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some(c @ ('"' | '\\' | '$' | '`')) => word.push(c),
+                            Some(c) => {
+                                word.push('\\');
+                                word.push(c);
+                            }
+                            None => return Err(format!("unterminated quote in {:?}", line)),
+                        },
+                        Some(c) => word.push(c),
+                        None => return Err(format!("unterminated quote in {:?}", line)),
+                    }
+                }
+            }
+            '\\' => {
+                in_word = true;
+                if let Some(c) = chars.next() {
+                    word.push(c);
+                }
+            }
+            c if c.is_whitespace() => {
+                if in_word {
+                    words.push(std::mem::take(&mut word));
+                    in_word = false;
+                }
+            }
+            c => {
+                in_word = true;
+                word.push(c);
+            }
+        }
+    }
+    if in_word {
+        words.push(word);
+    }
+    Ok(words)
+}
+
+/// Commands using pipes, redirects or variables need a shell to run.
+fn needs_shell(command: &str) -> bool {
+    command.contains(['$', '|', '&', ';', '<', '>', '`', '*', '?'])
+}
+
+fn shell_command(command: &str) -> Result<Vec<String>, String> {
+    if needs_shell(command) {
+        Ok(vec![
+            "sh".to_string(),
+            "-c".to_string(),
+            command.to_string(),
+        ])
+    } else {
+        split_command(command)
+    }
+}
+
+fn import_supervisord(text: &str, dir: &str) -> Result<Import, String> {
+    let mut result = Import::default();
+
+    for (section, options) in parse_ini(text)? {
+        let name = match section.strip_prefix("program:") {
+            Some(name) => name.trim().to_string(),
+            None => {
+                result
+                    .warnings
+                    .push(format!("[{}]: section skipped", section));
+                continue;
+            }
+        };
+
+        let expand = |value: &str| -> Result<String, String> {
+            let mut value = value
+                .replace("%(program_name)s", &name)
+                .replace("%(here)s", dir);
+            // %(process_num)d, %(process_num)02d...
+            while let Some(start) = value.find("%(process_num)") {
+                let rest = &value[start + "%(process_num)".len()..];
+                let digits =
+                    rest.len() - rest.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+                let spec = match rest[digits..].starts_with(['d', 's']) {
+                    true => digits + 1,
+                    false => 0,
+                };
+                let placeholder = match &rest[..digits] {
+                    "" => INSTANCE_PLACEHOLDER.to_string(),
+                    width if width.starts_with('0') => format!("{{instance:{}}}", width),
+                    width => {
+                        return Err(format!(
+                            "[{}]: %(process_num){}d pads with spaces, only zeros are supported",
+                            section, width
+                        ))
+                    }
+                };
+                value.replace_range(start..start + "%(process_num)".len() + spec, &placeholder);
+            }
+            Ok(value)
+        };
+
+        let mut process = new_process(&name, vec![], dir);
+        // supervisord's default for autorestart
+        process.restart = RestartPolicy::OnFailure;
+        let mut warn = |key: &str, message: &str| {
+            result
+                .warnings
+                .push(format!("[{}] {}: {}", section, key, message));
+        };
+
+        for (key, value) in options {
+            match key.as_str() {
+                "command" => {
+                    let mut words = shell_command(&expand(&value)?)?.into_iter();
+                    process.program = words.next().unwrap_or_default();
+                    process.args = words.collect();
+                }
+                "directory" => process.cwd = expand(&value)?,
+                "environment" => process.env.extend(parse_environment(&expand(&value)?)?),
+                "autostart" => {
+                    if value.eq_ignore_ascii_case("false") {
+                        process.state = ProcessConfigState::Disabled;
+                    }
+                }
+                "autorestart" => {
+                    process.restart = match value.to_ascii_lowercase().as_str() {
+                        "true" => RestartPolicy::Always,
+                        "false" => RestartPolicy::Never,
+                        "unexpected" => RestartPolicy::OnFailure,
+                        _ => {
+                            return Err(format!(
+                                "[{}] autorestart: invalid value {:?}",
+                                section, value
+                            ))
+                        }
+                    }
+                }
+                "stopsignal" => process.stop_signal = value.clone(),
+                "stopwaitsecs" => {
+                    process.stop_timeout =
+                        HumanDuration::from_secs(value.parse().map_err(|_| {
+                            format!("[{}] stopwaitsecs: not a number: {:?}", section, value)
+                        })?)
+                }
+                "numprocs" => {
+                    process.numprocs = value
+                        .parse()
+                        .map_err(|_| format!("[{}] numprocs: not a number: {:?}", section, value))?
+                }
+                "process_name" => {
+                    if value != "%(program_name)s" {
+                        warn(&key, "instances are always named name:N");
+                    }
+                }
+                "exitcodes" => {
+                    process.success_exit_codes = value
+                        .split(',')
+                        .map(|code| code.trim().parse().map(ExitMatch::Code))
+                        .collect::<Result<_, _>>()
+                        .map_err(|_| {
+                            format!(
+                                "[{}] exitcodes: not a list of numbers: {:?}",
+                                section, value
+                            )
+                        })?
+                }
+                _ => warn(&key, "not supported"),
+            }
+        }
+
+        if process.program.is_empty() {
+            return Err(format!("[{}]: missing command", section));
+        }
+        result.processes.push(process);
+    }
+
+    Ok(result)
+}
+
+type IniSection = (String, Vec<(String, String)>);
+
+/// Sections in order, each with its `key=value` pairs. Indented lines continue the
+/// previous value, `;` and `#` start comments.
+fn parse_ini(text: &str) -> Result<Vec<IniSection>, String> {
+    let mut sections: Vec<IniSection> = vec![];
+
+    for (number, raw) in text.lines().enumerate() {
+        let line = match raw.find(" ;") {
+            Some(comment) => &raw[..comment],
+            None => raw,
+        };
+        if line.trim().is_empty() || line.trim_start().starts_with([';', '#']) {
+            continue;
+        }
+
+        if raw.starts_with([' ', '\t']) {
+            if let Some((_, value)) = sections
+                .last_mut()
+                .and_then(|(_, options)| options.last_mut())
+            {
+                value.push(' ');
+                value.push_str(line.trim());
+                continue;
+            }
+        }
+
+        let line = line.trim();
+        if let Some(section) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+            sections.push((section.trim().to_string(), vec![]));
+            continue;
+        }
+
+        let (key, value) = line
+            .split_once(['=', ':'])
+            .ok_or_else(|| format!("line {}: expected key=value", number + 1))?;
+        let (_, options) = sections
+            .last_mut()
+            .ok_or_else(|| format!("line {}: option outside of a section", number + 1))?;
+        options.push((key.trim().to_ascii_lowercase(), value.trim().to_string()));
+    }
+
+    Ok(sections)
+}
+
+/// `KEY="value",KEY2=value2`
+fn parse_environment(value: &str) -> Result<BTreeMap<String, String>, String> {
+    let mut env = BTreeMap::new();
+    let mut rest = value.trim();
+
+    while !rest.is_empty() {
+        let (key, tail) = rest
+            .split_once('=')
+            .ok_or_else(|| format!("environment: expected KEY=value in {:?}", rest))?;
+        let tail = tail.trim_start();
+        let (value, tail) = match tail.chars().next() {
+            Some(quote @ ('"' | '\'')) => {
+                let end = tail[1..]
+                    .find(quote)
+                    .ok_or_else(|| format!("environment: unterminated quote in {:?}", tail))?;
+                (&tail[1..end + 1], &tail[end + 2..])
+            }
+            _ => match tail.find(',') {
+                Some(comma) => (&tail[..comma], &tail[comma..]),
+                None => (tail, ""),
+            },
+        };
+        env.insert(key.trim().to_string(), value.to_string());
+        rest = tail.trim_start().trim_start_matches(',').trim_start();
+    }
+
+    Ok(env)
+}
+
+fn import_procfile(text: &str, dir: &str) -> Result<Import, String> {
+    let mut result = Import::default();
+
+    for (number, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (name, command) = line
+            .split_once(':')
+            .ok_or_else(|| format!("line {}: expected `name: command`", number + 1))?;
+        let (name, command) = (name.trim(), command.trim());
+
+        if command.contains("$PORT") {
+            result.warnings.push(format!(
+                "{}: $PORT is not assigned by servicers, set it in env",
+                name
+            ));
+        }
+        result
+            .processes
+            .push(new_process(name, shell_command(command)?, dir));
+    }
+
+    Ok(result)
+}
+
+/// Interpreters pm2 picks by script extension.
+fn pm2_interpreter(script: &str) -> Option<&'static str> {
+    match Path::new(script).extension()?.to_str()? {
+        "js" | "mjs" | "cjs" => Some("node"),
+        "py" => Some("python3"),
+        "sh" => Some("bash"),
+        "rb" => Some("ruby"),
+        "php" => Some("php"),
+        _ => None,
+    }
+}
+
+fn value_to_string(value: &Value) -> String {
+    match value {
+        Value::String(text) => text.clone(),
+        other => other.to_string(),
+    }
+}
+
+fn import_pm2(text: &str, dir: &str) -> Result<Import, String> {
+    let mut result = Import::default();
+
+    let document: Value = serde_json::from_str(text).map_err(|err| err.to_string())?;
+    let apps = match &document {
+        Value::Array(apps) => apps,
+        Value::Object(map) => match map.get("apps") {
+            Some(Value::Array(apps)) => apps,
+            _ => return Err("expected an `apps` list".to_string()),
+        },
+        _ => return Err("expected an `apps` list".to_string()),
+    };
+
+    for (index, app) in apps.iter().enumerate() {
+        let app = app
+            .as_object()
+            .ok_or_else(|| format!("apps[{}]: expected an object", index))?;
+        let script = app
+            .get("script")
+            .and_then(Value::as_str)
+            .ok_or_else(|| format!("apps[{}]: missing script", index))?;
+        let name = app
+            .get("name")
+            .and_then(Value::as_str)
+            .map(str::to_string)
+            .unwrap_or_else(|| ProcessConfig::_new(script.to_string(), vec![], String::new()).name);
+
+        let mut command = vec![];
+        let interpreter = match app.get("interpreter").and_then(Value::as_str) {
+            Some("none") => None,
+            Some(interpreter) => Some(interpreter),
+            None => pm2_interpreter(script),
+        };
+        if let Some(interpreter) = interpreter {
+            command.push(interpreter.to_string());
+            match app.get("interpreter_args") {
+                Some(Value::String(args)) => command.extend(split_command(args)?),
+                Some(Value::Array(args)) => command.extend(args.iter().map(value_to_string)),
+                _ => (),
+            }
+        }
+        command.push(script.to_string());
+        match app.get("args") {
+            Some(Value::String(args)) => command.extend(split_command(args)?),
+            Some(Value::Array(args)) => command.extend(args.iter().map(value_to_string)),
+            _ => (),
+        }
+
+        let cwd = app.get("cwd").and_then(Value::as_str).unwrap_or(dir);
+        let mut process = new_process(&name, command, cwd);
+        // pm2 stops with SIGINT and kills after 1.6s
+        process.stop_signal = "INT".to_string();
+        process.stop_timeout = HumanDuration::from_millis(1600);
+
+        for (key, value) in app {
+            let mut warn = |message: &str| {
+                result
+                    .warnings
+                    .push(format!("apps[{}] ({}) {}: {}", index, name, key, message));
+            };
+            match key.as_str() {
+                "name" | "script" | "args" | "cwd" | "interpreter" | "interpreter_args" => (),
+                "env" => {
+                    if let Some(env) = value.as_object() {
+                        for (key, value) in env {
+                            process.env.insert(key.clone(), value_to_string(value));
+                        }
+                    }
+                }
+                "autorestart" => {
+                    if value == &Value::Bool(false) {
+                        process.restart = RestartPolicy::Never;
+                    }
+                }
+                "kill_timeout" => match value.as_u64() {
+                    Some(millis) => process.stop_timeout = HumanDuration::from_millis(millis),
+                    None => warn("expected milliseconds"),
+                },
+                "restart_delay" => match value.as_u64() {
+                    Some(millis) => process.restart_delay = HumanDuration::from_millis(millis),
+                    None => warn("expected milliseconds"),
+                },
+                "instances" => match value.as_u64() {
+                    Some(count) if count > 0 => process.numprocs = count as u32,
+                    _ => warn("only a fixed number of instances is supported"),
+                },
+                "exec_mode" => {
+                    if value.as_str() == Some("cluster") || value.as_str() == Some("cluster_mode") {
+                        warn("cluster mode runs separate instances without a shared port");
+                    }
+                }
+                _ => warn("not supported"),
+            }
+        }
+
+        result.processes.push(process);
+    }
+
+    Ok(result)
+}
+
+#[test]
+fn test_supervisord() {
+    let text = r#"
+[supervisord]
+logfile=/var/log/supervisord.log
+
+; php workers
+[program:php]
+command=/usr/bin/php-cgi -b "127.0.0.1:90%(process_num)02d"
+directory=/srv/www
+environment=APP_ENV="prod",
+    DEBUG=0
+autorestart=unexpected
+exitcodes=0,2
+stopsignal=QUIT
+stopwaitsecs=30
+numprocs=4
+process_name=%(program_name)s_%(process_num)02d
+startretries=5
+"#;
+    let result = import(
+        ImportFormat::Supervisord,
+        text,
+        Path::new("/etc/supervisor/php.conf"),
+    )
+    .unwrap();
+    let php = &result.processes[0];
+
+    assert_eq!(php.program, "/usr/bin/php-cgi");
+    assert_eq!(php.args, ["-b", "127.0.0.1:90{instance:02}"]);
+    assert_eq!(php.instances()[0].args[1], "127.0.0.1:9000");
+    assert_eq!(php.cwd, "/srv/www");
+    assert_eq!(php.env["APP_ENV"], "prod");
+    assert_eq!(php.env["DEBUG"], "0");
+    assert_eq!(php.restart, RestartPolicy::OnFailure);
+    assert_eq!(
+        php.success_exit_codes,
+        [ExitMatch::Code(0), ExitMatch::Code(2)]
+    );
+    assert_eq!(php.stop_signal, "QUIT");
+    assert_eq!(php.stop_timeout, HumanDuration::from_secs(30));
+    assert_eq!(php.numprocs, 4);
+    assert_eq!(result.warnings.len(), 3, "{:?}", result.warnings);
+}
+
+#[test]
+fn test_supervisord_defaults() {
+    let text = "[program:worker]\ncommand=/usr/bin/php artisan queue:work\n";
+    let result = import(
+        ImportFormat::Supervisord,
+        text,
+        Path::new("/etc/supervisor/worker.conf"),
+    )
+    .unwrap();
+    assert_eq!(result.processes[0].restart, RestartPolicy::OnFailure);
+
+    let text = "[program:php]\ncommand=/usr/bin/php-cgi -b 127.0.0.1:90%(process_num)2d\n";
+    let err = import(
+        ImportFormat::Supervisord,
+        text,
+        Path::new("/etc/supervisor/php.conf"),
+    )
+    .unwrap_err();
+    assert!(err.contains("pads with spaces"), "{}", err);
+}
+
+#[test]
+fn test_procfile() {
+    let text = "web: bundle exec puma -p $PORT\nworker: php artisan queue:work --tries=3\n";
+    let result = import(ImportFormat::Procfile, text, Path::new("/app/Procfile")).unwrap();
+
+    assert_eq!(result.processes[0].program, "sh");
+    assert_eq!(result.processes[1].name, "worker");
+    assert_eq!(
+        result.processes[1].args,
+        ["artisan", "queue:work", "--tries=3"]
+    );
+    assert_eq!(result.processes[1].cwd, "/app");
+    assert_eq!(result.warnings.len(), 1);
+}
+
+#[test]
+fn test_pm2() {
+    let text = r#"{"apps": [{
+        "name": "api",
+        "script": "server.js",
+        "args": "--port 3000",
+        "instances": 2,
+        "env": {"NODE_ENV": "production", "WORKERS": 4},
+        "kill_timeout": 5000,
+        "max_memory_restart": "300M"
+    }]}"#;
+    let result = import(ImportFormat::Pm2, text, Path::new("ecosystem.config.json")).unwrap();
+    let api = &result.processes[0];
+
+    assert_eq!(api.program, "node");
+    assert_eq!(api.args, ["server.js", "--port", "3000"]);
+    assert_eq!(api.cwd, ".");
+    assert_eq!(api.env["WORKERS"], "4");
+    assert_eq!(api.numprocs, 2);
+    assert_eq!(api.stop_signal, "INT");
+    assert_eq!(api.stop_timeout, HumanDuration::from_secs(5));
+    assert_eq!(result.warnings.len(), 1);
+}
@@ -0,0 +1,421 @@
+use std::ffi::OsString;
+use std::fmt::Write;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::cli::{InitSystem, Restart};
+use crate::export::{escape_specifiers, escape_word};
+use crate::logger::log;
+
+const DESCRIPTION: &str = "servicers process supervisor";
+
+/// What to install: `<exe> <options> run` as `user` in `workdir`, under `root`.
+#[derive(Debug)]
+pub struct InitService {
+    pub init: InitSystem,
+    pub root: PathBuf,
+    pub exe: PathBuf,
+    /// Global options for `servicers run`, like `--config`.
+    pub options: Vec<OsString>,
+    pub user: Option<String>,
+    pub restart: Restart,
+    pub workdir: Option<PathBuf>,
+}
+
+impl InitSystem {
+    /// Picks what is running on the system at `root`.
+    pub fn detect(root: &Path) -> InitSystem {
+        if root.join("run/systemd/system").is_dir() {
+            InitSystem::Systemd
+        } else if root.join("sbin/openrc-run").exists() {
+            InitSystem::Openrc
+        } else {
+            InitSystem::Sysvinit
+        }
+    }
+}
+
+/// Joins `root` with an absolute system path.
+fn under_root(root: &Path, path: &str) -> PathBuf {
+    root.join(path.trim_start_matches('/'))
+}
+
+/// Single-quotes a word for `sh`.
+fn shell_quote(word: &str) -> String {
+    match !word.is_empty()
+        && word
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_./:=@,+".contains(c))
+    {
+        true => word.to_string(),
+        false => format!("'{}'", word.replace('\'', "'\\''")),
+    }
+}
+
+impl InitService {
+    pub fn script_path(&self) -> PathBuf {
+        match self.init {
+            InitSystem::Systemd => under_root(
+                &self.root,
+                &format!("/etc/systemd/system/{}.service", crate::SERVICE_NAME),
+            ),
+            InitSystem::Openrc | InitSystem::Sysvinit => {
+                under_root(&self.root, &format!("/etc/init.d/{}", crate::SERVICE_NAME))
+            }
+        }
+    }
+
+    fn run_args(&self) -> Vec<String> {
+        self.options
+            .iter()
+            .map(|option| option.to_string_lossy().into_owned())
+            .chain(std::iter::once("run".to_string()))
+            .collect()
+    }
+
+    pub fn render(&self) -> String {
+        match self.init {
+            InitSystem::Systemd => self.render_systemd(),
+            InitSystem::Openrc => self.render_openrc(),
+            InitSystem::Sysvinit => self.render_sysvinit(),
+        }
+    }
+
+    fn render_systemd(&self) -> String {
+        let mut unit = String::new();
+        let command: Vec<String> = std::iter::once(self.exe.to_string_lossy().into_owned())
+            .chain(self.run_args())
+            .map(|word| escape_word(&word))
+            .collect();
+
+        writeln!(unit, "[Unit]").unwrap();
+        writeln!(unit, "Description={}", DESCRIPTION).unwrap();
+        writeln!(unit, "After=network.target").unwrap();
+        writeln!(unit).unwrap();
+        writeln!(unit, "[Service]").unwrap();
+        writeln!(unit, "Type=notify").unwrap();
+        writeln!(unit, "ExecStart={}", command.join(" ")).unwrap();
+        writeln!(unit, "ExecReload=/bin/kill -HUP $MAINPID").unwrap();
+        if let Some(workdir) = &self.workdir {
+            writeln!(
+                unit,
+                "WorkingDirectory={}",
+                escape_specifiers(&workdir.to_string_lossy())
+            )
+            .unwrap();
+        }
+        if let Some(user) = &self.user {
+            writeln!(unit, "User={}", escape_specifiers(user)).unwrap();
+        }
+        let restart = match self.restart {
+            Restart::Always => "always",
+            Restart::OnFailure => "on-failure",
+            Restart::No => "no",
+        };
+        writeln!(unit, "Restart={}", restart).unwrap();
+        writeln!(unit).unwrap();
+        writeln!(unit, "[Install]").unwrap();
+        writeln!(unit, "WantedBy=multi-user.target").unwrap();
+        unit
+    }
+
+    fn render_openrc(&self) -> String {
+        let args: Vec<String> = self.run_args().iter().map(|arg| shell_quote(arg)).collect();
+        let mut script = String::new();
+
+        writeln!(script, "#!/sbin/openrc-run").unwrap();
+        writeln!(script).unwrap();
+        writeln!(script, "description=\"{}\"", DESCRIPTION).unwrap();
+        writeln!(
+            script,
+            "command={}",
+            shell_quote(&self.exe.to_string_lossy())
+        )
+        .unwrap();
+        writeln!(
+            script,
+            "command_args=\"{}\"",
+            args.join(" ").replace('"', "\\\"")
+        )
+        .unwrap();
+        match self.restart {
+            // supervise-daemon restarts the supervisor whenever it exits
+            Restart::Always | Restart::OnFailure => {
+                writeln!(script, "supervisor=supervise-daemon").unwrap()
+            }
+            Restart::No => {
+                writeln!(script, "command_background=true").unwrap();
+                writeln!(script, "pidfile=\"/run/${{RC_SVCNAME}}.pid\"").unwrap();
+            }
+        }
+        if let Some(user) = &self.user {
+            writeln!(script, "command_user={}", shell_quote(user)).unwrap();
+        }
+        if let Some(workdir) = &self.workdir {
+            writeln!(
+                script,
+                "directory={}",
+                shell_quote(&workdir.to_string_lossy())
+            )
+            .unwrap();
+        }
+        writeln!(script).unwrap();
+        writeln!(script, "depend() {{").unwrap();
+        writeln!(script, "\tneed net").unwrap();
+        writeln!(script, "}}").unwrap();
+        script
+    }
+
+    fn render_sysvinit(&self) -> String {
+        let args: Vec<String> = self.run_args().iter().map(|arg| shell_quote(arg)).collect();
+        let mut options = vec![
+            "--background".to_string(),
+            "--make-pidfile".to_string(),
+            "--pidfile \"$PIDFILE\"".to_string(),
+        ];
+        if let Some(user) = &self.user {
+            options.push(format!("--chuid {}", shell_quote(user)));
+        }
+        if let Some(workdir) = &self.workdir {
+            options.push(format!(
+                "--chdir {}",
+                shell_quote(&workdir.to_string_lossy())
+            ));
+        }
+
+        let mut script = String::new();
+        writeln!(script, "#!/bin/sh").unwrap();
+        writeln!(script, "### BEGIN INIT INFO").unwrap();
+        writeln!(script, "# Provides:          {}", crate::SERVICE_NAME).unwrap();
+        writeln!(script, "# Required-Start:    $network $remote_fs").unwrap();
+        writeln!(script, "# Required-Stop:     $network $remote_fs").unwrap();
+        writeln!(script, "# Default-Start:     2 3 4 5").unwrap();
+        writeln!(script, "# Default-Stop:      0 1 6").unwrap();
+        writeln!(script, "# Short-Description: {}", DESCRIPTION).unwrap();
+        writeln!(script, "### END INIT INFO").unwrap();
+        writeln!(script).unwrap();
+        writeln!(
+            script,
+            "DAEMON={}",
+            shell_quote(&self.exe.to_string_lossy())
+        )
+        .unwrap();
+        writeln!(script, "PIDFILE=/run/{}.pid", crate::SERVICE_NAME).unwrap();
+        writeln!(script).unwrap();
+        writeln!(script, "case \"$1\" in").unwrap();
+        writeln!(script, "  start)").unwrap();
+        writeln!(
+            script,
+            "    start-stop-daemon --start --quiet {} --exec \"$DAEMON\" -- {}",
+            options.join(" "),
+            args.join(" ")
+        )
+        .unwrap();
+        writeln!(script, "    ;;").unwrap();
+        writeln!(script, "  stop)").unwrap();
+        writeln!(
+            script,
+            "    start-stop-daemon --stop --quiet --retry TERM/30/KILL/5 --pidfile \"$PIDFILE\" && rm -f \"$PIDFILE\""
+        )
+        .unwrap();
+        writeln!(script, "    ;;").unwrap();
+        writeln!(script, "  restart)").unwrap();
+        writeln!(script, "    \"$0\" stop").unwrap();
+        writeln!(script, "    \"$0\" start").unwrap();
+        writeln!(script, "    ;;").unwrap();
+        writeln!(script, "  status)").unwrap();
+        writeln!(
+            script,
+            "    start-stop-daemon --status --pidfile \"$PIDFILE\" && echo running || {{ echo stopped; exit 3; }}"
+        )
+        .unwrap();
+        writeln!(script, "    ;;").unwrap();
+        writeln!(script, "  *)").unwrap();
+        writeln!(
+            script,
+            "    echo \"Usage: $0 {{start|stop|restart|status}}\""
+        )
+        .unwrap();
+        writeln!(script, "    exit 1").unwrap();
+        writeln!(script, "    ;;").unwrap();
+        writeln!(script, "esac").unwrap();
+        script
+    }
+
+    /// Touches the running system only when `root` is `/`.
+    fn is_live(&self) -> bool {
+        self.root == Path::new("/")
+    }
+
+    /// Command managing the installed service, e.g. `systemctl start servicers`.
+    fn control_command(&self, action: &str) -> Command {
+        let mut command = match self.init {
+            InitSystem::Systemd => Command::new("systemctl"),
+            InitSystem::Openrc => Command::new("rc-service"),
+            InitSystem::Sysvinit => Command::new(self.script_path()),
+        };
+        match self.init {
+            InitSystem::Systemd => command.arg(action).arg(crate::SERVICE_NAME),
+            InitSystem::Openrc => command.arg(crate::SERVICE_NAME).arg(action),
+            InitSystem::Sysvinit => command.arg(action),
+        };
+        command
+    }
+
+    fn daemon_reload(&self) -> io::Result<()> {
+        let mut command = Command::new("systemctl");
+        command.arg("daemon-reload");
+        self.run(command)
+    }
+
+    fn run(&self, mut command: Command) -> io::Result<()> {
+        log!("Running {:?}", &command);
+        let status = command.status()?;
+        match status.success() {
+            true => Ok(()),
+            false => Err(io::Error::other(format!(
+                "{:?} failed: {}",
+                command, status
+            ))),
+        }
+    }
+
+    pub fn install(&self) -> io::Result<()> {
+        let path = self.script_path();
+        if path.exists() {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("{} already exists, uninstall first", path.display()),
+            ));
+        }
+        std::fs::create_dir_all(path.parent().unwrap())?;
+        std::fs::write(&path, self.render())?;
+        if self.init != InitSystem::Systemd {
+            use std::os::unix::fs::PermissionsExt;
+            std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755))?;
+        }
+        log!("Installed {}", path.display());
+
+        if self.is_live() {
+            match self.init {
+                InitSystem::Systemd => {
+                    self.daemon_reload()?;
+                    let mut enable = Command::new("systemctl");
+                    enable.args(["enable", crate::SERVICE_NAME]);
+                    self.run(enable)?;
+                }
+                InitSystem::Openrc => {
+                    let mut enable = Command::new("rc-update");
+                    enable.args(["add", crate::SERVICE_NAME, "default"]);
+                    self.run(enable)?;
+                }
+                InitSystem::Sysvinit => {
+                    let mut enable = Command::new("update-rc.d");
+                    enable.args([crate::SERVICE_NAME, "defaults"]);
+                    self.run(enable)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn uninstall(&self) -> io::Result<()> {
+        let path = self.script_path();
+        if !path.exists() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("{} is not installed", path.display()),
+            ));
+        }
+
+        if self.is_live() {
+            // Already stopped is fine
+            self.run(self.control_command("stop")).ok();
+            let mut disable = match self.init {
+                InitSystem::Systemd => Command::new("systemctl"),
+                InitSystem::Openrc => Command::new("rc-update"),
+                InitSystem::Sysvinit => Command::new("update-rc.d"),
+            };
+            match self.init {
+                InitSystem::Systemd => disable.args(["disable", crate::SERVICE_NAME]),
+                InitSystem::Openrc => disable.args(["del", crate::SERVICE_NAME, "default"]),
+                InitSystem::Sysvinit => disable.args([crate::SERVICE_NAME, "remove"]),
+            };
+            self.run(disable).ok();
+        }
+
+        std::fs::remove_file(&path)?;
+        log!("Removed {}", path.display());
+
+        if self.is_live() && self.init == InitSystem::Systemd {
+            self.daemon_reload()?;
+        }
+        Ok(())
+    }
+
+    pub fn start(&self) -> io::Result<()> {
+        self.run(self.control_command("start"))
+    }
+
+    pub fn stop(&self) -> io::Result<()> {
+        self.run(self.control_command("stop"))
+    }
+
+    pub fn status(&self) -> io::Result<()> {
+        self.run(self.control_command("status"))
+    }
+}
+
+#[test]
+fn test_install() {
+    let root = tempfile::tempdir().unwrap();
+    let mut service = InitService {
+        init: InitSystem::detect(root.path()),
+        root: root.path().to_path_buf(),
+        exe: PathBuf::from("/usr/local/bin/servicers"),
+        options: vec!["--config".into(), "/etc/servicers/servicers.yaml".into()],
+        user: Some("www-data".to_string()),
+        restart: Restart::OnFailure,
+        workdir: Some(PathBuf::from("/var/lib/servicers")),
+    };
+    assert_eq!(service.init, InitSystem::Sysvinit);
+
+    std::fs::create_dir_all(root.path().join("run/systemd/system")).unwrap();
+    service.init = InitSystem::detect(root.path());
+    assert_eq!(service.init, InitSystem::Systemd);
+
+    service.install().unwrap();
+    let unit =
+        std::fs::read_to_string(root.path().join("etc/systemd/system/servicers.service")).unwrap();
+    assert!(unit.contains(
+        "ExecStart=/usr/local/bin/servicers --config /etc/servicers/servicers.yaml run\n"
+    ));
+    assert!(unit.contains("User=www-data\n"));
+    assert!(unit.contains("Restart=on-failure\n"));
+    assert!(service.install().is_err());
+    service.uninstall().unwrap();
+    assert!(!service.script_path().exists());
+
+    // Taken as is, apart from specifiers
+    let workdir = service.workdir.replace(PathBuf::from("/srv/$app 100%"));
+    service.install().unwrap();
+    let unit = std::fs::read_to_string(service.script_path()).unwrap();
+    assert!(unit.contains("WorkingDirectory=/srv/$app 100%%\n"));
+    service.uninstall().unwrap();
+    service.workdir = workdir;
+
+    service.init = InitSystem::Openrc;
+    service.install().unwrap();
+    let script = std::fs::read_to_string(root.path().join("etc/init.d/servicers")).unwrap();
+    assert!(script.starts_with("#!/sbin/openrc-run\n"));
+    assert!(script.contains("command_args=\"--config /etc/servicers/servicers.yaml run\"\n"));
+    assert!(script.contains("supervisor=supervise-daemon\n"));
+    service.uninstall().unwrap();
+
+    service.init = InitSystem::Sysvinit;
+    service.user = None;
+    service.install().unwrap();
+    let script = std::fs::read_to_string(service.script_path()).unwrap();
+    assert!(script.contains("--chdir /var/lib/servicers --exec \"$DAEMON\" -- --config /etc/servicers/servicers.yaml run\n"));
+}
@@ -0,0 +1,224 @@
+//! `servicers.journal`: process lifecycle events as JSON lines next to `servicers.log`.
+//! Unlike the log it survives restarts of the supervisor and is only trimmed by age and size.
+
+use chrono::{DateTime, TimeZone, Utc};
+use serde::{Deserialize, Serialize};
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::logger::{self, log};
+use crate::proc_config::ExitClass;
+use crate::units::HumanDuration;
+
+static WRITE_LOCK: Mutex<()> = Mutex::new(());
+static MAX_AGE_SECS: AtomicU64 = AtomicU64::new(30 * 24 * 3600);
+static MAX_SIZE: AtomicU64 = AtomicU64::new(1 << 20);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EventKind {
+    Started,
+    /// Its `ready` conditions hold; `uptime` is how long that took.
+    Ready,
+    SpawnFailed,
+    Exited,
+    Stopped,
+    /// Restarted by the supervisor, e.g. over a `watchdog` limit.
+    Restarted,
+    /// Restarted per `max_lifetime` or `restart_schedule`.
+    PlannedRestart,
+    Paused,
+    Resumed,
+    Adopted,
+    /// Sent a signal or ran one of its `actions`, named in `status`.
+    Signaled,
+    /// Progress of `servicers restart --rolling`, named after what was restarted.
+    RollingRestart,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Event {
+    pub time: DateTime<Utc>,
+    pub name: String,
+    pub event: EventKind,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pid: Option<u32>,
+    /// Exit status or spawn error, e.g. `exit status: 1`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status: Option<String>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub failed: bool,
+    /// What an exit means, per `success_exit_codes` and the like.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub class: Option<ExitClass>,
+    /// How long the process ran, for exits and stops.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub uptime: Option<HumanDuration>,
+    /// Crash report file written for an abnormal exit.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub report: Option<String>,
+}
+
+impl Event {
+    pub fn new(name: &str, event: EventKind) -> Event {
+        Event {
+            time: Utc::now(),
+            name: name.to_string(),
+            event,
+            pid: None,
+            status: None,
+            failed: false,
+            class: None,
+            uptime: None,
+            report: None,
+        }
+    }
+}
+
+pub fn journal_path() -> PathBuf {
+    logger::log_dir().join("servicers.journal")
+}
+
+/// Retention from the `journal` config section.
+pub fn set_retention(max_age: Duration, max_size: u64) {
+    MAX_AGE_SECS.store(max_age.as_secs(), Ordering::Relaxed);
+    MAX_SIZE.store(max_size, Ordering::Relaxed);
+}
+
+/// Appends `event`, trimming the journal once it outgrows `journal.max_size`.
+pub fn record(event: Event) {
+    let _lock = WRITE_LOCK.lock().unwrap();
+    let path = journal_path();
+    if let Err(err) = append(&path, &event) {
+        log!("Can't write {}: {:?}", path.display(), &err);
+    }
+}
+
+fn append(path: &Path, event: &Event) -> io::Result<()> {
+    let mut file = OpenOptions::new().append(true).create(true).open(path)?;
+    writeln!(file, "{}", serde_json::to_string(event)?)?;
+    if file.metadata()?.len() > MAX_SIZE.load(Ordering::Relaxed) {
+        drop(file);
+        trim(path)?;
+    }
+    Ok(())
+}
+
+/// Drops events past `journal.max_age`, then the oldest ones until the journal fits in
+/// three quarters of `journal.max_size`, so that it isn't rewritten on every event.
+pub fn compact() {
+    let _lock = WRITE_LOCK.lock().unwrap();
+    let path = journal_path();
+    if path.exists() {
+        if let Err(err) = trim(&path) {
+            log!("Can't trim {}: {:?}", path.display(), &err);
+        }
+    }
+}
+
+fn trim(path: &Path) -> io::Result<()> {
+    let max_age = chrono::Duration::seconds(MAX_AGE_SECS.load(Ordering::Relaxed) as i64);
+    let cutoff = Utc::now() - max_age;
+    let budget = MAX_SIZE.load(Ordering::Relaxed) / 4 * 3;
+
+    let text = std::fs::read_to_string(path)?;
+    // Newest first, to know which ones fit
+    let mut kept = vec![];
+    let mut size = 0;
+    for line in text.lines().rev() {
+        let event: Event = match serde_json::from_str(line) {
+            Ok(event) => event,
+            Err(_) => continue,
+        };
+        size += line.len() as u64 + 1;
+        if event.time < cutoff || size > budget {
+            break;
+        }
+        kept.push(line);
+    }
+    kept.reverse();
+
+    let temp = path.with_extension("journal.tmp");
+    let mut text = kept.join("\n");
+    if !text.is_empty() {
+        text.push('\n');
+    }
+    std::fs::write(&temp, text)?;
+    std::fs::rename(&temp, path)
+}
+
+/// Events for `name` (a process or group, all when `None`) since `since`, oldest first.
+pub fn read(
+    path: &Path,
+    name: Option<&str>,
+    since: Option<DateTime<Utc>>,
+) -> io::Result<Vec<Event>> {
+    let text = match std::fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+        Err(err) => return Err(err),
+    };
+    Ok(text
+        .lines()
+        .filter_map(|line| serde_json::from_str::<Event>(line).ok())
+        .filter(|event| name.is_none_or(|name| crate::control_socket::matches(name, &event.name)))
+        .filter(|event| since.is_none_or(|since| event.time >= since))
+        .collect())
+}
+
+/// Parses `--since`: an age like `7d` or `12h`, a date, or an RFC 3339 time.
+pub fn parse_since(text: &str) -> Result<DateTime<Utc>, String> {
+    if let Ok(age) = text.parse::<HumanDuration>() {
+        let age = chrono::Duration::from_std(age.0).map_err(|err| err.to_string())?;
+        return Ok(Utc::now() - age);
+    }
+    if let Ok(time) = DateTime::parse_from_rfc3339(text) {
+        return Ok(time.with_timezone(&Utc));
+    }
+    chrono::NaiveDate::parse_from_str(text, "%Y-%m-%d")
+        .map(|date| Utc.from_utc_datetime(&date.and_hms_opt(0, 0, 0).unwrap()))
+        .map_err(|_| {
+            format!(
+                "invalid time {:?}: expected e.g. 7d, 2024-05-01 or an RFC 3339 time",
+                text
+            )
+        })
+}
+
+#[test]
+fn test_journal() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("servicers.journal");
+
+    let mut old = Event::new("php-cgi:0", EventKind::Exited);
+    old.time = Utc::now() - chrono::Duration::days(40);
+    old.failed = true;
+    append(&path, &old).unwrap();
+    for name in ["php-cgi:0", "php-cgi:1", "nginx"] {
+        let mut event = Event::new(name, EventKind::Exited);
+        event.status = Some("exit status: 1".to_string());
+        event.uptime = Some(HumanDuration::from_secs(90));
+        append(&path, &event).unwrap();
+    }
+
+    assert_eq!(read(&path, None, None).unwrap().len(), 4);
+    let php = read(&path, Some("php-cgi"), Some(parse_since("7d").unwrap())).unwrap();
+    assert_eq!(php.len(), 2);
+    assert_eq!(php[1].name, "php-cgi:1");
+    assert_eq!(php[1].uptime, Some(HumanDuration::from_secs(90)));
+
+    // Past max_age
+    trim(&path).unwrap();
+    assert_eq!(read(&path, None, None).unwrap().len(), 3);
+
+    assert!(parse_since("2024-05-01").is_ok());
+    assert!(parse_since("2024-05-01T10:00:00+02:00").is_ok());
+    assert!(parse_since("last week").is_err());
+    assert!(read(&dir.path().join("missing"), None, None)
+        .unwrap()
+        .is_empty());
+}
@@ -0,0 +1,451 @@
+//! `limits` of a process: rlimits, nice and I/O priority, set between fork and exec, and a
+//! cgroup v2 of its own (Linux only). A limit that can't be applied fails the spawn rather
+//! than leaving the process running without it. The switch to the process's `user` comes
+//! last, once nothing needs the supervisor's privileges anymore.
+
+#[cfg(not(target_os = "linux"))]
+use std::io;
+use std::path::PathBuf;
+#[cfg(not(target_os = "linux"))]
+use std::process::Command;
+use std::sync::Mutex;
+
+#[cfg(any(test, not(target_os = "linux")))]
+use crate::proc_config::LimitsConfig;
+#[cfg(not(target_os = "linux"))]
+use crate::users::Account;
+
+/// Leaf the supervisor moves itself into when its cgroup has to hand out controllers. Next
+/// to the cgroups named after processes, so no process may take this name.
+pub const SUPERVISOR_CGROUP: &str = "servicers.scope";
+
+/// `service.cgroup_root`, `None` for the supervisor's own cgroup.
+static CGROUP_ROOT: Mutex<Option<PathBuf>> = Mutex::new(None);
+
+pub fn set_cgroup_root(root: Option<PathBuf>) {
+    #[cfg(target_os = "linux")]
+    own_cgroup();
+    *CGROUP_ROOT.lock().unwrap() = root;
+}
+
+#[cfg(target_os = "linux")]
+pub use linux::prepare;
+
+#[cfg(target_os = "linux")]
+mod linux {
+    use std::fs::{self, File, OpenOptions};
+    use std::io::{self, Read, Write};
+    use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+    use std::os::unix::fs::OpenOptionsExt;
+    use std::os::unix::process::CommandExt;
+    use std::path::{Path, PathBuf};
+    use std::process::Command;
+
+    use super::{own_cgroup, CGROUP_ROOT, SUPERVISOR_CGROUP};
+    use crate::logger::log;
+    use crate::proc_config::{CgroupConfig, IoClass, LimitsConfig};
+    use crate::users::Account;
+
+    /// cpu.max period in microseconds, the kernel's default.
+    const CPU_PERIOD: u64 = 100_000;
+
+    /// One call made in the child. It must not allocate, so everything is worked out before
+    /// the fork.
+    enum Step {
+        Rlimit(libc::c_int, u64),
+        Nice(libc::c_int),
+        IoPriority(libc::c_int),
+        Cgroup(RawFd),
+        Groups(Vec<libc::gid_t>),
+        Gid(libc::gid_t),
+        Uid(libc::uid_t),
+    }
+
+    /// Set up for one spawn; keep it until the spawn returns.
+    pub struct Prepared {
+        /// What each step does, for errors.
+        steps: Vec<String>,
+        /// The index of the failed step, written by the child.
+        failed: File,
+        _failed_writer: OwnedFd,
+        _cgroup_procs: Option<File>,
+    }
+
+    impl Prepared {
+        /// Says which limit failed to apply, if it was one of them.
+        pub fn explain(&self, err: io::Error) -> io::Error {
+            let mut index = [0u8];
+            match (&self.failed).read(&mut index) {
+                Ok(1) => match self.steps.get(index[0] as usize) {
+                    Some(step) => io::Error::new(err.kind(), format!("can't {}: {}", step, err)),
+                    None => err,
+                },
+                _ => err,
+            }
+        }
+    }
+
+    /// Makes `command` apply `limits` to the process `name` and switch to `account` before it
+    /// execs.
+    pub fn prepare(
+        command: &mut Command,
+        name: &str,
+        limits: &LimitsConfig,
+        account: Option<&Account>,
+    ) -> io::Result<Prepared> {
+        let mut steps = vec![];
+        let mut descriptions = vec![];
+        let mut rlimit = |resource, field: &str, value: u64, text: String| {
+            steps.push(Step::Rlimit(resource as libc::c_int, value));
+            descriptions.push(format!("set {} to {}", field, text));
+        };
+        if let Some(open_files) = limits.open_files {
+            rlimit(
+                libc::RLIMIT_NOFILE,
+                "open_files",
+                open_files,
+                open_files.to_string(),
+            );
+        }
+        if let Some(core_size) = limits.core_size {
+            rlimit(
+                libc::RLIMIT_CORE,
+                "core_size",
+                core_size.bytes(),
+                core_size.to_string(),
+            );
+        }
+        if let Some(address_space) = limits.address_space {
+            rlimit(
+                libc::RLIMIT_AS,
+                "address_space",
+                address_space.bytes(),
+                address_space.to_string(),
+            );
+        }
+        if let Some(processes) = limits.processes {
+            rlimit(
+                libc::RLIMIT_NPROC,
+                "processes",
+                processes,
+                processes.to_string(),
+            );
+        }
+        if let Some(nice) = limits.nice {
+            steps.push(Step::Nice(nice));
+            descriptions.push(format!("set nice to {}", nice));
+        }
+        if limits.io_class.is_some() || limits.io_priority.is_some() {
+            let class = limits.io_class.unwrap_or(IoClass::BestEffort);
+            // Idle has no levels; 4 is the kernel's default for the others
+            let level = match class {
+                IoClass::Idle => 0,
+                _ => limits.io_priority.unwrap_or(4),
+            };
+            let class_number = match class {
+                IoClass::Realtime => 1,
+                IoClass::BestEffort => 2,
+                IoClass::Idle => 3,
+            };
+            steps.push(Step::IoPriority(class_number << 13 | level as libc::c_int));
+            descriptions.push(format!("set I/O priority to {:?} {}", class, level));
+        }
+        let cgroup_procs = match &limits.cgroup {
+            Some(cgroup) => {
+                let (dir, procs) = prepare_cgroup(name, cgroup)?;
+                steps.push(Step::Cgroup(procs.as_raw_fd()));
+                descriptions.push(format!("join cgroup {}", dir.display()));
+                Some(procs)
+            }
+            None => None,
+        };
+        // Otherwise it's the supervisor's own account, checked when the config was loaded
+        if let Some(account) = account.filter(|_| unsafe { libc::geteuid() } == 0) {
+            steps.push(Step::Groups(account.groups.clone()));
+            descriptions.push(format!("set supplementary groups {:?}", account.groups));
+            steps.push(Step::Gid(account.gid));
+            descriptions.push(format!("switch to group {}", account.gid));
+            steps.push(Step::Uid(account.uid));
+            descriptions.push(format!("switch to user {}", account.name));
+        }
+
+        let mut fds = [0; 2];
+        if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC | libc::O_NONBLOCK) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let (failed, failed_writer) =
+            unsafe { (File::from_raw_fd(fds[0]), OwnedFd::from_raw_fd(fds[1])) };
+        let writer = failed_writer.as_raw_fd();
+
+        unsafe {
+            command.pre_exec(move || {
+                for (index, step) in steps.iter().enumerate() {
+                    let result = match step {
+                        Step::Rlimit(resource, value) => {
+                            let (resource, value) = (*resource, *value);
+                            let limit = libc::rlimit {
+                                rlim_cur: value,
+                                rlim_max: value,
+                            };
+                            libc::setrlimit(resource as _, &limit)
+                        }
+                        Step::Nice(nice) => libc::setpriority(libc::PRIO_PROCESS as _, 0, *nice),
+                        // IOPRIO_WHO_PROCESS, this process
+                        Step::IoPriority(priority) => {
+                            libc::syscall(libc::SYS_ioprio_set, 1, 0, *priority) as libc::c_int
+                        }
+                        // 0 stands for the writing process
+                        Step::Cgroup(procs) => {
+                            if libc::write(*procs, b"0".as_ptr().cast(), 1) == 1 {
+                                0
+                            } else {
+                                -1
+                            }
+                        }
+                        Step::Groups(groups) => libc::setgroups(groups.len(), groups.as_ptr()),
+                        Step::Gid(gid) => libc::setgid(*gid),
+                        Step::Uid(uid) => libc::setuid(*uid),
+                    };
+                    if result == -1 {
+                        let err = io::Error::last_os_error();
+                        libc::write(writer, [index as u8].as_ptr().cast(), 1);
+                        return Err(err);
+                    }
+                }
+                Ok(())
+            });
+        }
+
+        Ok(Prepared {
+            steps: descriptions,
+            failed,
+            _failed_writer: failed_writer,
+            _cgroup_procs: cgroup_procs,
+        })
+    }
+
+    /// Creates or updates the cgroup of `name` and opens its `cgroup.procs`.
+    fn prepare_cgroup(name: &str, config: &CgroupConfig) -> io::Result<(PathBuf, File)> {
+        let root = CGROUP_ROOT
+            .lock()
+            .unwrap()
+            .clone()
+            .or_else(own_cgroup)
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::NotFound,
+                    "can't find the cgroup v2 of the supervisor",
+                )
+            })?;
+        let context = |what: &str, path: &Path, err: io::Error| {
+            io::Error::new(
+                err.kind(),
+                format!("can't {} {}: {}", what, path.display(), err),
+            )
+        };
+
+        let mut controllers = vec![];
+        if config.memory_max.is_some() {
+            controllers.push("memory");
+        }
+        if config.cpu_max.is_some() {
+            controllers.push("cpu");
+        }
+        enable_controllers(&root, &controllers)
+            .map_err(|err| context("enable controllers in", &root, err))?;
+
+        let dir = root.join(name);
+        if let Err(err) = fs::create_dir(&dir) {
+            if err.kind() != io::ErrorKind::AlreadyExists {
+                return Err(context("create", &dir, err));
+            }
+        }
+        // Written every time, so that removed limits are lifted on the next start
+        let memory_max = config
+            .memory_max
+            .map_or("max".to_string(), |max| max.bytes().to_string());
+        let cpu_max = match config.cpu_max {
+            Some(percent) => format!(
+                "{} {}",
+                (percent / 100.0 * CPU_PERIOD as f64).round() as u64,
+                CPU_PERIOD
+            ),
+            None => format!("max {}", CPU_PERIOD),
+        };
+        for (file, value) in [("memory.max", memory_max), ("cpu.max", cpu_max)] {
+            let path = dir.join(file);
+            // Without the controller the file doesn't exist, and there's nothing to lift
+            if !path.exists() && value.starts_with("max") {
+                continue;
+            }
+            fs::write(&path, value).map_err(|err| context("write", &path, err))?;
+        }
+
+        let path = dir.join("cgroup.procs");
+        let procs = OpenOptions::new()
+            .write(true)
+            .custom_flags(libc::O_CLOEXEC)
+            .open(&path)
+            .map_err(|err| context("open", &path, err))?;
+        Ok((dir, procs))
+    }
+
+    fn enable_controllers(root: &Path, controllers: &[&str]) -> io::Result<()> {
+        let available = fs::read_to_string(root.join("cgroup.controllers"))?;
+        if let Some(name) = controllers
+            .iter()
+            .find(|name| !available.split_whitespace().any(|a| a == **name))
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!("the {} controller isn't available", name),
+            ));
+        }
+        let control = root.join("cgroup.subtree_control");
+        let enabled = fs::read_to_string(&control)?;
+        let missing: Vec<String> = controllers
+            .iter()
+            .filter(|name| !enabled.split_whitespace().any(|enabled| enabled == **name))
+            .map(|name| format!("+{}", name))
+            .collect();
+        if missing.is_empty() {
+            return Ok(());
+        }
+
+        match fs::write(&control, missing.join(" ")) {
+            // Only cgroups without processes of their own may hand out controllers
+            Err(err) if err.raw_os_error() == Some(libc::EBUSY) => {
+                let leaf = root.join(SUPERVISOR_CGROUP);
+                log!(
+                    "Moving processes of {} to {}",
+                    root.display(),
+                    leaf.display()
+                );
+                if let Err(err) = fs::create_dir(&leaf) {
+                    if err.kind() != io::ErrorKind::AlreadyExists {
+                        return Err(err);
+                    }
+                }
+                let mut procs = OpenOptions::new()
+                    .write(true)
+                    .open(leaf.join("cgroup.procs"))?;
+                for pid in fs::read_to_string(root.join("cgroup.procs"))?.lines() {
+                    // Each pid needs a write of its own; it may also have exited meanwhile
+                    if let Err(err) = procs.write_all(pid.as_bytes()) {
+                        if err.raw_os_error() != Some(libc::ESRCH) {
+                            return Err(err);
+                        }
+                    }
+                }
+                fs::write(&control, missing.join(" "))
+            }
+            result => result,
+        }
+    }
+}
+
+/// The supervisor's cgroup v2 directory, looked up once, before it may be moved into
+/// a `SUPERVISOR_CGROUP` leaf of its own.
+#[cfg(target_os = "linux")]
+fn own_cgroup() -> Option<PathBuf> {
+    static OWN_CGROUP: std::sync::OnceLock<Option<PathBuf>> = std::sync::OnceLock::new();
+    OWN_CGROUP
+        .get_or_init(|| {
+            let text = std::fs::read_to_string("/proc/self/cgroup").ok()?;
+            let path = text.lines().find_map(|line| line.strip_prefix("0::"))?;
+            // /sys/fs/cgroup, or /sys/fs/cgroup/unified next to v1 hierarchies
+            let mounts = std::fs::read_to_string("/proc/self/mounts").ok()?;
+            let mount = mounts.lines().find_map(|line| {
+                let fields: Vec<&str> = line.split(' ').collect();
+                (fields.get(2) == Some(&"cgroup2")).then(|| fields[1].to_string())
+            })?;
+            let mut root = PathBuf::from(mount);
+            root.extend(path.split('/').filter(|part| !part.is_empty()));
+            Some(root)
+        })
+        .clone()
+}
+
+#[cfg(not(target_os = "linux"))]
+pub struct Prepared;
+
+#[cfg(not(target_os = "linux"))]
+impl Prepared {
+    pub fn explain(&self, err: io::Error) -> io::Error {
+        err
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn prepare(
+    _command: &mut Command,
+    name: &str,
+    _limits: &LimitsConfig,
+    _account: Option<&Account>,
+) -> io::Result<Prepared> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        format!("{}: limits are only supported on Linux", name),
+    ))
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn test_limits() {
+    use crate::proc_config::ProcessConfig;
+    use crate::units::ByteSize;
+
+    let config = |limits: LimitsConfig| ProcessConfig {
+        name: "sh".to_string(),
+        program: "sh".to_string(),
+        args: vec![
+            "-c".to_string(),
+            "ulimit -n; ulimit -c; cut -d ' ' -f 19 /proc/self/stat".to_string(),
+        ],
+        cwd: "/".to_string(),
+        limits: Some(limits),
+        ..Default::default()
+    };
+
+    let child = config(LimitsConfig {
+        open_files: Some(64),
+        core_size: Some(ByteSize(0)),
+        nice: Some(5),
+        io_class: Some(crate::proc_config::IoClass::Idle),
+        ..Default::default()
+    })
+    .spawn_new()
+    .unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "64\n0\n5\n");
+
+    // Over fs.nr_open, even for root
+    let err = config(LimitsConfig {
+        open_files: Some(1 << 40),
+        ..Default::default()
+    })
+    .spawn_new()
+    .unwrap_err();
+    assert!(
+        err.to_string()
+            .starts_with("can't set open_files to 1099511627776: "),
+        "{}",
+        err
+    );
+
+    // Raised limits first, then the switch to an account that couldn't raise them
+    if unsafe { libc::geteuid() } == 0 {
+        let mut nobody = config(LimitsConfig {
+            open_files: Some(4096),
+            ..Default::default()
+        });
+        nobody.user = Some("nobody".to_string());
+        nobody.args[1] = "ulimit -n; id -u; id -G; echo $HOME $USER".to_string();
+        let output = nobody.spawn_new().unwrap().wait_with_output().unwrap();
+        let text = String::from_utf8_lossy(&output.stdout).into_owned();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines[..2], ["4096", "65534"]);
+        assert!(!lines[2].split(' ').any(|gid| gid == "0"), "{}", text);
+        assert!(lines[3].ends_with(" nobody"), "{}", text);
+    }
+}
@@ -2,34 +2,78 @@ use chrono::Utc;
 use core::fmt::Display;
 use std::fs::OpenOptions;
 use std::io::prelude::*;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::Mutex;
 
 static WRITE_CHECK: Mutex<bool> = Mutex::new(true);
+static LOG_DIR: Mutex<Option<PathBuf>> = Mutex::new(None);
+static VERBOSE: AtomicBool = AtomicBool::new(false);
+static MAX_SIZE: AtomicU64 = AtomicU64::new(10240);
+static CONSOLE: AtomicBool = AtomicBool::new(true);
+
+/// Overrides the directory of `servicers.log` (next to the executable by default).
+pub fn set_log_dir(dir: PathBuf) {
+    *LOG_DIR.lock().unwrap() = Some(dir);
+}
+
+pub fn log_dir() -> PathBuf {
+    match LOG_DIR.lock().unwrap().as_ref() {
+        Some(dir) => dir.clone(),
+        None => {
+            let mut dir = std::env::current_exe().unwrap();
+            dir.pop();
+            dir
+        }
+    }
+}
+
+pub fn log_path() -> PathBuf {
+    log_dir().join("servicers.log")
+}
+
+/// Stops echoing log lines to stdout, e.g. once stdout is the log itself.
+#[cfg(unix)]
+pub fn set_console(console: bool) {
+    CONSOLE.store(console, Ordering::Relaxed);
+}
+
+/// Size after which `servicers.log` starts over, from `log.max_size`.
+pub fn set_max_size(bytes: u64) {
+    MAX_SIZE.store(bytes, Ordering::Relaxed);
+}
+
+pub fn set_verbose(verbose: bool) {
+    VERBOSE.store(verbose, Ordering::Relaxed);
+}
+
+pub fn is_verbose() -> bool {
+    VERBOSE.load(Ordering::Relaxed)
+}
 
 pub fn log_write<T: Display + ?Sized>(message: &T) {
-    println!("{}", &message);
+    if CONSOLE.load(Ordering::Relaxed) {
+        println!("{}", &message);
+    }
 
     let mut num = WRITE_CHECK.lock().unwrap();
     if num.eq(&true) {
         *num = false;
 
-        let mut file_path = std::env::current_exe().unwrap();
-        file_path.pop();
-        file_path.push("servicers.log");
+        let file_path = log_path();
 
         let mut file = OpenOptions::new()
-            .write(true)
             .append(true)
             .create(true)
             .open(&file_path)
             .unwrap();
-        if file.metadata().unwrap().len() > 10240 { //10mb
+        if file.metadata().unwrap().len() > MAX_SIZE.load(Ordering::Relaxed) {
             file = OpenOptions::new()
-            .write(true)
-            .append(false)
-            .create(true)
-            .open(&file_path)
-            .unwrap();
+                .write(true)
+                .truncate(true)
+                .create(true)
+                .open(&file_path)
+                .unwrap();
         }
 
         let time = Utc::now().format("%F %T").to_string();
@@ -48,4 +92,15 @@ macro_rules! log {
     }};
 }
 
+/// Same as `log!`, but only written when `--verbose` is set.
+macro_rules! verbose {
+    () => {};
+    ($($arg:tt)*) => {{
+        if crate::logger::is_verbose() {
+            crate::logger::log!($($arg)*);
+        }
+    }};
+}
+
 pub(crate) use log;
+pub(crate) use verbose;
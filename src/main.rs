@@ -1,73 +1,426 @@
-use std::env;
-use std::{
-    sync::{atomic::AtomicBool, Arc},
-    thread,
-    time::Duration,
-};
+use chrono::{DateTime, Utc};
+use clap::{CommandFactory, Parser};
+use std::error::Error;
+use std::path::PathBuf;
+use std::process::ExitCode;
 
-use crate::child_proc::{run_processes, ChildProcess};
-use crate::logger::log;
+use crate::child_proc::ProcessStatus;
+use crate::cli::{Cli, Command, ConfigCommand, CrashCommand, ExportCommand};
+#[cfg(unix)]
+use crate::cli::{InitSystem, Restart, ServiceOptions};
+use crate::control_socket::{Request, Response};
 
 mod child_proc;
+#[cfg(windows)]
 mod child_service;
+mod cli;
+mod config_format;
+#[cfg(windows)]
 mod control;
+mod control_socket;
+mod crash_report;
+#[cfg(unix)]
+mod daemon;
+mod export;
+mod file_watch;
+mod import;
+#[cfg(unix)]
+mod init_service;
+mod journal;
+mod limits;
 mod logger;
+#[cfg(windows)]
 mod monitor_service;
+mod output;
 mod proc_config;
+mod readiness;
+mod resource_watch;
+mod runtime_state;
+mod schedule;
+#[cfg(unix)]
+mod sd_notify;
+mod signals;
+mod sockets;
+mod supervisor;
+#[cfg(all(test, windows))]
 mod tests;
+mod units;
+mod users;
 
 pub const SERVICE_NAME: &str = "servicers";
 
-#[cfg(not(windows))]
-pub fn main() {
-    panic!("This program is only intended to run on Windows.");
+pub fn main() -> ExitCode {
+    let cli = Cli::parse();
+
+    if let Some(dir) = &cli.log_dir {
+        logger::set_log_dir(dir.clone());
+    }
+    if let Some(path) = &cli.config {
+        proc_config::set_config_path(path.clone());
+    }
+    logger::set_verbose(cli.verbose);
+
+    match execute(&cli) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("servicers: {}", err);
+            ExitCode::FAILURE
+        }
+    }
 }
 
-#[cfg(windows)]
-fn main() -> windows_service::Result<()> {
-    let args: Vec<String> = env::args().collect();
-
-    match args.get(1) {
-        Some(cmd) => match cmd.as_str() {
-            "install" => control::install(),
-            "uninstall" => control::uninstall(),
-            "start" => control::start(),
-            "stop" => control::stop(),
-            "pause" => control::pause(),
-            "resume" => control::resume(),
-            "status" => {
-                let stat = control::status();
-                println!("{:?}", stat);
-                stat
+fn execute(cli: &Cli) -> Result<(), Box<dyn Error>> {
+    match &cli.command {
+        Command::Run { daemon, pidfile } => run(*daemon, pidfile.clone()),
+        Command::Config { command } => execute_config(command),
+        Command::Import {
+            format,
+            file,
+            to,
+            output,
+        } => {
+            let text = std::fs::read_to_string(file)?;
+            let result = import::import(*format, &text, file)
+                .map_err(|err| format!("{}: {}", file.display(), err))?;
+            for warning in &result.warnings {
+                eprintln!("warning: {}", warning);
             }
-            "run" => {
-                let mut list = Vec::<ChildProcess>::new();
-                for cfg in proc_config::load() {
-                    list.push(ChildProcess::from_config(cfg));
-                }
-
-                let need_exit = Arc::new(AtomicBool::new(false));
-                let threads = run_processes(list, &need_exit);
 
-                while !threads.iter().all(|t| t.is_finished()) {
-                    thread::sleep(Duration::from_millis(100));
+            let config = proc_config::Config {
+                processes: result.processes,
+                ..Default::default()
+            };
+            let text = to.serialize(&config)?;
+            match output {
+                Some(output) if output.exists() => {
+                    return Err(format!("{} already exists", output.display()).into())
                 }
-                Ok(())
+                Some(output) => std::fs::write(output, text)?,
+                None => println!("{}", text),
             }
-            "runservice" => {
-                match monitor_service::run() {
-                    Err(err) => log!("{:?}", &err),
-                    _ => (),
+            Ok(())
+        }
+        Command::Export {
+            command: ExportCommand::Systemd { dir, prefix },
+        } => {
+            let config = proc_config::load()?;
+            for (name, unit) in export::render_systemd(prefix, &config.processes) {
+                match dir {
+                    Some(dir) => {
+                        std::fs::create_dir_all(dir)?;
+                        std::fs::write(dir.join(&name), unit)?;
+                        println!("{}", dir.join(&name).display());
+                    }
+                    None => println!("# {}\n{}", name, unit),
                 }
-                Ok(())
             }
-            _ => Ok(()),
-        },
-        None => {
-            println!("Using: servicers.exe <command>");
-            println!("Available commands: install, uninstall, start, stop, run, runservice");
-
             Ok(())
         }
+        // Without a name, Windows pauses the whole service through the service manager
+        Command::Pause { name } if cfg!(unix) || name.is_some() => {
+            control_command(Request::Pause { name: name.clone() })
+        }
+        Command::Resume { name } if cfg!(unix) || name.is_some() => {
+            control_command(Request::Resume { name: name.clone() })
+        }
+        Command::Restart {
+            name,
+            rolling,
+            settle,
+        } => control_command(Request::Restart {
+            name: name.clone(),
+            rolling: *rolling,
+            settle: *settle,
+        }),
+        Command::Signal { name, signal } => control_command(Request::Signal {
+            name: name.clone(),
+            signal: signal.clone(),
+        }),
+        Command::Action { name, action } => control_command(Request::Action {
+            name: name.clone(),
+            action: action.clone(),
+        }),
+        Command::History { name, since } => {
+            let events = journal::read(&journal::journal_path(), name.as_deref(), *since)?;
+            print_events(&events);
+            Ok(())
+        }
+        Command::Crashes { command } => {
+            let dir = crash_report::reports_dir();
+            match command {
+                CrashCommand::List { name } => {
+                    println!("{:<23} {:<24} {:<24} FILE", "TIME", "NAME", "EXIT");
+                    for report in crash_report::list(&dir, name.as_deref())? {
+                        println!(
+                            "{:<23} {:<24} {:<24} {}",
+                            report.time, report.process, report.exit, report.file
+                        );
+                    }
+                }
+                CrashCommand::Show { report } => {
+                    let path = crash_report::find(&dir, report)?;
+                    print!("{}", std::fs::read_to_string(path)?);
+                }
+            }
+            Ok(())
+        }
+        Command::Completions { shell } => {
+            clap_complete::generate(
+                *shell,
+                &mut Cli::command(),
+                SERVICE_NAME,
+                &mut std::io::stdout(),
+            );
+            Ok(())
+        }
+        command => execute_service(cli, command),
+    }
+}
+
+#[cfg(unix)]
+fn run(daemon: bool, pidfile: Option<PathBuf>) -> Result<(), Box<dyn Error>> {
+    let path = pidfile.unwrap_or_else(|| proc_config::config_path().with_extension("pid"));
+    let mut pidfile = daemon::Pidfile::lock(&path)?;
+    if daemon {
+        // Config errors go to the terminal rather than only the log
+        proc_config::load()?;
+        daemon::daemonize()?;
+    }
+    pidfile.write_pid()?;
+    supervisor::run()
+}
+
+#[cfg(not(unix))]
+fn run(daemon: bool, pidfile: Option<PathBuf>) -> Result<(), Box<dyn Error>> {
+    if daemon || pidfile.is_some() {
+        return Err("`run --daemon` and `--pidfile` are only supported on Unix".into());
+    }
+    supervisor::run()
+}
+
+fn execute_config(command: &ConfigCommand) -> Result<(), Box<dyn Error>> {
+    match command {
+        ConfigCommand::Show { format } => {
+            println!("{}", format.serialize(&proc_config::load()?)?);
+        }
+        ConfigCommand::Convert { file, to, output } => {
+            let file = file.clone().unwrap_or_else(proc_config::config_path);
+            let output = output
+                .clone()
+                .unwrap_or_else(|| file.with_extension(to.extension()));
+            if output.exists() {
+                return Err(format!("{} already exists", output.display()).into());
+            }
+
+            let config = proc_config::read_document(&file)?;
+            std::fs::write(&output, to.serialize(&config)?)?;
+            println!("{} -> {}", file.display(), output.display());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(windows)]
+fn execute_service(cli: &Cli, command: &Command) -> Result<(), Box<dyn Error>> {
+    use crate::logger::log;
+
+    // A broken config shouldn't prevent stopping or removing the service.
+    let wait = match proc_config::load() {
+        Ok(config) => config.service.status_wait.0,
+        Err(_) => proc_config::ServiceConfig::default().status_wait.0,
+    };
+
+    match command {
+        Command::Install { .. } => control::install(service_arguments(cli)?)?,
+        Command::Uninstall { .. } => control::uninstall(wait)?,
+        Command::Start { .. } => control::start(wait)?,
+        Command::Stop { .. } => control::stop(wait)?,
+        Command::Pause { .. } => control::pause(wait)?,
+        Command::Resume { .. } => control::resume(wait)?,
+        Command::Status { .. } => {
+            control::status()?;
+            // Not running is already shown above
+            control_command(Request::Status).ok();
+        }
+        Command::Runservice => {
+            if let Err(err) = monitor_service::run() {
+                log!("{:?}", &err);
+                return Err(err.into());
+            }
+        }
+        _ => unreachable!(),
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn execute_service(cli: &Cli, command: &Command) -> Result<(), Box<dyn Error>> {
+    use crate::init_service::InitService;
+
+    let init_service = |service: &ServiceOptions| -> Result<InitService, Box<dyn Error>> {
+        Ok(InitService {
+            init: service
+                .init
+                .unwrap_or_else(|| InitSystem::detect(&service.root)),
+            root: service.root.clone(),
+            exe: std::env::current_exe()?,
+            options: service_arguments(cli)?,
+            user: None,
+            restart: Restart::OnFailure,
+            workdir: None,
+        })
+    };
+
+    match command {
+        Command::Install { service, install } => InitService {
+            user: install.user.clone(),
+            restart: install.restart,
+            workdir: install.workdir.clone(),
+            ..init_service(service)?
+        }
+        .install()?,
+        Command::Uninstall { service } => init_service(service)?.uninstall()?,
+        Command::Start { service } => init_service(service)?.start()?,
+        Command::Stop { service } => init_service(service)?.stop()?,
+        Command::Status { service } => {
+            if control_command(Request::Status).is_err() {
+                init_service(service)?.status()?
+            }
+        }
+        command => {
+            let name = format!("{:?}", command).to_lowercase();
+            return Err(format!("`{}` is only supported on Windows", name).into());
+        }
+    }
+    Ok(())
+}
+
+fn control_address() -> String {
+    // A broken config shouldn't hide a running supervisor
+    proc_config::load()
+        .map(|config| config.service)
+        .unwrap_or_default()
+        .control_address()
+}
+
+/// Sends `request` to the running supervisor and prints its answer.
+fn control_command(request: Request) -> Result<(), Box<dyn Error>> {
+    let address = control_address();
+    let mut errors = vec![];
+    control_socket::call(&address, &request, |response| match response {
+        Response::Status { processes } => print_processes(&processes),
+        Response::Message { text } => println!("{}", text),
+        Response::Error { text } => errors.push(text),
+        Response::Done => (),
+    })
+    .map_err(|err| format!("can't reach the supervisor at {}: {}", address, err))?;
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors.join("\n").into())
+    }
+}
+
+fn print_processes(processes: &[ProcessStatus]) {
+    // Run times only when there are jobs
+    let jobs = processes
+        .iter()
+        .any(|process| process.last_run.is_some() || process.next_run.is_some());
+    let runs = |last: &str, next: &str| match jobs {
+        true => format!("{:<19} {:<19}  ", last, next),
+        false => String::new(),
+    };
+    // Planned restarts only when there have been some
+    let planned = processes.iter().any(|process| process.planned_restarts > 0);
+    let planned_restarts = |count: &dyn std::fmt::Display| match planned {
+        true => format!("{:>8}  ", count),
+        false => String::new(),
+    };
+    let time = |time: Option<DateTime<Utc>>| {
+        time.map_or("-".to_string(), |time| {
+            time.with_timezone(&chrono::Local)
+                .format("%F %T")
+                .to_string()
+        })
+    };
+
+    println!(
+        "{:<24} {:<10} {:>7} {:>8}  {}{}LAST EXIT",
+        "NAME",
+        "STATE",
+        "PID",
+        "RESTARTS",
+        planned_restarts(&"PLANNED"),
+        runs("LAST RUN", "NEXT RUN")
+    );
+    for process in processes {
+        let pid = match process.pid {
+            0 => "-".to_string(),
+            pid => pid.to_string(),
+        };
+        println!(
+            "{:<24} {:<10} {:>7} {:>8}  {}{}{}",
+            process.name,
+            format!("{:?}", process.state).to_uppercase(),
+            pid,
+            process.restarts,
+            planned_restarts(&process.planned_restarts),
+            runs(&time(process.last_run), &time(process.next_run)),
+            with_class(process.last_exit.as_deref(), process.exit_class)
+        );
+    }
+}
+
+fn print_events(events: &[journal::Event]) {
+    println!(
+        "{:<19} {:<24} {:<15} {:>7}  {:<28} UPTIME",
+        "TIME (UTC)", "NAME", "EVENT", "PID", "STATUS"
+    );
+    for event in events {
+        println!(
+            "{:<19} {:<24} {:<15} {:>7}  {:<28} {}",
+            event.time.format("%F %T"),
+            event.name,
+            serde_json::to_value(event.event)
+                .unwrap()
+                .as_str()
+                .unwrap_or_default(),
+            event.pid.map_or("-".to_string(), |pid| pid.to_string()),
+            with_class(event.status.as_deref(), event.class),
+            event
+                .uptime
+                .map_or("-".to_string(), |uptime| units::format_elapsed(uptime.0))
+        );
+    }
+
+    let failures = events.iter().filter(|event| event.failed).count();
+    println!("{} events, {} failures", events.len(), failures);
+}
+
+/// `exit status: 75 (temporary)`
+fn with_class(status: Option<&str>, class: Option<proc_config::ExitClass>) -> String {
+    match (status, class) {
+        (Some(status), Some(class)) => format!("{} ({})", status, class),
+        (Some(status), None) => status.to_string(),
+        (None, _) => "-".to_string(),
+    }
+}
+
+/// Global options the installed service has to be launched with, as absolute paths
+/// because service managers start it from another directory (`System32` on Windows).
+fn service_arguments(cli: &Cli) -> std::io::Result<Vec<std::ffi::OsString>> {
+    let mut args = Vec::new();
+    if let Some(path) = &cli.config {
+        args.push("--config".into());
+        args.push(std::fs::canonicalize(path)?.into_os_string());
+    }
+    if let Some(dir) = &cli.log_dir {
+        args.push("--log-dir".into());
+        args.push(std::fs::canonicalize(dir)?.into_os_string());
+    }
+    if cli.verbose {
+        args.push("--verbose".into());
     }
+    Ok(args)
 }
@@ -3,6 +3,7 @@ use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::mpsc::Receiver;
 use std::sync::Arc;
 use std::thread;
+use std::time::Instant;
 use std::{ffi::OsString, sync::mpsc, time::Duration};
 use windows_service::{
     define_windows_service,
@@ -14,9 +15,11 @@ use windows_service::{
     service_dispatcher, Result,
 };
 
-use crate::child_proc::{run_processes, ChildProcess};
+use crate::child_proc::{all_started, run_processes, ChildProcess, ProcessCommand};
 use crate::child_service::run_services;
-use crate::logger::log;
+use crate::control_socket::{self, Registry};
+use crate::journal;
+use crate::logger::{self, log};
 use crate::proc_config::{self, *};
 
 const SERVICE_TYPE: ServiceType = ServiceType::OWN_PROCESS;
@@ -114,18 +117,62 @@ fn run_main_loop(
     status_handle: ServiceStatusHandle,
     shutdown_rx: Receiver<ServiceControl>,
 ) -> windows_service::Result<()> {
-    let mut list = Vec::<ChildProcess>::new();
-    for cfg in proc_config::load() {
-        list.push(ChildProcess::from_config(cfg));
-    }
+    let config = match proc_config::load() {
+        Ok(config) => config,
+        Err(err) => {
+            log!("{}", &err);
+            return Ok(());
+        }
+    };
+
+    logger::set_max_size(config.log.max_size.bytes());
+    journal::set_retention(config.journal.max_age.0, config.journal.max_size.bytes());
+    journal::compact();
+    let poll_interval = config.service.poll_interval.0;
+
+    // Ready or failed by then
+    let start_timeout = config
+        .processes
+        .iter()
+        .filter_map(|process| process.ready.as_ref())
+        .map(|ready| ready.timeout.0)
+        .max()
+        .unwrap_or_default();
+    let list = ChildProcess::from_configs(config.processes);
 
     // Атомарный потокобезопасный флажок обернутый в потокобезопасный strong счетчик ссылок.
     // Видимо, подразумевается что он безопасно чистит память при выходе из блока. Интересно как.
     // От родителя к потомку - Arc, обратно Weak. Написано, что иначе память потечет.
     let need_exit = Arc::new(AtomicBool::new(false));
 
-    let mut threads = run_processes(list, &need_exit);
-    threads.extend(run_services(&need_exit));
+    let handles = run_processes(list, &need_exit, poll_interval);
+    let registry = Arc::new(Registry::default());
+    registry.set(&handles);
+    let address = config.service.control_address();
+    if let Err(err) = control_socket::serve(&address, registry.clone()) {
+        log!("Can't listen for commands on {}: {:?}", &address, &err);
+    }
+
+    // Running only once every process is, as the supervisor tells systemd elsewhere
+    let started = Instant::now();
+    let mut checkpoint = 0;
+    while !all_started(&handles) && started.elapsed() < start_timeout + poll_interval {
+        checkpoint += 1;
+        status_handle.set_service_status(ServiceStatus {
+            checkpoint,
+            wait_hint: Duration::from_secs(10),
+            ..ServiceStatus::state(ServiceState::StartPending)
+        })?;
+        thread::sleep(poll_interval);
+    }
+
+    let mut threads: Vec<thread::JoinHandle<()>> =
+        handles.into_iter().map(|handle| handle.thread).collect();
+    threads.extend(run_services(
+        &need_exit,
+        poll_interval,
+        config.service.status_wait.0,
+    ));
 
     // Сообщаю венде, что служба запущена
     status_handle.set_service_status(ServiceStatus::state(ServiceState::Running))?;
@@ -137,10 +184,26 @@ fn run_main_loop(
                 ServiceControl::Interrogate => {}
                 ServiceControl::Continue => {
                     status_handle
-                        .set_service_status(ServiceStatus::state(ServiceState::StartPending))?;
+                        .set_service_status(ServiceStatus::state(ServiceState::ContinuePending))?;
+                    for result in registry.command(None, ProcessCommand::Resume) {
+                        if let Err(err) = result {
+                            log!("{}", &err);
+                        }
+                    }
+                    status_handle
+                        .set_service_status(ServiceStatus::state(ServiceState::Running))?;
+                    log!("Service resumed");
                 }
                 ServiceControl::Pause => {
+                    status_handle
+                        .set_service_status(ServiceStatus::state(ServiceState::PausePending))?;
+                    for result in registry.command(None, ProcessCommand::Pause) {
+                        if let Err(err) = result {
+                            log!("{}", &err);
+                        }
+                    }
                     status_handle.set_service_status(ServiceStatus::state(ServiceState::Paused))?;
+                    log!("Service paused");
                 }
                 ServiceControl::Stop => {
                     status_handle
@@ -155,7 +218,7 @@ fn run_main_loop(
                         .ok();
 
                     while !threads.iter().all(|t| t.is_finished()) {
-                        thread::sleep(Duration::from_millis(100));
+                        thread::sleep(poll_interval);
                     }
 
                     status_handle
@@ -0,0 +1,189 @@
+//! Drains the stdout/stderr pipes of supervised processes, keeping the last lines for crash
+//! reports. Unread pipes would block a process once their buffer fills up.
+
+use regex::Regex;
+use std::collections::VecDeque;
+use std::io::{self, BufRead, BufReader, Read};
+use std::process::Child;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stream {
+    Stdout,
+    Stderr,
+}
+
+#[derive(Debug, Clone)]
+pub struct Line {
+    pub stream: Stream,
+    pub text: String,
+}
+
+/// The last `capacity` lines written by a process, shared with its reader threads.
+#[derive(Debug, Clone)]
+pub struct OutputTail {
+    lines: Arc<Mutex<VecDeque<Line>>>,
+    capacity: usize,
+}
+
+impl OutputTail {
+    pub fn new(capacity: usize) -> OutputTail {
+        OutputTail {
+            lines: Arc::new(Mutex::new(VecDeque::with_capacity(capacity))),
+            capacity,
+        }
+    }
+
+    fn push(&self, stream: Stream, text: String) {
+        if self.capacity == 0 {
+            return;
+        }
+        let mut lines = self.lines.lock().unwrap();
+        if lines.len() == self.capacity {
+            lines.pop_front();
+        }
+        lines.push_back(Line { stream, text });
+    }
+
+    pub fn lines(&self) -> Vec<Line> {
+        self.lines.lock().unwrap().iter().cloned().collect()
+    }
+
+    /// Forgets the output of the previous run.
+    pub fn clear(&self) {
+        self.lines.lock().unwrap().clear();
+    }
+}
+
+/// Flags a line of stdout matching `pattern`, for `ready.stdout`.
+#[derive(Debug, Clone)]
+pub struct LineWatch {
+    pub pattern: Regex,
+    pub seen: Arc<AtomicBool>,
+}
+
+/// Reader threads for the pipes of one run of a process.
+pub struct Capture {
+    readers: Vec<JoinHandle<()>>,
+}
+
+impl Capture {
+    /// Takes the piped stdout and stderr of `child`, watching stdout for `watch`.
+    pub fn start(child: &mut Child, tail: &OutputTail, watch: Option<LineWatch>) -> Capture {
+        let mut readers = vec![];
+        if let Some(stdout) = child.stdout.take() {
+            readers.push(read_lines(stdout, Stream::Stdout, tail.clone(), watch));
+        }
+        if let Some(stderr) = child.stderr.take() {
+            readers.push(read_lines(stderr, Stream::Stderr, tail.clone(), None));
+        }
+        Capture { readers }
+    }
+
+    /// Waits up to `timeout` for the last lines of an exited process. The pipes may stay
+    /// open longer if it left children of its own behind.
+    pub fn finish(self, timeout: Duration) {
+        let deadline = Instant::now() + timeout;
+        while Instant::now() < deadline && !self.readers.iter().all(|r| r.is_finished()) {
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+}
+
+/// Longest line kept; the rest of it is dropped, so that output without newlines (binary,
+/// progress bars redrawn with `\r`) can't grow the supervisor's memory.
+const MAX_LINE: usize = 4096;
+
+/// `read_until` a newline, keeping at most `MAX_LINE` bytes of the line. The number of
+/// bytes consumed, 0 at the end of the pipe.
+fn read_line(reader: &mut impl BufRead, line: &mut Vec<u8>) -> io::Result<usize> {
+    let mut read = 0;
+    loop {
+        let buffer = match reader.fill_buf() {
+            Ok(buffer) => buffer,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err),
+        };
+        if buffer.is_empty() {
+            return Ok(read);
+        }
+        let (chunk, done) = match buffer.iter().position(|b| *b == b'\n') {
+            Some(end) => (&buffer[..=end], true),
+            None => (buffer, false),
+        };
+        let room = MAX_LINE.saturating_sub(line.len());
+        line.extend_from_slice(&chunk[..chunk.len().min(room)]);
+        let used = chunk.len();
+        reader.consume(used);
+        read += used;
+        if done {
+            return Ok(read);
+        }
+    }
+}
+
+fn read_lines<R: Read + Send + 'static>(
+    pipe: R,
+    stream: Stream,
+    tail: OutputTail,
+    watch: Option<LineWatch>,
+) -> JoinHandle<()> {
+    thread::spawn(move || {
+        let mut reader = BufReader::new(pipe);
+        let mut line = Vec::new();
+        // Not `lines()`: output isn't necessarily UTF-8
+        while let Ok(read) = read_line(&mut reader, &mut line) {
+            if read == 0 {
+                break;
+            }
+            let text = String::from_utf8_lossy(&line);
+            let text = text.trim_end_matches(['\r', '\n']);
+            if let Some(watch) = &watch {
+                if !watch.seen.load(Ordering::Relaxed) && watch.pattern.is_match(text) {
+                    watch.seen.store(true, Ordering::Relaxed);
+                }
+            }
+            tail.push(stream, text.to_string());
+            line.clear();
+        }
+    })
+}
+
+#[cfg(unix)]
+#[test]
+fn test_capture() {
+    use std::process::{Command, Stdio};
+
+    let tail = OutputTail::new(3);
+    let run = |script: &str| {
+        let mut child = Command::new("sh")
+            .args(["-c", script])
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap();
+        let capture = Capture::start(&mut child, &tail, None);
+        child.wait().unwrap();
+        capture.finish(Duration::from_secs(5));
+        tail.lines()
+    };
+
+    let lines = run("for i in 1 2 3 4; do echo out $i; done");
+    let texts: Vec<&str> = lines.iter().map(|line| line.text.as_str()).collect();
+    assert_eq!(texts, ["out 2", "out 3", "out 4"]);
+
+    tail.clear();
+    let lines = run("printf 'oops\\r\\n' >&2");
+    assert_eq!(lines.len(), 1);
+    assert_eq!(lines[0].stream, Stream::Stderr);
+    assert_eq!(lines[0].text, "oops");
+
+    tail.clear();
+    let lines = run("head -c 100000 /dev/zero | tr '\\0' x; echo; echo after");
+    assert_eq!(lines.len(), 2);
+    assert_eq!(lines[0].text, "x".repeat(MAX_LINE));
+    assert_eq!(lines[1].text, "after");
+}
@@ -1,115 +1,1519 @@
+use super::config_format::{Format, Shape};
+use super::limits;
 use super::logger::log;
+use super::schedule::Cron;
+use super::sockets;
+use super::units::{ByteSize, HumanDuration};
+use super::users;
 use serde::{self, Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{BTreeMap, HashSet};
+use std::fmt::{self, Display};
 use std::fs::File;
 use std::io::{BufReader, Read, Write};
+use std::path::{Path, PathBuf};
 use std::process::Child;
 use std::process::Command;
 use std::process::Stdio;
+use std::sync::Mutex;
 
+#[cfg(windows)]
 pub const NGINX_PATH: &str = "C:/nginx/nginx.exe";
+#[cfg(windows)]
 pub const NGINX_STOP_ARGS: [&str; 2] = ["-s", "stop"];
+#[cfg(windows)]
 pub const NGINX_CWD: &str = "C:/nginx";
 
-#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+/// Prefix of per-field overrides: `SERVICERS__<NAME>__<FIELD>=<value>`.
+pub const ENV_OVERRIDE_PREFIX: &str = "SERVICERS__";
+
+static CONFIG_PATH: Mutex<Option<PathBuf>> = Mutex::new(None);
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
 #[serde(rename_all = "UPPERCASE")]
 pub enum ProcessConfigState {
     Enabled,
     Disabled,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+/// When an exited process is started again.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum RestartPolicy {
+    #[default]
+    Always,
+    /// Only after a non-zero exit code or a signal.
+    OnFailure,
+    Never,
+}
+
+impl RestartPolicy {
+    fn is_always(&self) -> bool {
+        *self == RestartPolicy::Always
+    }
+}
+
+/// An exit code, or the name of a signal that killed the process.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum ExitMatch {
+    Code(i32),
+    Signal(String),
+}
+
+impl ExitMatch {
+    fn matches(&self, status: &std::process::ExitStatus) -> bool {
+        match self {
+            ExitMatch::Code(code) => status.code() == Some(*code),
+            #[cfg(unix)]
+            ExitMatch::Signal(name) => {
+                use std::os::unix::process::ExitStatusExt;
+                status.signal().is_some() && status.signal() == crate::signals::parse(name)
+            }
+            #[cfg(not(unix))]
+            ExitMatch::Signal(_) => false,
+        }
+    }
+}
+
+impl Display for ExitMatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExitMatch::Code(code) => write!(f, "{}", code),
+            ExitMatch::Signal(name) => f.write_str(name),
+        }
+    }
+}
+
+/// What an exit means, per `success_exit_codes` and the like.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ExitClass {
+    Success,
+    Failure,
+    /// In `restart_on_exit_codes`.
+    Temporary,
+    /// In `fatal_exit_codes`.
+    Fatal,
+}
+
+impl Display for ExitClass {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            ExitClass::Success => "success",
+            ExitClass::Failure => "failure",
+            ExitClass::Temporary => "temporary",
+            ExitClass::Fatal => "fatal",
+        };
+        f.write_str(name)
+    }
+}
+
+/// One of a process's `actions`.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum Action {
+    /// A signal such as `"SIGUSR1"` sent to the process (Unix only).
+    Signal(String),
+    /// A program and its arguments, e.g. `["nginx", "-s", "reload"]`, run as the process
+    /// with its `cwd` and `env` and SERVICERS_PID set; it has `stop_timeout` to succeed.
+    Command(Vec<String>),
+}
+
+impl Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Action::Signal(signal) => f.write_str(signal),
+            Action::Command(command) => f.write_str(&command.join(" ")),
+        }
+    }
+}
+
+/// What a failed `pre_start` hook does to the start.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum HookFailure {
+    /// The start fails, and is retried per `restart`.
+    #[default]
+    Fail,
+    /// The process starts anyway.
+    Ignore,
+}
+
+impl HookFailure {
+    fn is_default(&self) -> bool {
+        *self == HookFailure::default()
+    }
+}
+
+/// What pausing the supervisor does to a process.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum PauseMode {
+    /// SIGSTOP/SIGCONT on Unix; stops and holds on Windows.
+    #[default]
+    Suspend,
+    /// Stops the process and starts it again on resume.
+    Stop,
+    /// Keeps running.
+    Ignore,
+}
+
+impl PauseMode {
+    fn is_default(&self) -> bool {
+        *self == PauseMode::default()
+    }
+}
+
+/// What a supervisor does with children that outlived its previous run.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum OrphanPolicy {
+    /// Stops them with their `stop_signal` before starting fresh copies.
+    #[default]
+    Terminate,
+    /// Watches them until they exit, then restarts as usual. Their output is lost, and one
+    /// that doesn't ignore SIGPIPE dies the next time it writes to stdout.
+    Adopt,
+}
+
+/// Replaced with the instance number in `args` and `env` when `numprocs` > 1; as
+/// `{instance:02}` it is padded with zeros to two digits.
+pub const INSTANCE_PLACEHOLDER: &str = "{instance}";
+
+/// Replaces each `{instance}` and `{instance:0N}` in `text` with `with(N)`, `with(0)` for the
+/// unpadded one.
+pub fn replace_instance(text: &str, mut with: impl FnMut(usize) -> String) -> String {
+    let mut result = String::new();
+    let mut rest = text;
+    while let Some(start) = rest.find("{instance") {
+        result.push_str(&rest[..start]);
+        let after = &rest[start + "{instance".len()..];
+        let placeholder = match after.strip_prefix(":0") {
+            Some(spec) => spec.split_once('}').and_then(|(digits, _)| {
+                let width = digits.parse::<usize>().ok()?;
+                Some((width, ":0}".len() + digits.len()))
+            }),
+            None => after.starts_with('}').then_some((0, 1)),
+        };
+        match placeholder {
+            Some((width, len)) => {
+                result.push_str(&with(width));
+                rest = &after[len..];
+            }
+            None => {
+                result.push_str("{instance");
+                rest = after;
+            }
+        }
+    }
+    result.push_str(rest);
+    result
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct ProcessConfig {
+    /// Unique name, defaults to the program file name without extension.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub name: String,
     pub program: String,
     pub args: Vec<String>,
     pub cwd: String,
     pub state: ProcessConfigState,
     pub pid: u32,
+    /// Pause between an exit and the restart.
+    #[serde(default, skip_serializing_if = "HumanDuration::is_zero")]
+    pub restart_delay: HumanDuration,
+    #[serde(default, skip_serializing_if = "RestartPolicy::is_always")]
+    pub restart: RestartPolicy,
+    /// Doubles `restart_delay` (a second if there's none) for each failure in a row, up to
+    /// this; a run that lasts longer starts over.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub restart_backoff: Option<HumanDuration>,
+    /// Exits that count as success, e.g. `[0, 2]`; signal names such as `"SIGTERM"` match a
+    /// process killed by it.
+    #[serde(
+        default = "default_success_exit_codes",
+        skip_serializing_if = "is_default_success_exit_codes"
+    )]
+    pub success_exit_codes: Vec<ExitMatch>,
+    /// Temporary failures, e.g. `75`, restarted whatever `restart` says.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub restart_on_exit_codes: Vec<ExitMatch>,
+    /// Failures a restart won't fix, e.g. `78` for a configuration error; the process is left
+    /// `FATAL` until restarted by hand.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub fatal_exit_codes: Vec<ExitMatch>,
+    /// Restarts the process gracefully once it has run this long, e.g. `"12h"` for one that
+    /// leaks memory; each run gets up to a tenth less at random, so copies don't restart
+    /// together.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_lifetime: Option<HumanDuration>,
+    /// Restarts the process gracefully on a schedule, e.g. `"0 3 * * *"` in local time.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub restart_schedule: Option<Cron>,
+    /// Variables added to the supervisor's environment.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub env: BTreeMap<String, String>,
+    /// Sent on stop before falling back to a kill after `stop_timeout` (Unix only).
+    #[serde(default = "default_stop_signal")]
+    pub stop_signal: String,
+    #[serde(default = "default_stop_timeout")]
+    pub stop_timeout: HumanDuration,
+    /// Number of copies to run, named `name:0`, `name:1`...
+    #[serde(default = "default_numprocs", skip_serializing_if = "is_one")]
+    pub numprocs: u32,
+    /// Names of processes started before this one.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub depends_on: Vec<String>,
+    #[serde(default, skip_serializing_if = "PauseMode::is_default")]
+    pub pause_mode: PauseMode,
+    /// Last lines of stdout/stderr kept for crash reports.
+    #[serde(
+        default = "default_output_lines",
+        skip_serializing_if = "is_default_output_lines"
+    )]
+    pub output_lines: usize,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub watchdog: Option<WatchdogConfig>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub limits: Option<LimitsConfig>,
+    /// Account to run as, by name or uid; HOME, USER and LOGNAME follow it (Linux only).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,
+    /// [default: the primary group of `user`]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub group: Option<String>,
+    /// Supplementary groups [default: those `user` is a member of].
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub groups: Vec<String>,
+    /// Runs the program on a schedule, or once, instead of keeping it running.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub job: Option<JobConfig>,
+    /// Restarts the process when files under these paths change (Linux only).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub watch: Option<WatchConfig>,
+    /// Listening sockets bound by the supervisor and passed as fds 3 and up, per systemd's
+    /// LISTEN_FDS; they stay open across restarts (Linux only).
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub sockets: Vec<SocketConfig>,
+    /// Starts the process on the first connection to its `sockets` rather than right away,
+    /// and again on the next one after it exits.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub lazy: bool,
+    /// When a started process counts as running rather than still starting.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ready: Option<ReadyConfig>,
+    /// Named actions run with `servicers action`, e.g. `reload = "SIGHUP"`.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub actions: BTreeMap<String, Action>,
+    /// Commands run in order before each start, e.g. `[["mkdir", "-p", "/run/app"]]`, as the
+    /// process with its `cwd` and `env`; the first that fails ends the hook.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub pre_start: Vec<Vec<String>>,
+    /// Run once the process is running, after `ready` if given, with SERVICERS_PID set.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub post_start: Vec<Vec<String>>,
+    /// Run before the supervisor stops the process, with SERVICERS_PID set.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub pre_stop: Vec<Vec<String>>,
+    /// Run after every run ends, however it ended.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub post_stop: Vec<Vec<String>>,
+    /// How long each hook command has to succeed.
+    #[serde(
+        default = "default_hook_timeout",
+        skip_serializing_if = "is_default_hook_timeout"
+    )]
+    pub hook_timeout: HumanDuration,
+    #[serde(default, skip_serializing_if = "HookFailure::is_default")]
+    pub pre_start_failure: HookFailure,
 }
 
-impl ProcessConfig {
-    pub fn _new(program: String, args: Vec<String>, cwd: String) -> ProcessConfig {
+/// Applied to the process before it starts (Linux only). One that can't be applied fails
+/// the start instead of being skipped.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LimitsConfig {
+    /// RLIMIT_NOFILE, soft and hard.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub open_files: Option<u64>,
+    /// RLIMIT_CORE; 0 disables core dumps.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub core_size: Option<ByteSize>,
+    /// RLIMIT_AS.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub address_space: Option<ByteSize>,
+    /// RLIMIT_NPROC, counted per user.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub processes: Option<u64>,
+    /// -20 (highest priority) to 19.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nice: Option<i32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub io_class: Option<IoClass>,
+    /// 0 (highest) to 7 within `io_class`, best effort by default.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub io_priority: Option<u8>,
+    /// Runs the process in its own cgroup v2 under `service.cgroup_root`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cgroup: Option<CgroupConfig>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum IoClass {
+    Realtime,
+    BestEffort,
+    Idle,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CgroupConfig {
+    /// memory.max
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub memory_max: Option<ByteSize>,
+    /// cpu.max as percent of one core, e.g. 150 for one and a half.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cpu_max: Option<f64>,
+}
+
+/// Restarts a process whose tree uses too much memory or CPU (Linux only).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WatchdogConfig {
+    /// Total resident memory of the process and its descendants.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_memory: Option<ByteSize>,
+    /// Percent of one core, e.g. 150 for one and a half.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_cpu: Option<f64>,
+    /// How long `max_cpu` has to be exceeded.
+    #[serde(default = "default_cpu_window")]
+    pub cpu_window: HumanDuration,
+    /// Time between samples.
+    #[serde(default = "default_watchdog_interval")]
+    pub interval: HumanDuration,
+}
+
+/// A program run by the supervisor on `cron` or `every`. With neither it runs once at
+/// startup, and its dependents start after it finishes; with `restart = "ON_FAILURE"` only
+/// once it has succeeded.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct JobConfig {
+    /// e.g. `"*/15 * * * *"` or `"@daily"`, in local time.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cron: Option<Cron>,
+    /// Time between runs, the first one after startup.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub every: Option<HumanDuration>,
+    /// Stops a run that takes longer, as a failure.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeout: Option<HumanDuration>,
+    #[serde(default, skip_serializing_if = "OverlapPolicy::is_default")]
+    pub overlap: OverlapPolicy,
+}
+
+impl JobConfig {
+    pub fn is_once(&self) -> bool {
+        self.cron.is_none() && self.every.is_none()
+    }
+}
+
+/// What happens when a job is due while its previous run is still going.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum OverlapPolicy {
+    #[default]
+    Skip,
+    /// Runs once more right after it; further runs due meanwhile are skipped.
+    Queue,
+    /// Stops the previous run and starts a new one.
+    Kill,
+}
+
+/// Conditions for a started process to count as running; all the given ones have to hold.
+/// Until then its dependents wait, and so does the supervisor before reporting it started.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ReadyConfig {
+    /// A TCP port accepting connections, on localhost unless given as `"host:port"`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub port: Option<ReadyPort>,
+    /// A regular expression matching a line of stdout.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stdout: Option<String>,
+    /// A file the process creates or modifies once it's ready, e.g. a pid file; relative to
+    /// `cwd`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub file: Option<String>,
+    /// How long the process has to keep running.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_uptime: Option<HumanDuration>,
+    /// A process that isn't ready by then is stopped as a failed start.
+    #[serde(default = "default_ready_timeout")]
+    pub timeout: HumanDuration,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum ReadyPort {
+    Number(u16),
+    Address(String),
+}
+
+impl Display for ReadyPort {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReadyPort::Number(port) => write!(f, "localhost:{}", port),
+            ReadyPort::Address(address) => f.write_str(address),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct SocketConfig {
+    /// `"127.0.0.1:9123"`, `"[::]:80"`, or the path of a Unix socket such as
+    /// `"/run/app.sock"`.
+    pub listen: String,
+    /// Its LISTEN_FDNAMES entry [default: the process name].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// Also passed as standard input, where FastCGI programs such as php-cgi expect it.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub stdin: bool,
+}
+
+/// Files whose changes restart the process, e.g. its configuration during development.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WatchConfig {
+    /// Files, or directories watched with everything under them; relative to `cwd`.
+    pub paths: Vec<String>,
+    /// Globs such as `"*.ini"` or `"conf/**/*.php"` over paths relative to the watched
+    /// one; one without a `/` matches any file or directory name [default: every file].
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub include: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub exclude: Vec<String>,
+    /// How long changes have to stop before the restart, so a burst of them restarts once.
+    #[serde(default = "default_debounce")]
+    pub debounce: HumanDuration,
+}
+
+impl OverlapPolicy {
+    fn is_default(&self) -> bool {
+        *self == OverlapPolicy::default()
+    }
+}
+
+fn default_ready_timeout() -> HumanDuration {
+    HumanDuration::from_secs(60)
+}
+
+fn default_debounce() -> HumanDuration {
+    HumanDuration::from_millis(500)
+}
+
+fn default_cpu_window() -> HumanDuration {
+    HumanDuration::from_secs(60)
+}
+
+fn default_watchdog_interval() -> HumanDuration {
+    HumanDuration::from_secs(5)
+}
+
+fn default_stop_signal() -> String {
+    "TERM".to_string()
+}
+
+fn default_stop_timeout() -> HumanDuration {
+    HumanDuration::from_secs(10)
+}
+
+fn default_output_lines() -> usize {
+    100
+}
+
+fn is_default_output_lines(value: &usize) -> bool {
+    *value == default_output_lines()
+}
+
+fn default_numprocs() -> u32 {
+    1
+}
+
+fn default_hook_timeout() -> HumanDuration {
+    HumanDuration::from_secs(30)
+}
+
+fn is_default_hook_timeout(timeout: &HumanDuration) -> bool {
+    *timeout == default_hook_timeout()
+}
+
+fn default_success_exit_codes() -> Vec<ExitMatch> {
+    vec![ExitMatch::Code(0)]
+}
+
+fn is_default_success_exit_codes(codes: &Vec<ExitMatch>) -> bool {
+    *codes == default_success_exit_codes()
+}
+
+fn is_one(value: &u32) -> bool {
+    *value == 1
+}
+
+impl Default for ProcessConfig {
+    fn default() -> Self {
         ProcessConfig {
-            program: program,
-            args: args,
-            cwd: cwd,
+            name: String::new(),
+            program: String::new(),
+            args: vec![],
+            cwd: String::new(),
             state: ProcessConfigState::Enabled,
             pid: 0,
+            restart_delay: HumanDuration::default(),
+            restart: RestartPolicy::default(),
+            restart_backoff: None,
+            success_exit_codes: default_success_exit_codes(),
+            restart_on_exit_codes: vec![],
+            fatal_exit_codes: vec![],
+            max_lifetime: None,
+            restart_schedule: None,
+            env: BTreeMap::new(),
+            stop_signal: default_stop_signal(),
+            stop_timeout: default_stop_timeout(),
+            numprocs: default_numprocs(),
+            depends_on: vec![],
+            pause_mode: PauseMode::default(),
+            output_lines: default_output_lines(),
+            watchdog: None,
+            limits: None,
+            user: None,
+            group: None,
+            groups: vec![],
+            job: None,
+            watch: None,
+            sockets: vec![],
+            lazy: false,
+            ready: None,
+            actions: BTreeMap::new(),
+            pre_start: vec![],
+            post_start: vec![],
+            pre_stop: vec![],
+            post_stop: vec![],
+            hook_timeout: default_hook_timeout(),
+            pre_start_failure: HookFailure::default(),
+        }
+    }
+}
+
+impl ProcessConfig {
+    pub fn _new(program: String, args: Vec<String>, cwd: String) -> ProcessConfig {
+        let mut config = ProcessConfig {
+            program,
+            args,
+            cwd,
+            ..Default::default()
+        };
+        config.fill_name();
+        config
+    }
+
+    /// What `status` means for this process, per `fatal_exit_codes`, then
+    /// `restart_on_exit_codes`, then `success_exit_codes`.
+    pub fn classify(&self, status: &std::process::ExitStatus) -> ExitClass {
+        let matches = |codes: &[ExitMatch]| codes.iter().any(|code| code.matches(status));
+        if matches(&self.fatal_exit_codes) {
+            ExitClass::Fatal
+        } else if matches(&self.restart_on_exit_codes) {
+            ExitClass::Temporary
+        } else if matches(&self.success_exit_codes) {
+            ExitClass::Success
+        } else {
+            ExitClass::Failure
         }
     }
 
     pub fn is_valid(&self) -> bool {
-        self.program.len() > 0 && self.state != ProcessConfigState::Disabled
+        !self.program.is_empty() && self.state != ProcessConfigState::Disabled
+    }
+
+    /// Copies to run for `numprocs`, with `{instance}` substituted in `args`, `env` and hooks,
+    /// and `SERVICERS_INSTANCE` set.
+    pub fn instances(&self) -> Vec<ProcessConfig> {
+        if self.numprocs <= 1 {
+            return vec![self.clone()];
+        }
+
+        (0..self.numprocs)
+            .map(|n| {
+                let number = n.to_string();
+                let mut instance = self.clone();
+                instance.name = format!("{}:{}", self.name, n);
+                instance.numprocs = 1;
+                let hooks = [
+                    &mut instance.pre_start,
+                    &mut instance.post_start,
+                    &mut instance.pre_stop,
+                    &mut instance.post_stop,
+                ];
+                let hook_args = hooks.into_iter().flatten().flatten();
+                let expand = |text: &str| {
+                    replace_instance(text, |width| format!("{:0width$}", n, width = width))
+                };
+                for arg in instance.args.iter_mut().chain(hook_args) {
+                    *arg = expand(arg);
+                }
+                for value in instance.env.values_mut() {
+                    *value = expand(value);
+                }
+                instance
+                    .env
+                    .insert("SERVICERS_INSTANCE".to_string(), number);
+                instance
+            })
+            .collect()
     }
 
     pub fn spawn_new(&self) -> Result<Child, std::io::Error> {
-        Command::new(&self.program)
+        let account = users::account(self)
+            .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidInput, err))?;
+        let mut command = Command::new(&self.program);
+        command
             .args(&self.args)
+            // Meant for the supervisor, not its children
+            .env_remove("NOTIFY_SOCKET")
+            .env_remove("WATCHDOG_USEC")
+            .env_remove("WATCHDOG_PID");
+        if let Some(account) = &account {
+            command
+                .env("HOME", &account.home)
+                .env("USER", &account.name)
+                .env("LOGNAME", &account.name);
+        }
+        command
+            .envs(&self.env)
             .current_dir(&self.cwd)
             .stdout(Stdio::piped())
-            .stderr(Stdio::piped())
-            .spawn()
+            .stderr(Stdio::piped());
+        let prepared = match self.limits.is_none() && account.is_none() {
+            true => None,
+            false => {
+                let limits = self.limits.clone().unwrap_or_default();
+                Some(limits::prepare(
+                    &mut command,
+                    &self.name,
+                    &limits,
+                    account.as_ref(),
+                )?)
+            }
+        };
+        // Last, as it execs the program itself
+        let passed = sockets::pass(&mut command, self)?;
+        let child = command.spawn().map_err(|err| match &prepared {
+            Some(prepared) => prepared.explain(err),
+            None => err,
+        })?;
+        match passed {
+            Some(passed) => passed.check(child),
+            None => Ok(child),
+        }
+    }
+
+    fn fill_name(&mut self) {
+        if self.name.is_empty() {
+            // Split by hand: configs with Windows paths are also read on Linux.
+            let file_name = self.program.rsplit(['/', '\\']).next().unwrap_or_default();
+            self.name = match file_name.rsplit_once('.') {
+                Some((stem, _)) if !stem.is_empty() => stem.to_string(),
+                _ => file_name.to_string(),
+            };
+        }
     }
 }
 
-pub fn load() -> Vec<ProcessConfig> {
-    let mut file_path = std::env::current_exe().unwrap();
-    file_path.pop();
-    file_path.push("servicers.json");
-    if !file_path.exists() {
-        create_default().unwrap();
+/// Top-level table of a config file. JSON and YAML files may also be a bare list of
+/// processes; TOML can't, so there it is `[[processes]]`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    #[serde(default)]
+    pub processes: Vec<ProcessConfig>,
+    #[serde(default)]
+    pub log: LogConfig,
+    #[serde(default)]
+    pub service: ServiceConfig,
+    #[serde(default)]
+    pub journal: JournalConfig,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LogConfig {
+    /// `servicers.log` starts over once it grows past this.
+    pub max_size: ByteSize,
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        LogConfig {
+            max_size: ByteSize(10240),
+        }
+    }
+}
+
+/// Retention of `servicers.journal`; the oldest events go first.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct JournalConfig {
+    pub max_age: HumanDuration,
+    pub max_size: ByteSize,
+}
+
+impl Default for JournalConfig {
+    fn default() -> Self {
+        JournalConfig {
+            max_age: HumanDuration::from_secs(30 * 24 * 3600),
+            max_size: ByteSize(1 << 20),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServiceConfig {
+    /// How often supervised processes and services are checked.
+    pub poll_interval: HumanDuration,
+    /// How long start/stop/pause/resume wait for a service to change state.
+    pub status_wait: HumanDuration,
+    /// Where the supervisor listens for commands: a Unix socket path, or a named pipe on
+    /// Windows [default: servicers.sock in the log directory, `\\.\pipe\servicers` on Windows].
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub control_address: Option<String>,
+    /// Survivors of a supervisor crash, found through the state file (Linux only).
+    pub orphans: OrphanPolicy,
+    /// Delegated cgroup v2 directory for `limits.cgroup` [default: the supervisor's own].
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cgroup_root: Option<PathBuf>,
+    /// Signals the supervisor passes on to every running process, e.g. `["USR1"]` to have
+    /// them all reopen their logs (Unix only). HUP, INT and TERM are its own.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub forward_signals: Vec<String>,
+}
+
+impl ServiceConfig {
+    pub fn control_address(&self) -> String {
+        match &self.control_address {
+            Some(address) => address.clone(),
+            #[cfg(unix)]
+            None => crate::logger::log_dir()
+                .join("servicers.sock")
+                .to_string_lossy()
+                .into_owned(),
+            #[cfg(not(unix))]
+            None => r"\\.\pipe\servicers".to_string(),
+        }
+    }
+}
+
+impl Default for ServiceConfig {
+    fn default() -> Self {
+        ServiceConfig {
+            poll_interval: HumanDuration::from_millis(100),
+            status_wait: HumanDuration::from_secs(1),
+            control_address: None,
+            orphans: OrphanPolicy::default(),
+            cgroup_root: None,
+            forward_signals: vec![],
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct ConfigError {
+    pub path: PathBuf,
+    pub message: String,
+}
+
+impl ConfigError {
+    fn new<T: Display>(path: &Path, message: T) -> ConfigError {
+        ConfigError {
+            path: path.to_path_buf(),
+            message: message.to_string(),
+        }
     }
+}
 
-    let mut vec: Vec<ProcessConfig> = match File::open(file_path) {
-        Ok(file) => {
-            let mut reader = BufReader::new(file);
+impl Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.path.display(), self.message)
+    }
+}
 
-            let mut text = String::new();
-            reader.read_to_string(&mut text).unwrap();
+impl std::error::Error for ConfigError {}
 
-            let sad = serde_json::from_str(&text).unwrap();
-            sad
+/// Overrides the config file location (`servicers.json` next to the executable by default).
+pub fn set_config_path(path: PathBuf) {
+    *CONFIG_PATH.lock().unwrap() = Some(path);
+}
+
+/// Without an override, the first of `servicers.json`, `.toml`, `.yaml` or `.yml`
+/// next to the executable, falling back to `servicers.json`.
+pub fn config_path() -> PathBuf {
+    match CONFIG_PATH.lock().unwrap().as_ref() {
+        Some(path) => path.clone(),
+        None => {
+            let mut file_path = std::env::current_exe().unwrap();
+            file_path.pop();
+            file_path.push("servicers.json");
+
+            ["json", "toml", "yaml", "yml"]
+                .iter()
+                .map(|ext| file_path.with_extension(ext))
+                .find(|path| path.exists())
+                .unwrap_or(file_path)
         }
-        Err(err) => {
-            log!("{:?}", &err);
-            Vec::<ProcessConfig>::new()
+    }
+}
+
+/// Directory with per-process fragments for `path`: `servicers.json` -> `servicers.d`.
+pub fn fragment_dir(path: &Path) -> PathBuf {
+    path.with_extension("d")
+}
+
+pub fn load() -> Result<Config, ConfigError> {
+    load_from(&config_path(), std::env::vars())
+}
+
+/// Merges, in order: built-in processes, the config file, fragments from its `.d`
+/// directory in lexical order, and `SERVICERS__<NAME>__<FIELD>` variables from `env`.
+/// Entries with the same name replace earlier ones. Sections other than `processes`
+/// are only read from the config file itself.
+pub fn load_from<I>(file_path: &Path, env: I) -> Result<Config, ConfigError>
+where
+    I: IntoIterator<Item = (String, String)>,
+{
+    if !file_path.exists() {
+        if let Err(err) = create_default(file_path) {
+            log!("Can't create default config {:?}: {:?}", file_path, &err);
+        }
+    }
+
+    let mut config = Config {
+        processes: builtin(),
+        ..Default::default()
+    };
+    if file_path.exists() {
+        let document = read_document(file_path)?;
+        config.log = document.log;
+        config.service = document.service;
+        config.journal = document.journal;
+        merge(&mut config.processes, document.processes, file_path)?;
+    }
+
+    let dir = fragment_dir(file_path);
+    if dir.is_dir() {
+        let mut fragments = std::fs::read_dir(&dir)
+            .and_then(|entries| {
+                entries
+                    .map(|entry| entry.map(|e| e.path()))
+                    .collect::<std::io::Result<Vec<PathBuf>>>()
+            })
+            .map_err(|err| ConfigError::new(&dir, err))?;
+        fragments.retain(|path| Format::from_path(path).is_some());
+        fragments.sort();
+
+        for fragment in fragments {
+            merge(
+                &mut config.processes,
+                read_document(&fragment)?.processes,
+                &fragment,
+            )?;
         }
+    }
+
+    apply_env_overrides(&mut config.processes, env)?;
+    validate(&config).map_err(|message| ConfigError::new(file_path, message))?;
+
+    Ok(config)
+}
+
+/// Reads a config file as a document, whatever its top-level shape.
+pub fn read_document(file_path: &Path) -> Result<Config, ConfigError> {
+    let format = Format::from_path(file_path).ok_or_else(|| {
+        ConfigError::new(
+            file_path,
+            "unknown config format, expected .json, .toml or .yaml",
+        )
+    })?;
+
+    let mut text = String::new();
+    File::open(file_path)
+        .and_then(|file| BufReader::new(file).read_to_string(&mut text))
+        .map_err(|err| ConfigError::new(file_path, err))?;
+
+    let result = match format.shape(&text) {
+        Ok(Shape::List) => format.parse(&text).map(|processes| Config {
+            processes,
+            ..Default::default()
+        }),
+        Ok(Shape::Document) => format.parse(&text),
+        Ok(Shape::Process) => format.parse(&text).map(|config| Config {
+            processes: vec![config],
+            ..Default::default()
+        }),
+        Err(err) => Err(err),
     };
+    result.map_err(|err| ConfigError::new(file_path, err))
+}
 
-    vec.push(ProcessConfig {
-        program: NGINX_PATH.to_string(),
-        args: vec![],
-        cwd: NGINX_CWD.to_string(),
-        state: ProcessConfigState::Enabled,
-        pid: 0,
-    });
-    vec.push(ProcessConfig {
-        program: "C:/php/8.1.8/php-cgi.exe".to_string(),
-        args: vec!["-b".to_string(), "localhost:9123".to_string()],
-        cwd: "C:/nginx/html".to_string(),
-        state: ProcessConfigState::Enabled,
-        pid: 0,
-    });
+fn merge(
+    list: &mut Vec<ProcessConfig>,
+    layer: Vec<ProcessConfig>,
+    file_path: &Path,
+) -> Result<(), ConfigError> {
+    let mut seen = HashSet::new();
+
+    for mut config in layer {
+        config.fill_name();
+
+        if config.name.is_empty() {
+            list.push(config);
+            continue;
+        }
+        if !seen.insert(config.name.clone()) {
+            return Err(ConfigError::new(
+                file_path,
+                format!(
+                    "duplicate process name {:?}, set `name` explicitly",
+                    config.name
+                ),
+            ));
+        }
+
+        match list
+            .iter_mut()
+            .find(|existing| existing.name == config.name)
+        {
+            Some(existing) => *existing = config,
+            None => list.push(config),
+        }
+    }
+
+    Ok(())
+}
+
+fn env_key(name: &str) -> String {
+    name.chars()
+        .map(|c| match c.is_ascii_alphanumeric() {
+            true => c.to_ascii_uppercase(),
+            false => '_',
+        })
+        .collect()
+}
+
+/// Values are parsed as JSON when possible (`["-b", "localhost:9123"]`, `8080`),
+/// anything else is taken as a plain string (`DISABLED`, `C:/nginx`).
+fn apply_env_overrides<I>(list: &mut [ProcessConfig], env: I) -> Result<(), ConfigError>
+where
+    I: IntoIterator<Item = (String, String)>,
+{
+    let mut overrides: Vec<(String, String)> = env
+        .into_iter()
+        .filter(|(key, _)| key.starts_with(ENV_OVERRIDE_PREFIX))
+        .collect();
+    overrides.sort();
+
+    for (key, value) in overrides {
+        let source = PathBuf::from(format!("${}", key));
+        let (name, field) = match key[ENV_OVERRIDE_PREFIX.len()..].split_once("__") {
+            Some((name, field)) if !name.is_empty() && !field.is_empty() => {
+                (name.to_string(), field.to_ascii_lowercase())
+            }
+            _ => {
+                return Err(ConfigError::new(
+                    &source,
+                    "expected SERVICERS__<NAME>__<FIELD>",
+                ))
+            }
+        };
+
+        let config = list
+            .iter_mut()
+            .find(|config| !config.name.is_empty() && env_key(&config.name) == name)
+            .ok_or_else(|| ConfigError::new(&source, format!("no process named {:?}", name)))?;
+
+        let mut object =
+            serde_json::to_value(&*config).map_err(|err| ConfigError::new(&source, err))?;
+        let value = serde_json::from_str(&value).unwrap_or(Value::String(value));
+        object[field.as_str()] = value;
+
+        *config = serde_json::from_value(object).map_err(|err| ConfigError::new(&source, err))?;
+    }
+
+    Ok(())
+}
+
+fn validate(config: &Config) -> Result<(), String> {
+    for signal in &config.service.forward_signals {
+        match crate::signals::parse(signal) {
+            None => {
+                return Err(format!(
+                    "service: unknown signal {:?} in forward_signals",
+                    signal
+                ))
+            }
+            Some(number) if crate::signals::is_reserved(number) => {
+                return Err(format!("service: {} can't be forwarded", signal))
+            }
+            Some(_) => (),
+        }
+    }
+    for process in &config.processes {
+        if crate::signals::parse(&process.stop_signal).is_none() {
+            return Err(format!(
+                "{}: unknown stop_signal {:?}",
+                process.name, process.stop_signal
+            ));
+        }
+        let exit_codes = [
+            ("success_exit_codes", &process.success_exit_codes),
+            ("restart_on_exit_codes", &process.restart_on_exit_codes),
+            ("fatal_exit_codes", &process.fatal_exit_codes),
+        ];
+        for (i, (key, codes)) in exit_codes.iter().enumerate() {
+            for code in codes.iter() {
+                if let ExitMatch::Signal(name) = code {
+                    if crate::signals::parse(name).is_none() {
+                        return Err(format!(
+                            "{}: unknown signal {:?} in {}",
+                            process.name, name, key
+                        ));
+                    }
+                }
+                if let Some((other, _)) = exit_codes[i + 1..].iter().find(|(_, c)| c.contains(code))
+                {
+                    return Err(format!(
+                        "{}: {} is in both {} and {}",
+                        process.name, code, key, other
+                    ));
+                }
+            }
+        }
+        for (name, action) in &process.actions {
+            match action {
+                Action::Signal(signal) if crate::signals::parse(signal).is_none() => {
+                    return Err(format!(
+                        "{}: unknown signal {:?} in action {}",
+                        process.name, signal, name
+                    ));
+                }
+                Action::Command(command) if command.is_empty() => {
+                    return Err(format!("{}: action {} has no command", process.name, name));
+                }
+                _ => (),
+            }
+        }
+        let hooks = [
+            ("pre_start", &process.pre_start),
+            ("post_start", &process.post_start),
+            ("pre_stop", &process.pre_stop),
+            ("post_stop", &process.post_stop),
+        ];
+        for (hook, commands) in hooks {
+            if commands.iter().any(Vec::is_empty) {
+                return Err(format!("{}: empty command in {}", process.name, hook));
+            }
+        }
+        if process.hook_timeout.is_zero() {
+            return Err(format!("{}: hook_timeout must not be zero", process.name));
+        }
+        if process
+            .max_lifetime
+            .is_some_and(|lifetime| lifetime.is_zero())
+        {
+            return Err(format!("{}: max_lifetime must not be zero", process.name));
+        }
+        let planned = process.max_lifetime.is_some() || process.restart_schedule.is_some();
+        if process.job.is_some() && planned {
+            return Err(format!(
+                "{}: max_lifetime and restart_schedule don't apply to jobs, see job.timeout",
+                process.name
+            ));
+        }
+        if process
+            .restart_backoff
+            .is_some_and(|backoff| backoff.is_zero())
+        {
+            return Err(format!(
+                "{}: restart_backoff must not be zero",
+                process.name
+            ));
+        }
+        if process.numprocs == 0 {
+            return Err(format!("{}: numprocs must be at least 1", process.name));
+        }
+        if let Some(watchdog) = &process.watchdog {
+            if watchdog.max_cpu.is_some_and(|cpu| cpu <= 0.0) {
+                return Err(format!(
+                    "{}: watchdog.max_cpu must be positive",
+                    process.name
+                ));
+            }
+            if watchdog.interval.is_zero() {
+                return Err(format!(
+                    "{}: watchdog.interval must not be zero",
+                    process.name
+                ));
+            }
+        }
+        if process
+            .watch
+            .as_ref()
+            .is_some_and(|watch| watch.paths.is_empty())
+        {
+            return Err(format!("{}: watch.paths must not be empty", process.name));
+        }
+        if process.lazy && process.sockets.is_empty() {
+            return Err(format!("{}: lazy needs sockets to wait on", process.name));
+        }
+        if process.sockets.iter().filter(|socket| socket.stdin).count() > 1 {
+            return Err(format!("{}: only one socket can be stdin", process.name));
+        }
+        if let Some(ready) = &process.ready {
+            if let Some(pattern) = &ready.stdout {
+                regex::Regex::new(pattern)
+                    .map_err(|err| format!("{}: invalid ready.stdout: {}", process.name, err))?;
+            }
+            if ready.timeout.is_zero() {
+                return Err(format!("{}: ready.timeout must not be zero", process.name));
+            }
+        }
+        if let Some(job) = &process.job {
+            if job.cron.is_some() && job.every.is_some() {
+                return Err(format!(
+                    "{}: job.cron and job.every are exclusive",
+                    process.name
+                ));
+            }
+            if job.every.is_some_and(|every| every.is_zero()) {
+                return Err(format!("{}: job.every must not be zero", process.name));
+            }
+        }
+        users::check(process).map_err(|err| format!("{}: {}", process.name, err))?;
+        if let Some(limits) = &process.limits {
+            if limits.nice.is_some_and(|nice| !(-20..=19).contains(&nice)) {
+                return Err(format!(
+                    "{}: limits.nice must be from -20 to 19",
+                    process.name
+                ));
+            }
+            if limits.io_priority.is_some_and(|priority| priority > 7) {
+                return Err(format!(
+                    "{}: limits.io_priority must be from 0 to 7",
+                    process.name
+                ));
+            }
+            if let Some(cpu_max) = limits.cgroup.as_ref().and_then(|cgroup| cgroup.cpu_max) {
+                if cpu_max <= 0.0 {
+                    return Err(format!(
+                        "{}: limits.cgroup.cpu_max must be positive",
+                        process.name
+                    ));
+                }
+            }
+            if limits.cgroup.is_some() && process.name == crate::limits::SUPERVISOR_CGROUP {
+                return Err(format!(
+                    "{}: the name is reserved for the supervisor's own cgroup",
+                    process.name
+                ));
+            }
+        }
+        for dependency in &process.depends_on {
+            if !config.processes.iter().any(|p| &p.name == dependency) {
+                return Err(format!(
+                    "{}: depends on unknown process {:?}",
+                    process.name, dependency
+                ));
+            }
+        }
+    }
+    start_order(&config.processes).map(|_| ())
+}
+
+/// Indexes of `processes` with every process after its `depends_on`.
+pub fn start_order(processes: &[ProcessConfig]) -> Result<Vec<usize>, String> {
+    fn visit(
+        index: usize,
+        processes: &[ProcessConfig],
+        visiting: &mut Vec<usize>,
+        order: &mut Vec<usize>,
+    ) -> Result<(), String> {
+        if order.contains(&index) {
+            return Ok(());
+        }
+        if visiting.contains(&index) {
+            return Err(format!("{}: dependency cycle", processes[index].name));
+        }
+
+        visiting.push(index);
+        for dependency in &processes[index].depends_on {
+            if let Some(position) = processes.iter().position(|p| &p.name == dependency) {
+                visit(position, processes, visiting, order)?;
+            }
+        }
+        visiting.pop();
+        order.push(index);
+        Ok(())
+    }
+
+    let mut order = vec![];
+    for index in 0..processes.len() {
+        visit(index, processes, &mut vec![], &mut order)?;
+    }
+    Ok(order)
+}
+
+#[cfg(windows)]
+fn builtin() -> Vec<ProcessConfig> {
+    vec![
+        ProcessConfig::_new(NGINX_PATH.to_string(), vec![], NGINX_CWD.to_string()),
+        ProcessConfig::_new(
+            "C:/php/8.1.8/php-cgi.exe".to_string(),
+            vec!["-b".to_string(), "localhost:9123".to_string()],
+            "C:/nginx/html".to_string(),
+        ),
+    ]
+}
 
-    vec
+#[cfg(not(windows))]
+fn builtin() -> Vec<ProcessConfig> {
+    vec![]
 }
 
-fn create_default() -> std::io::Result<()> {
-    let text = serde_json::to_string_pretty(&vec![&ProcessConfig {
-        program: "".to_string(),
-        args: vec![],
-        cwd: "".to_string(),
-        state: ProcessConfigState::Enabled,
-        pid: 0,
-    }])?;
+fn create_default(file_path: &Path) -> std::io::Result<()> {
+    let processes = vec![ProcessConfig::default()];
+    let text = match Format::from_path(file_path).unwrap_or(Format::Json) {
+        Format::Json => serde_json::to_string_pretty(&processes)?,
+        format => format
+            .serialize(&Config {
+                processes,
+                ..Default::default()
+            })
+            .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidData, err))?,
+    };
 
-    let mut file_path = std::env::current_exe().unwrap();
-    file_path.pop();
-    file_path.push("servicers.json");
-    File::create(file_path)?.write_all(&text.as_bytes())?;
+    File::create(file_path)?.write_all(text.as_bytes())?;
     Ok(())
 }
 
 #[test]
 fn test_load() {
-    dbg!(load());
+    let dir = tempfile::tempdir().unwrap();
+    let file_path = dir.path().join("servicers.json");
+    std::fs::write(
+        &file_path,
+        r#"[
+            {"program": "/usr/sbin/nginx", "args": [], "cwd": "/", "state": "ENABLED", "pid": 0},
+            {"program": "/usr/bin/php-cgi", "args": [], "cwd": "/", "state": "ENABLED", "pid": 0}
+        ]"#,
+    )
+    .unwrap();
+    std::fs::create_dir(fragment_dir(&file_path)).unwrap();
+    std::fs::write(
+        fragment_dir(&file_path).join("10-php.json"),
+        r#"{"program": "/usr/bin/php-cgi", "args": ["-b", "localhost:9123"], "cwd": "/srv", "state": "ENABLED", "pid": 0}"#,
+    )
+    .unwrap();
+    std::fs::write(
+        fragment_dir(&file_path).join("20-worker.json"),
+        r#"[{"name": "worker", "program": "/usr/bin/php", "args": [], "cwd": "/srv", "state": "ENABLED", "pid": 0}]"#,
+    )
+    .unwrap();
+
+    let env = vec![
+        (
+            "SERVICERS__PHP_CGI__CWD".to_string(),
+            "/var/www".to_string(),
+        ),
+        (
+            "SERVICERS__NGINX__STATE".to_string(),
+            "DISABLED".to_string(),
+        ),
+        ("PATH".to_string(), "/bin".to_string()),
+    ];
+    let list = load_from(&file_path, env).unwrap().processes;
+    let names: Vec<&str> = list.iter().map(|c| c.name.as_str()).collect();
+
+    assert_eq!(names, ["nginx", "php-cgi", "worker"]);
+    assert_eq!(list[0].state, ProcessConfigState::Disabled);
+    assert_eq!(list[1].args, ["-b", "localhost:9123"]);
+    assert_eq!(list[1].cwd, "/var/www");
+
+    let env = vec![("SERVICERS__MISSING__CWD".to_string(), "/".to_string())];
+    assert!(load_from(&file_path, env).is_err());
+}
+
+#[test]
+fn test_formats() {
+    let dir = tempfile::tempdir().unwrap();
+
+    let toml_path = dir.path().join("servicers.toml");
+    std::fs::write(
+        &toml_path,
+        r#"
+[[processes]]
+program = 'C:\nginx\nginx.exe' # literal string, no escaping
+args = []
+cwd = 'C:\nginx'
+state = "ENABLED"
+pid = 0
+"#,
+    )
+    .unwrap();
+    let list = load_from(&toml_path, vec![]).unwrap().processes;
+    assert_eq!(list.last().unwrap().program, "C:\\nginx\\nginx.exe");
+
+    let yaml_path = dir.path().join("servicers.yaml");
+    std::fs::write(
+        &yaml_path,
+        "- program: /usr/bin/php-cgi\n  args: [-b, localhost:9123]\n  cwd: /srv\n  state: ENABLED\n  pid: 0\n",
+    )
+    .unwrap();
+    let list = load_from(&yaml_path, vec![]).unwrap().processes;
+    assert_eq!(list.last().unwrap().name, "php-cgi");
+
+    std::fs::write(
+        &yaml_path,
+        "processes:\n  - program: /bin/true\n    argz: []\n",
+    )
+    .unwrap();
+    let err = load_from(&yaml_path, vec![]).unwrap_err().to_string();
+    assert!(err.contains("argz") && err.contains("line 3"), "{}", err);
+
+    std::fs::write(&toml_path, "[[processes]]\nprogram = 1\n").unwrap();
+    let err = load_from(&toml_path, vec![]).unwrap_err().to_string();
+    assert!(err.contains("line 2"), "{}", err);
+}
+
+#[test]
+fn test_sections() {
+    let dir = tempfile::tempdir().unwrap();
+    let file_path = dir.path().join("servicers.yaml");
+    std::fs::write(
+        &file_path,
+        "processes:\n  - program: /bin/true\n    args: []\n    cwd: /\n    state: ENABLED\n    pid: 0\n    restart_delay: 5s\nlog:\n  max_size: 10MB\nservice:\n  poll_interval: 250ms\n",
+    )
+    .unwrap();
+    let config = load_from(&file_path, vec![]).unwrap();
+    assert_eq!(
+        config.processes[0].restart_delay,
+        HumanDuration::from_secs(5)
+    );
+    assert_eq!(config.log.max_size, ByteSize(10 << 20));
+    assert_eq!(
+        config.service.poll_interval,
+        HumanDuration::from_millis(250)
+    );
+    assert_eq!(config.service.status_wait, HumanDuration::from_secs(1));
+
+    std::fs::write(&file_path, "service:\n  status_wait: soon\n").unwrap();
+    let err = load_from(&file_path, vec![]).unwrap_err().to_string();
+    assert!(
+        err.contains("invalid duration") && err.contains("line 2"),
+        "{}",
+        err
+    );
+
+    let process = "processes:\n  - program: /bin/true\n    args: []\n    cwd: /\n    state: ENABLED\n    pid: 0\n";
+    std::fs::write(
+        &file_path,
+        format!(
+            "{}    success_exit_codes: [0, SIGTERM]\n    fatal_exit_codes: [78]\n",
+            process
+        ),
+    )
+    .unwrap();
+    let config = load_from(&file_path, vec![]).unwrap();
+    let codes = &config.processes[0].success_exit_codes;
+    assert_eq!(
+        codes,
+        &[ExitMatch::Code(0), ExitMatch::Signal("SIGTERM".to_string())]
+    );
+
+    std::fs::write(
+        &file_path,
+        format!(
+            "{}    restart_on_exit_codes: [75]\n    fatal_exit_codes: [75]\n",
+            process
+        ),
+    )
+    .unwrap();
+    let err = load_from(&file_path, vec![]).unwrap_err().to_string();
+    assert!(err.contains("75 is in both"), "{}", err);
+}
+
+#[test]
+fn test_instances() {
+    let mut config = ProcessConfig::_new(
+        "/usr/bin/php-cgi".to_string(),
+        vec!["-b".to_string(), "localhost:90{instance}".to_string()],
+        "/".to_string(),
+    );
+    assert_eq!(config.instances().len(), 1);
+
+    config.numprocs = 2;
+    config.post_stop = vec![vec![
+        "rm".to_string(),
+        "/run/php-{instance}.pid".to_string(),
+    ]];
+    config.env.insert(
+        "PORT".to_string(),
+        "90{instance:02} {instance:x}".to_string(),
+    );
+    let instances = config.instances();
+    assert_eq!(instances[1].name, "php-cgi:1");
+    assert_eq!(instances[1].args[1], "localhost:901");
+    assert_eq!(instances[1].env["PORT"], "9001 {instance:x}");
+    assert_eq!(instances[1].post_stop[0][1], "/run/php-1.pid");
+    assert_eq!(instances[1].env["SERVICERS_INSTANCE"], "1");
+}
+
+#[test]
+fn test_start_order() {
+    let mut web = ProcessConfig::_new("nginx".to_string(), vec![], "/".to_string());
+    let php = ProcessConfig::_new("php-cgi".to_string(), vec![], "/".to_string());
+    web.depends_on = vec!["php-cgi".to_string()];
+
+    let mut config = Config {
+        processes: vec![web, php],
+        ..Default::default()
+    };
+    assert_eq!(start_order(&config.processes), Ok(vec![1, 0]));
+
+    config.processes[1].depends_on = vec!["nginx".to_string()];
+    assert!(validate(&config).unwrap_err().contains("cycle"));
+}
+
+#[test]
+fn test_cgroup_name() {
+    let mut process = ProcessConfig::_new("sleep".to_string(), vec![], "/".to_string());
+    process.limits = Some(LimitsConfig {
+        cgroup: Some(CgroupConfig::default()),
+        ..Default::default()
+    });
+    let mut config = Config {
+        processes: vec![process],
+        ..Default::default()
+    };
+    assert_eq!(validate(&config), Ok(()));
+
+    // Would share the supervisor's leaf
+    config.processes[0].name = crate::limits::SUPERVISOR_CGROUP.to_string();
+    assert!(validate(&config).unwrap_err().contains("reserved"));
 }
@@ -0,0 +1,152 @@
+//! `ready`: when a started process counts as running. Each condition is checked on the
+//! supervision thread's polls, except `stdout`, matched by the output reader as lines come.
+
+use regex::Regex;
+use std::fs;
+use std::net::{TcpStream, ToSocketAddrs};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::output::LineWatch;
+use crate::proc_config::{ReadyConfig, ReadyPort};
+
+/// The `ready` conditions of one process, for its current run.
+pub struct Readiness {
+    config: ReadyConfig,
+    file: Option<PathBuf>,
+    stdout: Option<LineWatch>,
+    /// When the run started.
+    started: Instant,
+    /// The modification time `file` had then, so an old one doesn't count.
+    file_before: Option<SystemTime>,
+}
+
+impl Readiness {
+    pub fn new(config: &ReadyConfig, cwd: &Path) -> Result<Readiness, regex::Error> {
+        let stdout = match &config.stdout {
+            Some(pattern) => Some(LineWatch {
+                pattern: Regex::new(pattern)?,
+                seen: Arc::new(AtomicBool::new(false)),
+            }),
+            None => None,
+        };
+        Ok(Readiness {
+            config: config.clone(),
+            file: config.file.as_ref().map(|file| cwd.join(file)),
+            stdout,
+            started: Instant::now(),
+            file_before: None,
+        })
+    }
+
+    /// Starts over for a run starting now, returning what its stdout reader has to watch.
+    pub fn restart(&mut self) -> Option<LineWatch> {
+        self.started = Instant::now();
+        self.file_before = self.file.as_ref().and_then(|file| modified(file));
+        let stdout = self.stdout.as_ref()?;
+        stdout.seen.store(false, Ordering::Relaxed);
+        Some(stdout.clone())
+    }
+
+    pub fn timeout(&self) -> Duration {
+        self.config.timeout.0
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        self.started.elapsed()
+    }
+
+    /// The first condition that doesn't hold yet, `None` once the process is ready.
+    pub fn waiting_for(&self) -> Option<String> {
+        if let Some(min_uptime) = self.config.min_uptime {
+            if self.started.elapsed() < min_uptime.0 {
+                return Some(format!("up for less than {}", min_uptime));
+            }
+        }
+        if let Some(stdout) = &self.stdout {
+            if !stdout.seen.load(Ordering::Relaxed) {
+                return Some(format!(
+                    "no line of stdout matches {:?}",
+                    stdout.pattern.as_str()
+                ));
+            }
+        }
+        if let Some(file) = &self.file {
+            let written = modified(file);
+            if written.is_none() || written == self.file_before {
+                return Some(format!("{} isn't written", file.display()));
+            }
+        }
+        if let Some(port) = &self.config.port {
+            if !accepts(port) {
+                return Some(format!("{} doesn't accept connections", port));
+            }
+        }
+        None
+    }
+}
+
+fn modified(file: &Path) -> Option<SystemTime> {
+    fs::metadata(file).and_then(|meta| meta.modified()).ok()
+}
+
+fn accepts(port: &ReadyPort) -> bool {
+    let addresses = match port {
+        ReadyPort::Number(port) => ("localhost", *port).to_socket_addrs(),
+        ReadyPort::Address(address) => address.to_socket_addrs(),
+    };
+    addresses.is_ok_and(|mut addresses| {
+        addresses
+            .any(|address| TcpStream::connect_timeout(&address, Duration::from_millis(100)).is_ok())
+    })
+}
+
+#[test]
+fn test_readiness() {
+    use crate::units::HumanDuration;
+    use std::net::TcpListener;
+
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(dir.path().join("app.pid"), "1").unwrap();
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let config = ReadyConfig {
+        port: Some(ReadyPort::Number(port)),
+        stdout: Some("^listening on \\d+$".to_string()),
+        file: Some("app.pid".to_string()),
+        min_uptime: Some(HumanDuration::from_millis(50)),
+        timeout: HumanDuration::from_secs(5),
+    };
+    let mut readiness = Readiness::new(&config, dir.path()).unwrap();
+    let watch = readiness.restart().unwrap();
+
+    assert_eq!(readiness.waiting_for().unwrap(), "up for less than 50ms");
+    std::thread::sleep(Duration::from_millis(60));
+    assert_eq!(
+        readiness.waiting_for().unwrap(),
+        "no line of stdout matches \"^listening on \\\\d+$\""
+    );
+    watch.seen.store(true, Ordering::Relaxed);
+    // Left from before the start
+    assert!(readiness
+        .waiting_for()
+        .unwrap()
+        .ends_with("app.pid isn't written"));
+    let file = fs::File::options()
+        .write(true)
+        .open(dir.path().join("app.pid"))
+        .unwrap();
+    file.set_modified(SystemTime::now() + Duration::from_secs(1))
+        .unwrap();
+    assert_eq!(readiness.waiting_for(), None);
+
+    drop(listener);
+    assert_eq!(
+        readiness.waiting_for().unwrap(),
+        format!("localhost:{} doesn't accept connections", port)
+    );
+    readiness.restart();
+    assert!(readiness.waiting_for().is_some());
+}
@@ -0,0 +1,201 @@
+//! Memory and CPU readings of whole process trees from `/proc`, for `watchdog` limits.
+//! Elsewhere there are no readings and the limits never trigger.
+
+use std::time::{Duration, Instant};
+
+use crate::proc_config::WatchdogConfig;
+use crate::units::ByteSize;
+
+/// Totals over a process and its descendants.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Sample {
+    pub rss: u64,
+    /// CPU time, including that of reaped children.
+    pub cpu: Duration,
+}
+
+#[cfg(target_os = "linux")]
+struct Stat {
+    pid: u32,
+    ppid: u32,
+    rss_pages: u64,
+    cpu_ticks: u64,
+}
+
+#[cfg(target_os = "linux")]
+fn read_stat(pid: u32) -> Option<Stat> {
+    let text = std::fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?;
+    // The command name in parentheses may contain spaces
+    let (_, rest) = text.rsplit_once(") ")?;
+    let fields: Vec<&str> = rest.split(' ').collect();
+    // Numbered from `state`, the third field in proc(5)
+    let field = |number: usize| fields.get(number - 3)?.parse::<u64>().ok();
+    Some(Stat {
+        pid,
+        ppid: field(4)? as u32,
+        cpu_ticks: field(14)? + field(15)? + field(16)? + field(17)?,
+        rss_pages: field(24)?,
+    })
+}
+
+/// Sums up `root` and everything below it; `None` once `root` is gone.
+#[cfg(target_os = "linux")]
+pub fn sample(root: u32) -> Option<Sample> {
+    let stats: Vec<Stat> = std::fs::read_dir("/proc")
+        .ok()?
+        .filter_map(|entry| entry.ok()?.file_name().to_str()?.parse().ok())
+        .filter_map(read_stat)
+        .collect();
+    if !stats.iter().any(|stat| stat.pid == root) {
+        return None;
+    }
+
+    let mut tree = vec![root];
+    let mut index = 0;
+    while index < tree.len() {
+        let parent = tree[index];
+        tree.extend(stats.iter().filter(|s| s.ppid == parent).map(|s| s.pid));
+        index += 1;
+    }
+
+    let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as u64;
+    let ticks_per_sec = unsafe { libc::sysconf(libc::_SC_CLK_TCK) } as u64;
+    let (pages, ticks) = stats
+        .iter()
+        .filter(|stat| tree.contains(&stat.pid))
+        .fold((0, 0), |(pages, ticks), stat| {
+            (pages + stat.rss_pages, ticks + stat.cpu_ticks)
+        });
+    Some(Sample {
+        rss: pages * page_size,
+        cpu: Duration::from_millis(ticks * 1000 / ticks_per_sec.max(1)),
+    })
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn sample(_root: u32) -> Option<Sample> {
+    None
+}
+
+/// Tracks one process against its `watchdog` limits.
+pub struct Watchdog {
+    config: WatchdogConfig,
+    last_sample: Option<(Instant, Sample)>,
+    /// Since when `max_cpu` has been exceeded without a break.
+    busy_since: Option<Instant>,
+}
+
+impl Watchdog {
+    pub fn new(config: WatchdogConfig) -> Watchdog {
+        Watchdog {
+            config,
+            last_sample: None,
+            busy_since: None,
+        }
+    }
+
+    /// Forgets readings of a previous run.
+    pub fn reset(&mut self) {
+        self.last_sample = None;
+        self.busy_since = None;
+    }
+
+    /// Samples `pid` every `interval`, returning the reading that exceeded a limit.
+    pub fn check(&mut self, pid: u32) -> Option<String> {
+        let now = Instant::now();
+        if let Some((time, _)) = self.last_sample {
+            if now - time < self.config.interval.0 {
+                return None;
+            }
+        }
+        let sample = sample(pid)?;
+        self.check_sample(now, sample)
+    }
+
+    fn check_sample(&mut self, now: Instant, sample: Sample) -> Option<String> {
+        let last_sample = self.last_sample.replace((now, sample));
+
+        if let Some(max_memory) = self.config.max_memory {
+            if sample.rss > max_memory.bytes() {
+                return Some(format!(
+                    "memory {} over max_memory {}",
+                    ByteSize(sample.rss),
+                    max_memory
+                ));
+            }
+        }
+
+        let (max_cpu, (last_time, last)) = match (self.config.max_cpu, last_sample) {
+            (Some(max_cpu), Some(last_sample)) => (max_cpu, last_sample),
+            _ => return None,
+        };
+        let elapsed = (now - last_time).as_secs_f64();
+        // Reaped descendants drop out of the total
+        let used = sample.cpu.saturating_sub(last.cpu).as_secs_f64();
+        let percent = used / elapsed.max(f64::EPSILON) * 100.0;
+        if percent <= max_cpu {
+            self.busy_since = None;
+            return None;
+        }
+
+        let busy_since = *self.busy_since.get_or_insert(last_time);
+        if now - busy_since >= self.config.cpu_window.0 {
+            return Some(format!(
+                "CPU {:.0}% over max_cpu {}% for {}",
+                percent, max_cpu, self.config.cpu_window
+            ));
+        }
+        None
+    }
+}
+
+#[test]
+fn test_watchdog() {
+    use crate::units::HumanDuration;
+
+    let mut watchdog = Watchdog::new(WatchdogConfig {
+        max_memory: Some(ByteSize(100 << 20)),
+        max_cpu: Some(80.0),
+        cpu_window: HumanDuration::from_secs(20),
+        interval: HumanDuration::from_secs(5),
+    });
+    let start = Instant::now();
+    let at = |secs: u64| start + Duration::from_secs(secs);
+    let reading = |rss_mb: u64, cpu_secs: u64| Sample {
+        rss: rss_mb << 20,
+        cpu: Duration::from_secs(cpu_secs),
+    };
+
+    assert_eq!(watchdog.check_sample(at(0), reading(50, 0)), None);
+    // 100% for 15s, then a 40% dip restarts the window
+    assert_eq!(watchdog.check_sample(at(10), reading(50, 10)), None);
+    assert_eq!(watchdog.check_sample(at(15), reading(50, 15)), None);
+    assert_eq!(watchdog.check_sample(at(20), reading(50, 17)), None);
+    assert_eq!(watchdog.check_sample(at(30), reading(50, 27)), None);
+    assert_eq!(
+        watchdog.check_sample(at(40), reading(50, 37)).unwrap(),
+        "CPU 100% over max_cpu 80% for 20s"
+    );
+
+    watchdog.reset();
+    assert_eq!(
+        watchdog.check_sample(at(45), reading(150, 37)).unwrap(),
+        "memory 150MB over max_memory 100MB"
+    );
+
+    #[cfg(target_os = "linux")]
+    {
+        let mut child = std::process::Command::new("sh")
+            .args(["-c", "sleep 5 & sleep 5; wait"])
+            .spawn()
+            .unwrap();
+        std::thread::sleep(Duration::from_millis(100));
+        let tree = sample(child.id()).unwrap();
+        let own = read_stat(child.id()).unwrap();
+        let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as u64;
+        assert!(tree.rss > own.rss_pages * page_size);
+        child.kill().unwrap();
+        child.wait().unwrap();
+        assert_eq!(sample(child.id()), None);
+    }
+}
@@ -0,0 +1,184 @@
+//! Pids of the running children, saved so that a supervisor started after a crash can find
+//! the ones that outlived it. Start times from `/proc` tell a survivor from an unrelated
+//! process that got the same pid, so survivors are only found on Linux.
+
+use serde::{Deserialize, Serialize};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::child_proc::ProcessStatus;
+use crate::logger::{log, verbose};
+use crate::proc_config;
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct State {
+    supervisor: u32,
+    processes: Vec<Survivor>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Survivor {
+    pub name: String,
+    pub pid: u32,
+    /// Clock ticks after boot, as in `/proc/<pid>/stat`.
+    pub start_time: u64,
+}
+
+impl Survivor {
+    fn of(name: &str, pid: u32) -> Option<Survivor> {
+        start_time(pid).map(|start_time| Survivor {
+            name: name.to_string(),
+            pid,
+            start_time,
+        })
+    }
+
+    /// Still running, and not some other process that reused the pid.
+    pub fn is_alive(&self) -> bool {
+        start_time(self.pid) == Some(self.start_time)
+    }
+
+    /// Sends `signal` and waits up to `timeout` for the process to exit before killing it.
+    pub fn terminate(&self, signal: i32, timeout: Duration) {
+        if let Err(err) = crate::signals::send(self.pid, signal) {
+            log!("Can't signal {} ({}): {:?}", &self.name, self.pid, err);
+        }
+        let deadline = Instant::now() + timeout;
+        while Instant::now() < deadline {
+            if !self.is_alive() {
+                return;
+            }
+            thread::sleep(Duration::from_millis(50));
+        }
+
+        if self.is_alive() {
+            log!(
+                "{} ({}) didn't stop within {:?}, killing",
+                &self.name,
+                self.pid,
+                timeout
+            );
+            #[cfg(unix)]
+            crate::signals::send(self.pid, libc::SIGKILL).ok();
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn start_time(pid: u32) -> Option<u64> {
+    let stat = std::fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?;
+    // The command name in parentheses may contain spaces
+    let (_, fields) = stat.rsplit_once(") ")?;
+    let fields: Vec<&str> = fields.split(' ').collect();
+    // Exited, waiting to be reaped
+    if fields.first() == Some(&"Z") {
+        return None;
+    }
+    fields.get(19)?.parse().ok()
+}
+
+#[cfg(not(target_os = "linux"))]
+fn start_time(_pid: u32) -> Option<u64> {
+    None
+}
+
+/// The config path with `.state`, next to the pidfile.
+pub fn path() -> PathBuf {
+    proc_config::config_path().with_extension("state")
+}
+
+/// Records the running processes, replacing the file so it is never seen half written.
+pub fn save(path: &Path, processes: &[ProcessStatus]) -> io::Result<()> {
+    let state = State {
+        supervisor: std::process::id(),
+        processes: processes
+            .iter()
+            .filter(|process| process.pid != 0)
+            .filter_map(|process| Survivor::of(&process.name, process.pid))
+            .collect(),
+    };
+    let temp = path.with_extension("state.tmp");
+    std::fs::write(&temp, serde_json::to_string_pretty(&state)?)?;
+    std::fs::rename(&temp, path)
+}
+
+pub fn remove(path: &Path) {
+    std::fs::remove_file(path).ok();
+}
+
+/// Processes from a previous run that are still alive.
+pub fn survivors(path: &Path) -> Vec<Survivor> {
+    let text = match std::fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(_) => return vec![],
+    };
+    let state: State = match serde_json::from_str(&text) {
+        Ok(state) => state,
+        Err(err) => {
+            log!("Ignoring {}: {}", path.display(), err);
+            return vec![];
+        }
+    };
+
+    verbose!("Previous supervisor was pid {}", state.supervisor);
+    state
+        .processes
+        .into_iter()
+        .filter(|survivor| survivor.is_alive())
+        .inspect(|survivor| {
+            log!(
+                "{} ({}) survived the previous supervisor",
+                &survivor.name,
+                survivor.pid
+            )
+        })
+        .collect()
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+    use crate::child_proc::ProcessState;
+    use std::process::Command;
+
+    #[test]
+    fn test_survivors() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("servicers.state");
+        assert!(survivors(&path).is_empty());
+
+        let mut child = Command::new("sleep").arg("30").spawn().unwrap();
+        let status = |name: &str, pid: u32| ProcessStatus {
+            name: name.to_string(),
+            state: ProcessState::Running,
+            pid,
+            restarts: 0,
+            planned_restarts: 0,
+            last_exit: None,
+            failed: false,
+            exit_class: None,
+            last_run: None,
+            next_run: None,
+        };
+        save(&path, &[status("sleep", child.id()), status("stopped", 0)]).unwrap();
+
+        let found = survivors(&path);
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].name, "sleep");
+        assert_eq!(found[0].pid, child.id());
+
+        // Same pid, different process
+        let reused = Survivor {
+            start_time: found[0].start_time + 1,
+            ..found[0].clone()
+        };
+        assert!(!reused.is_alive());
+
+        let (survivor, reaper) = (found[0].clone(), thread::spawn(move || child.wait()));
+        survivor.terminate(libc::SIGTERM, Duration::from_secs(5));
+        assert!(!reaper.join().unwrap().unwrap().success());
+        assert!(!survivor.is_alive());
+    }
+}
@@ -0,0 +1,236 @@
+//! Cron expressions for `job.cron`: `minute hour day-of-month month day-of-week` in local
+//! time, with `*`, lists, ranges, `/step`, month and day names and the `@daily` shorthands.
+//! As in Vixie cron, a time matches either day field when both are restricted.
+
+use chrono::{DateTime, Datelike, Duration, Local, NaiveDateTime, TimeZone, Timelike};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt::{self, Display};
+use std::str::FromStr;
+
+const MONTHS: [&str; 12] = [
+    "jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec",
+];
+const WEEKDAYS: [&str; 7] = ["sun", "mon", "tue", "wed", "thu", "fri", "sat"];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cron {
+    text: String,
+    /// Bit n set when n matches.
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    /// Sunday is 0.
+    weekdays: u64,
+    /// Both day fields restricted, so either one matching is enough.
+    either_day: bool,
+}
+
+impl Cron {
+    /// The first matching minute after `time`, within the next five years.
+    pub fn next_after(&self, time: DateTime<Local>) -> Option<DateTime<Local>> {
+        let start = time.naive_local().with_second(0)?.with_nanosecond(0)? + Duration::minutes(1);
+        let mut next = start;
+        while next < start + Duration::days(5 * 366) {
+            if !self.months.has(next.month()) {
+                next = first_of_next_month(next)?;
+                continue;
+            }
+            if !self.matches_day(next) {
+                next = next.date().succ_opt()?.and_hms_opt(0, 0, 0)?;
+                continue;
+            }
+            if !self.hours.has(next.hour()) {
+                next = next.with_minute(0)? + Duration::hours(1);
+                continue;
+            }
+            if !self.minutes.has(next.minute()) {
+                next += Duration::minutes(1);
+                continue;
+            }
+            // Skipped over by a daylight saving change
+            match Local.from_local_datetime(&next).earliest() {
+                Some(local) => return Some(local),
+                None => next += Duration::minutes(1),
+            }
+        }
+        None
+    }
+
+    fn matches_day(&self, time: NaiveDateTime) -> bool {
+        let day = self.days.has(time.day());
+        let weekday = self.weekdays.has(time.weekday().num_days_from_sunday());
+        match self.either_day {
+            true => day || weekday,
+            false => day && weekday,
+        }
+    }
+}
+
+trait Bits {
+    fn has(&self, value: u32) -> bool;
+}
+
+impl Bits for u64 {
+    fn has(&self, value: u32) -> bool {
+        self & (1 << value) != 0
+    }
+}
+
+fn first_of_next_month(time: NaiveDateTime) -> Option<NaiveDateTime> {
+    let (year, month) = match time.month() {
+        12 => (time.year() + 1, 1),
+        month => (time.year(), month + 1),
+    };
+    chrono::NaiveDate::from_ymd_opt(year, month, 1)?.and_hms_opt(0, 0, 0)
+}
+
+/// One field: `*`, `5`, `1-5`, `*/15`, `mon-fri`, or a comma-separated list of them.
+fn parse_field(field: &str, min: u32, max: u32, names: &[&str]) -> Result<u64, String> {
+    let value = |text: &str| -> Result<u32, String> {
+        let lower = text.to_ascii_lowercase();
+        if let Some(index) = names.iter().position(|name| *name == lower) {
+            return Ok(index as u32 + min);
+        }
+        match text.parse::<u32>() {
+            Ok(value) if (min..=max).contains(&value) => Ok(value),
+            _ => Err(format!("{:?} isn't from {} to {}", text, min, max)),
+        }
+    };
+
+    let mut bits = 0;
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => match step.parse::<u32>() {
+                Ok(step) if step > 0 => (range, step),
+                _ => return Err(format!("invalid step in {:?}", part)),
+            },
+            None => (part, 1),
+        };
+        let (first, last) = match range {
+            "*" => (min, max),
+            _ => match range.split_once('-') {
+                Some((first, last)) => (value(first)?, value(last)?),
+                // `5/10` runs from 5 to the end
+                None if step > 1 => (value(range)?, max),
+                None => (value(range)?, value(range)?),
+            },
+        };
+        if first > last {
+            return Err(format!("empty range {:?}", range));
+        }
+        for value in (first..=last).step_by(step as usize) {
+            bits |= 1 << value;
+        }
+    }
+    Ok(bits)
+}
+
+impl FromStr for Cron {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Cron, String> {
+        let expanded = match text.trim() {
+            "@yearly" | "@annually" => "0 0 1 1 *",
+            "@monthly" => "0 0 1 * *",
+            "@weekly" => "0 0 * * 0",
+            "@daily" | "@midnight" => "0 0 * * *",
+            "@hourly" => "0 * * * *",
+            other => other,
+        };
+        let fields: Vec<&str> = expanded.split_whitespace().collect();
+        let [minute, hour, day, month, weekday] = fields[..] else {
+            return Err(format!(
+                "invalid cron expression {:?}: expected 5 fields",
+                text
+            ));
+        };
+        let invalid = |err: String| format!("invalid cron expression {:?}: {}", text, err);
+
+        let mut weekdays = parse_field(weekday, 0, 7, &WEEKDAYS).map_err(invalid)?;
+        // 7 is Sunday too
+        if weekdays.has(7) {
+            weekdays |= 1;
+        }
+        let restricted = |field: &str| !field.starts_with('*');
+        Ok(Cron {
+            text: text.trim().to_string(),
+            minutes: parse_field(minute, 0, 59, &[]).map_err(invalid)?,
+            hours: parse_field(hour, 0, 23, &[]).map_err(invalid)?,
+            days: parse_field(day, 1, 31, &[]).map_err(invalid)?,
+            months: parse_field(month, 1, 12, &MONTHS).map_err(invalid)?,
+            weekdays,
+            either_day: restricted(day) && restricted(weekday),
+        })
+    }
+}
+
+impl Display for Cron {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.text)
+    }
+}
+
+impl<'de> Deserialize<'de> for Cron {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+impl Serialize for Cron {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+#[test]
+fn test_cron() {
+    let at = |text: &str| {
+        let time = NaiveDateTime::parse_from_str(text, "%Y-%m-%d %H:%M").unwrap();
+        Local.from_local_datetime(&time).earliest().unwrap()
+    };
+    let next = |cron: &str, after: &str| {
+        let cron: Cron = cron.parse().unwrap();
+        cron.next_after(at(after))
+            .map(|time| time.format("%Y-%m-%d %H:%M").to_string())
+    };
+
+    assert_eq!(
+        next("*/15 * * * *", "2024-05-01 10:07").unwrap(),
+        "2024-05-01 10:15"
+    );
+    assert_eq!(
+        next("*/15 * * * *", "2024-05-01 10:15").unwrap(),
+        "2024-05-01 10:30"
+    );
+    assert_eq!(
+        next("30 2 * * *", "2024-05-01 03:00").unwrap(),
+        "2024-05-02 02:30"
+    );
+    assert_eq!(
+        next("0 9 * * mon-fri", "2024-05-03 10:00").unwrap(),
+        "2024-05-06 09:00"
+    );
+    assert_eq!(
+        next("@monthly", "2024-12-15 00:00").unwrap(),
+        "2025-01-01 00:00"
+    );
+    assert_eq!(
+        next("0 0 29 feb *", "2024-03-01 00:00").unwrap(),
+        "2028-02-29 00:00"
+    );
+    // Either day field: the 13th, or any Friday
+    assert_eq!(
+        next("0 0 13 * 5", "2024-05-01 00:00").unwrap(),
+        "2024-05-03 00:00"
+    );
+    assert_eq!(next("0 0 31 2 *", "2024-01-01 00:00"), None);
+
+    assert!("* * * *".parse::<Cron>().is_err());
+    assert!("61 * * * *".parse::<Cron>().is_err());
+    assert!("*/0 * * * *".parse::<Cron>().is_err());
+    assert!("0 0 * * funday".parse::<Cron>().is_err());
+    assert_eq!("@daily".parse::<Cron>().unwrap().to_string(), "@daily");
+}
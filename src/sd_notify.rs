@@ -0,0 +1,88 @@
+use std::io;
+use std::os::unix::net::UnixDatagram;
+use std::time::Duration;
+
+use crate::logger::verbose;
+
+/// Sends state updates to systemd over `NOTIFY_SOCKET` (`Type=notify` units).
+pub struct Notifier {
+    socket: UnixDatagram,
+    path: String,
+}
+
+impl Notifier {
+    /// `None` when not started by systemd with a notify socket.
+    pub fn from_env() -> Option<Notifier> {
+        let path = std::env::var("NOTIFY_SOCKET").ok()?;
+        match Notifier::connect(&path) {
+            Ok(notifier) => Some(notifier),
+            Err(err) => {
+                crate::logger::log!("Can't use NOTIFY_SOCKET {:?}: {:?}", path, err);
+                None
+            }
+        }
+    }
+
+    /// `path` is a socket file or, starting with `@`, an abstract socket name.
+    pub fn connect(path: &str) -> io::Result<Notifier> {
+        Ok(Notifier {
+            socket: UnixDatagram::unbound()?,
+            path: path.to_string(),
+        })
+    }
+
+    /// Sends newline separated `KEY=value` assignments, e.g. `READY=1\nSTATUS=...`.
+    pub fn notify(&self, state: &str) -> io::Result<()> {
+        verbose!("sd_notify: {}", state.replace('\n', " "));
+
+        match self.path.strip_prefix('@') {
+            #[cfg(target_os = "linux")]
+            Some(name) => {
+                use std::os::linux::net::SocketAddrExt;
+                let addr = std::os::unix::net::SocketAddr::from_abstract_name(name)?;
+                self.socket.send_to_addr(state.as_bytes(), &addr)?;
+            }
+            _ => {
+                self.socket.send_to(state.as_bytes(), &self.path)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// How often to send `WATCHDOG=1`: half of `WATCHDOG_USEC`, if it is meant for this process.
+pub fn watchdog_interval() -> Option<Duration> {
+    if let Ok(pid) = std::env::var("WATCHDOG_PID") {
+        if pid.parse::<u32>().ok()? != std::process::id() {
+            return None;
+        }
+    }
+    let usec: u64 = std::env::var("WATCHDOG_USEC").ok()?.parse().ok()?;
+    Some(Duration::from_micros(usec) / 2)
+}
+
+/// `MONOTONIC_USEC=` value systemd expects along with `RELOADING=1`.
+pub fn monotonic_usec() -> u64 {
+    let mut time = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    unsafe { libc::clock_gettime(libc::CLOCK_MONOTONIC, &mut time) };
+    time.tv_sec as u64 * 1_000_000 + time.tv_nsec as u64 / 1_000
+}
+
+#[test]
+fn test_notify() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("notify.sock");
+    let systemd = UnixDatagram::bind(&path).unwrap();
+
+    let notifier = Notifier::connect(path.to_str().unwrap()).unwrap();
+    notifier
+        .notify("READY=1\nSTATUS=2 running, 0 failed")
+        .unwrap();
+
+    let mut buffer = [0; 256];
+    let size = systemd.recv(&mut buffer).unwrap();
+    assert_eq!(&buffer[..size], b"READY=1\nSTATUS=2 running, 0 failed");
+}
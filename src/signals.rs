@@ -0,0 +1,80 @@
+/// Signal names accepted in config and on the command line, with or without `SIG`.
+#[cfg(unix)]
+const SIGNALS: [(&str, i32); 12] = [
+    ("HUP", libc::SIGHUP),
+    ("INT", libc::SIGINT),
+    ("QUIT", libc::SIGQUIT),
+    ("KILL", libc::SIGKILL),
+    ("USR1", libc::SIGUSR1),
+    ("USR2", libc::SIGUSR2),
+    ("TERM", libc::SIGTERM),
+    ("CONT", libc::SIGCONT),
+    ("STOP", libc::SIGSTOP),
+    ("TSTP", libc::SIGTSTP),
+    ("WINCH", libc::SIGWINCH),
+    ("ALRM", libc::SIGALRM),
+];
+
+/// Linux numbering, only used to validate names where there are no signals.
+#[cfg(not(unix))]
+const SIGNALS: [(&str, i32); 12] = [
+    ("HUP", 1),
+    ("INT", 2),
+    ("QUIT", 3),
+    ("KILL", 9),
+    ("USR1", 10),
+    ("USR2", 12),
+    ("TERM", 15),
+    ("CONT", 18),
+    ("STOP", 19),
+    ("TSTP", 20),
+    ("WINCH", 28),
+    ("ALRM", 14),
+];
+
+/// Parses `"TERM"`, `"SIGTERM"` or `"15"`.
+pub fn parse(name: &str) -> Option<i32> {
+    let name = name.trim().to_ascii_uppercase();
+    let name = name.strip_prefix("SIG").unwrap_or(&name);
+    if let Ok(number) = name.parse::<i32>() {
+        return Some(number).filter(|n| *n > 0 && *n < 65);
+    }
+    SIGNALS
+        .iter()
+        .find(|(signal, _)| *signal == name)
+        .map(|(_, number)| *number)
+}
+
+/// Handled by the supervisor itself, or impossible to catch, so never forwarded.
+pub fn is_reserved(signal: i32) -> bool {
+    ["HUP", "INT", "TERM", "KILL", "STOP"]
+        .iter()
+        .any(|name| parse(name) == Some(signal))
+}
+
+#[cfg(unix)]
+pub fn send(pid: u32, signal: i32) -> std::io::Result<()> {
+    // 0 would signal our own process group
+    if pid == 0 {
+        return Err(std::io::ErrorKind::InvalidInput.into());
+    }
+    match unsafe { libc::kill(pid as libc::pid_t, signal) } {
+        0 => Ok(()),
+        _ => Err(std::io::Error::last_os_error()),
+    }
+}
+
+#[cfg(not(unix))]
+pub fn send(_pid: u32, _signal: i32) -> std::io::Result<()> {
+    Err(std::io::ErrorKind::Unsupported.into())
+}
+
+#[cfg(unix)]
+#[test]
+fn test_parse() {
+    assert_eq!(parse("TERM"), Some(libc::SIGTERM));
+    assert_eq!(parse("sigusr1"), Some(libc::SIGUSR1));
+    assert_eq!(parse("9"), Some(libc::SIGKILL));
+    assert_eq!(parse("SIGBOGUS"), None);
+    assert!(is_reserved(libc::SIGHUP) && !is_reserved(libc::SIGUSR1));
+}
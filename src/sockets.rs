@@ -0,0 +1,399 @@
+//! `sockets` of a process: listening sockets bound by the supervisor and passed to it as fds
+//! 3 and up, with LISTEN_FDS, LISTEN_PID and LISTEN_FDNAMES set as systemd does (Linux only).
+//! They are kept here rather than with the process, so they stay open while it restarts or
+//! the config reloads, and connections queue up meanwhile instead of being refused.
+
+#[cfg(any(test, not(target_os = "linux")))]
+use std::io;
+#[cfg(not(target_os = "linux"))]
+use std::process::{Child, Command};
+
+use crate::logger::log;
+use crate::proc_config::ProcessConfig;
+
+/// Listens on the `sockets` of `processes`, keeping those already open, and closes the
+/// ones no longer configured.
+pub fn open(processes: &[ProcessConfig]) {
+    #[cfg(target_os = "linux")]
+    {
+        let wanted: std::collections::BTreeSet<&str> = processes
+            .iter()
+            .flat_map(|process| &process.sockets)
+            .map(|socket| socket.listen.as_str())
+            .collect();
+        linux::LISTENERS
+            .lock()
+            .unwrap()
+            .retain(|listen, _| wanted.contains(listen.as_str()));
+        for listen in wanted {
+            if let Err(err) = linux::listener(listen) {
+                log!("Can't listen on {}: {}", listen, err);
+            }
+        }
+    }
+    #[cfg(not(target_os = "linux"))]
+    if processes.iter().any(|process| !process.sockets.is_empty()) {
+        log!("Sockets are only supported on Linux");
+    }
+}
+
+#[cfg(target_os = "linux")]
+pub use linux::{has_connection, pass};
+
+#[cfg(not(target_os = "linux"))]
+pub struct Passed;
+
+#[cfg(not(target_os = "linux"))]
+impl Passed {
+    pub fn check(self, child: Child) -> io::Result<Child> {
+        Ok(child)
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn pass(_command: &mut Command, process: &ProcessConfig) -> io::Result<Option<Passed>> {
+    match process.sockets.is_empty() {
+        true => Ok(None),
+        false => Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "sockets are only supported on Linux",
+        )),
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn has_connection(_process: &ProcessConfig) -> bool {
+    false
+}
+
+#[cfg(target_os = "linux")]
+mod linux {
+    use std::collections::BTreeMap;
+    use std::ffi::{CString, OsStr, OsString};
+    use std::fs::{self, File};
+    use std::io::{self, Read};
+    use std::net::TcpListener;
+    use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+    use std::os::unix::ffi::OsStrExt;
+    use std::os::unix::fs::FileTypeExt;
+    use std::os::unix::net::{UnixListener, UnixStream};
+    use std::os::unix::process::CommandExt;
+    use std::path::{Path, PathBuf};
+    use std::process::{Child, Command};
+    use std::sync::{Arc, Mutex};
+
+    use crate::proc_config::ProcessConfig;
+
+    pub struct Listener {
+        fd: OwnedFd,
+        /// A Unix socket's file, removed along with it.
+        path: Option<PathBuf>,
+    }
+
+    impl Drop for Listener {
+        fn drop(&mut self) {
+            if let Some(path) = &self.path {
+                fs::remove_file(path).ok();
+            }
+        }
+    }
+
+    /// Open sockets by `listen` address.
+    pub static LISTENERS: Mutex<BTreeMap<String, Arc<Listener>>> = Mutex::new(BTreeMap::new());
+
+    /// A path is a Unix socket, anything else a TCP address.
+    fn bind(listen: &str) -> io::Result<Listener> {
+        if !listen.contains('/') {
+            return Ok(Listener {
+                fd: TcpListener::bind(listen)?.into(),
+                path: None,
+            });
+        }
+        let path = Path::new(listen);
+        // Left behind by a supervisor that didn't exit cleanly
+        let is_socket = fs::symlink_metadata(path).is_ok_and(|meta| meta.file_type().is_socket());
+        if is_socket && UnixStream::connect(path).is_err() {
+            fs::remove_file(path)?;
+        }
+        Ok(Listener {
+            fd: UnixListener::bind(path)?.into(),
+            path: Some(path.to_path_buf()),
+        })
+    }
+
+    /// The open socket for `listen`, bound now if it isn't yet.
+    pub fn listener(listen: &str) -> io::Result<Arc<Listener>> {
+        let mut listeners = LISTENERS.lock().unwrap();
+        if let Some(listener) = listeners.get(listen) {
+            return Ok(listener.clone());
+        }
+        let listener = Arc::new(bind(listen)?);
+        listeners.insert(listen.to_string(), listener.clone());
+        Ok(listener)
+    }
+
+    /// Whether a connection is waiting on one of the `sockets` of `process`.
+    pub fn has_connection(process: &ProcessConfig) -> bool {
+        let listeners = LISTENERS.lock().unwrap();
+        let mut fds: Vec<libc::pollfd> = process
+            .sockets
+            .iter()
+            .filter_map(|socket| listeners.get(&socket.listen))
+            .map(|listener| libc::pollfd {
+                fd: listener.fd.as_raw_fd(),
+                events: libc::POLLIN,
+                revents: 0,
+            })
+            .collect();
+        unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, 0) > 0 }
+    }
+
+    /// Everything the child needs to exec the program with its sockets. It must not
+    /// allocate, so it's all worked out before the fork.
+    struct Exec {
+        _strings: Vec<CString>,
+        argv: Vec<*const libc::c_char>,
+        envp: Vec<*const libc::c_char>,
+        /// `LISTEN_PID=` followed by room for the pid, only known in the child.
+        _pid_entry: Box<[u8]>,
+        pid_digits: *mut u8,
+        fds: Vec<RawFd>,
+        /// Where `fds` are copied first, out of the way of fds 3 and up.
+        moved: Vec<RawFd>,
+        stdin: Option<usize>,
+        report: RawFd,
+    }
+
+    // Only used in the child, which has a single thread
+    unsafe impl Send for Exec {}
+    unsafe impl Sync for Exec {}
+
+    impl Exec {
+        /// Returns only if it fails before any fd was touched; later failures are reported
+        /// to `report` and end the child.
+        unsafe fn run(&mut self) -> io::Error {
+            let first = 3 + self.fds.len() as libc::c_int;
+            let report = libc::fcntl(self.report, libc::F_DUPFD_CLOEXEC, first);
+            if report < 0 {
+                return io::Error::last_os_error();
+            }
+            let fail = || -> ! {
+                let errno = io::Error::last_os_error().raw_os_error().unwrap_or(0);
+                libc::write(report, errno.to_ne_bytes().as_ptr().cast(), 4);
+                libc::_exit(127)
+            };
+
+            for (index, fd) in self.fds.iter().enumerate() {
+                self.moved[index] = libc::fcntl(*fd, libc::F_DUPFD_CLOEXEC, first);
+                if self.moved[index] < 0 {
+                    fail();
+                }
+            }
+            // dup2 leaves the new fds open across exec
+            for (index, fd) in self.moved.iter().enumerate() {
+                if libc::dup2(*fd, 3 + index as libc::c_int) < 0 {
+                    fail();
+                }
+            }
+            if let Some(index) = self.stdin {
+                if libc::dup2(self.moved[index], 0) < 0 {
+                    fail();
+                }
+            }
+
+            let mut pid = libc::getpid() as u32;
+            let mut digits = [0u8; 10];
+            let mut count = 0;
+            loop {
+                digits[count] = b'0' + (pid % 10) as u8;
+                pid /= 10;
+                count += 1;
+                if pid == 0 {
+                    break;
+                }
+            }
+            for index in 0..count {
+                *self.pid_digits.add(index) = digits[count - 1 - index];
+            }
+            *self.pid_digits.add(count) = 0;
+
+            libc::execvpe(self.argv[0], self.argv.as_ptr(), self.envp.as_ptr());
+            fail()
+        }
+    }
+
+    /// Set up for one spawn; check the spawned child with it.
+    pub struct Passed {
+        /// The errno of a failed exec, written by the child.
+        report: File,
+        writer: Option<OwnedFd>,
+        _listeners: Vec<Arc<Listener>>,
+    }
+
+    impl Passed {
+        /// Fails, reaping `child`, when the program couldn't be executed.
+        pub fn check(mut self, mut child: Child) -> io::Result<Child> {
+            // Otherwise the read below never sees the end
+            self.writer = None;
+            let mut errno = [0u8; 4];
+            match self.report.read(&mut errno) {
+                Ok(4) => {
+                    child.wait().ok();
+                    Err(io::Error::from_raw_os_error(i32::from_ne_bytes(errno)))
+                }
+                _ => Ok(child),
+            }
+        }
+    }
+
+    /// Makes `command` pass the `sockets` of `process`, `None` if it has none. The child
+    /// execs the program itself, since LISTEN_PID has to be its pid; so this goes after
+    /// anything else `command` does before exec.
+    pub fn pass(command: &mut Command, process: &ProcessConfig) -> io::Result<Option<Passed>> {
+        if process.sockets.is_empty() {
+            return Ok(None);
+        }
+        let listeners = process
+            .sockets
+            .iter()
+            .map(|socket| {
+                listener(&socket.listen).map_err(|err| {
+                    io::Error::new(
+                        err.kind(),
+                        format!("can't listen on {}: {}", socket.listen, err),
+                    )
+                })
+            })
+            .collect::<io::Result<Vec<_>>>()?;
+        let names: Vec<&str> = process
+            .sockets
+            .iter()
+            .map(|socket| socket.name.as_deref().unwrap_or(&process.name))
+            .collect();
+
+        let mut env: BTreeMap<OsString, OsString> = std::env::vars_os().collect();
+        for (key, value) in command.get_envs() {
+            match value {
+                Some(value) => env.insert(key.to_owned(), value.to_owned()),
+                None => env.remove(key),
+            };
+        }
+        env.remove(OsStr::new("LISTEN_PID"));
+        env.insert("LISTEN_FDS".into(), listeners.len().to_string().into());
+        env.insert("LISTEN_FDNAMES".into(), names.join(":").into());
+
+        let c_string = |text: &OsStr| {
+            CString::new(text.as_bytes()).map_err(|_| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("{:?} contains a NUL", text),
+                )
+            })
+        };
+        let mut strings = vec![c_string(command.get_program())?];
+        for arg in command.get_args() {
+            strings.push(c_string(arg)?);
+        }
+        let arg_count = strings.len();
+        for (key, value) in &env {
+            let mut entry = key.clone();
+            entry.push("=");
+            entry.push(value);
+            strings.push(c_string(&entry)?);
+        }
+        let mut pid_entry: Box<[u8]> = [b"LISTEN_PID=".as_slice(), &[0; 11]].concat().into();
+        let pid_digits = unsafe { pid_entry.as_mut_ptr().add(b"LISTEN_PID=".len()) };
+
+        let pointers = |strings: &[CString]| {
+            let mut pointers: Vec<*const libc::c_char> =
+                strings.iter().map(|s| s.as_ptr()).collect();
+            pointers.push(std::ptr::null());
+            pointers
+        };
+        let argv = pointers(&strings[..arg_count]);
+        let mut envp = pointers(&strings[arg_count..]);
+        envp.insert(0, pid_entry.as_ptr().cast());
+
+        let mut fds = [0; 2];
+        if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let (report, writer) = unsafe { (File::from_raw_fd(fds[0]), OwnedFd::from_raw_fd(fds[1])) };
+
+        let mut exec = Exec {
+            _strings: strings,
+            argv,
+            envp,
+            _pid_entry: pid_entry,
+            pid_digits,
+            fds: listeners
+                .iter()
+                .map(|listener| listener.fd.as_raw_fd())
+                .collect(),
+            moved: vec![-1; listeners.len()],
+            stdin: process.sockets.iter().position(|socket| socket.stdin),
+            report: writer.as_raw_fd(),
+        };
+        unsafe {
+            command.pre_exec(move || Err(exec.run()));
+        }
+        Ok(Some(Passed {
+            report,
+            writer: Some(writer),
+            _listeners: listeners,
+        }))
+    }
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn test_sockets() {
+    use crate::proc_config::SocketConfig;
+    use std::os::unix::net::UnixStream;
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("admin.sock");
+    let script = r#"echo $LISTEN_FDS $LISTEN_FDNAMES
+[ "$LISTEN_PID" = $$ ] && echo pid
+readlink /proc/self/fd/3 /proc/self/fd/4 /proc/self/fd/0 | cut -c1-7"#;
+    let process = ProcessConfig {
+        name: "app".to_string(),
+        program: "sh".to_string(),
+        args: vec!["-c".to_string(), script.to_string()],
+        cwd: dir.path().to_string_lossy().into_owned(),
+        sockets: vec![
+            SocketConfig {
+                listen: "127.0.0.1:0".to_string(),
+                name: None,
+                stdin: false,
+            },
+            SocketConfig {
+                listen: path.to_string_lossy().into_owned(),
+                name: Some("admin".to_string()),
+                stdin: true,
+            },
+        ],
+        ..Default::default()
+    };
+    open(std::slice::from_ref(&process));
+    assert!(!has_connection(&process));
+
+    let output = process.spawn_new().unwrap().wait_with_output().unwrap();
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "2 app:admin\npid\nsocket:\nsocket:\nsocket:\n"
+    );
+    // Still listening once the process is gone
+    let _client = UnixStream::connect(&path).unwrap();
+    assert!(has_connection(&process));
+
+    let missing = ProcessConfig {
+        program: "no-such-program-here".to_string(),
+        ..process.clone()
+    };
+    let err = missing.spawn_new().unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::NotFound);
+
+    open(&[]);
+    assert!(!path.exists());
+}
@@ -0,0 +1,256 @@
+use std::error::Error;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Instant;
+
+#[cfg(unix)]
+use crate::child_proc::ProcessCommand;
+use crate::child_proc::{
+    all_started, run_processes, summarize, ChildProcess, ProcessHandle, ProcessState,
+};
+use crate::control_socket::{self, Registry};
+use crate::journal;
+use crate::limits;
+use crate::logger::{self, log};
+use crate::proc_config::{self, OrphanPolicy, ProcessConfig};
+use crate::runtime_state::{self, Survivor};
+use crate::sockets;
+
+/// Reports supervisor state to the service manager: sd_notify under systemd, nothing elsewhere.
+struct Reporter {
+    #[cfg(unix)]
+    notifier: Option<crate::sd_notify::Notifier>,
+}
+
+impl Reporter {
+    fn new() -> Reporter {
+        Reporter {
+            #[cfg(unix)]
+            notifier: crate::sd_notify::Notifier::from_env(),
+        }
+    }
+
+    #[cfg(unix)]
+    fn notify(&self, state: &str) {
+        if let Some(notifier) = &self.notifier {
+            if let Err(err) = notifier.notify(state) {
+                log!("sd_notify failed: {:?}", &err);
+            }
+        }
+    }
+
+    #[cfg(not(unix))]
+    fn notify(&self, _state: &str) {}
+}
+
+/// Stops survivors of a previous run with their config's stop signal.
+fn terminate_survivors(survivors: Vec<Survivor>, processes: &[ProcessConfig]) {
+    for survivor in survivors {
+        let config = processes
+            .iter()
+            .flat_map(ProcessConfig::instances)
+            .find(|config| config.name == survivor.name)
+            .unwrap_or_default();
+        let signal = crate::signals::parse(&config.stop_signal).unwrap_or(15);
+        log!(
+            "Terminating {} ({}) left by the previous supervisor",
+            &survivor.name,
+            survivor.pid
+        );
+        survivor.terminate(signal, config.stop_timeout.0);
+    }
+}
+
+/// A signal in `service.forward_signals`, flagged when the supervisor receives it.
+#[cfg(unix)]
+struct Forwarded {
+    signal: String,
+    received: Arc<AtomicBool>,
+    id: signal_hook::SigId,
+}
+
+/// Registers `signals` for forwarding in place of the previous ones.
+#[cfg(unix)]
+fn register_forwarded(forwarded: &mut Vec<Forwarded>, signals: &[String]) {
+    for previous in forwarded.drain(..) {
+        signal_hook::low_level::unregister(previous.id);
+    }
+    for signal in signals {
+        let number = match crate::signals::parse(signal) {
+            Some(number) => number,
+            None => continue,
+        };
+        let received = Arc::new(AtomicBool::new(false));
+        match signal_hook::flag::register(number, received.clone()) {
+            Ok(id) => forwarded.push(Forwarded {
+                signal: signal.clone(),
+                received,
+                id,
+            }),
+            Err(err) => log!("Can't forward {}: {:?}", signal, &err),
+        }
+    }
+}
+
+/// Passes the signals received since the last poll on to every process, in the background
+/// so a slow process thread doesn't hold up the supervisor.
+#[cfg(unix)]
+fn forward(forwarded: &[Forwarded], registry: &Arc<Registry>) {
+    for forwarded in forwarded {
+        if !forwarded.received.swap(false, Ordering::Relaxed) {
+            continue;
+        }
+        log!("Forwarding {}", &forwarded.signal);
+        let signal = forwarded.signal.clone();
+        let registry = registry.clone();
+        thread::spawn(move || {
+            let command = |reply| ProcessCommand::Signal(signal.clone(), reply);
+            // Disabled processes aren't sent to, so any error is worth reporting
+            for result in registry.command(None, command) {
+                if let Err(err) = result {
+                    log!("Can't forward {}: {}", signal, err);
+                }
+            }
+        });
+    }
+}
+
+fn stop_all(handles: Vec<ProcessHandle>, need_exit: &AtomicBool) {
+    need_exit.store(true, Ordering::Relaxed);
+    for handle in handles {
+        if handle.thread.join().is_err() {
+            log!("A process thread panicked while stopping");
+        }
+    }
+}
+
+/// Runs the configured processes until SIGTERM or Ctrl+C. SIGHUP reloads the config and
+/// restarts everything with it, and `service.forward_signals` are passed on to every
+/// process (Unix only).
+pub fn run() -> Result<(), Box<dyn Error>> {
+    let terminate = Arc::new(AtomicBool::new(false));
+    let reload = Arc::new(AtomicBool::new(false));
+    for signal in [signal_hook::consts::SIGTERM, signal_hook::consts::SIGINT] {
+        // A second signal while stopping exits right away
+        signal_hook::flag::register_conditional_shutdown(signal, 1, terminate.clone())?;
+        signal_hook::flag::register(signal, terminate.clone())?;
+    }
+    #[cfg(unix)]
+    signal_hook::flag::register(signal_hook::consts::SIGHUP, reload.clone())?;
+
+    let reporter = Reporter::new();
+    #[cfg(unix)]
+    let watchdog = crate::sd_notify::watchdog_interval();
+    #[cfg(not(unix))]
+    let watchdog: Option<std::time::Duration> = None;
+
+    let mut config = proc_config::load()?;
+
+    let state_path = runtime_state::path();
+    let mut survivors = runtime_state::survivors(&state_path);
+    if config.service.orphans == OrphanPolicy::Terminate {
+        terminate_survivors(std::mem::take(&mut survivors), &config.processes);
+    }
+
+    let registry = Arc::new(Registry::default());
+    let address = config.service.control_address();
+    if let Err(err) = control_socket::serve(&address, registry.clone()) {
+        log!("Can't listen for commands on {}: {:?}", &address, &err);
+    }
+
+    #[cfg(unix)]
+    let mut forwarded = vec![];
+    loop {
+        #[cfg(unix)]
+        register_forwarded(&mut forwarded, &config.service.forward_signals);
+        logger::set_max_size(config.log.max_size.bytes());
+        journal::set_retention(config.journal.max_age.0, config.journal.max_size.bytes());
+        journal::compact();
+        limits::set_cgroup_root(config.service.cgroup_root.clone());
+        sockets::open(&config.processes);
+        let poll_interval = config.service.poll_interval.0;
+
+        let mut list = ChildProcess::from_configs(config.processes.clone());
+        for process in list.iter_mut() {
+            if let Some(index) = survivors.iter().position(|s| s.name == process.config.name) {
+                process.adopt(survivors.remove(index));
+            }
+        }
+        // No longer configured, so nothing would restart them either
+        terminate_survivors(std::mem::take(&mut survivors), &config.processes);
+
+        let need_exit = Arc::new(AtomicBool::new(false));
+        let handles = run_processes(list, &need_exit, poll_interval);
+        registry.set(&handles);
+
+        let mut ready = false;
+        let mut last_status = String::new();
+        let mut last_pids = vec![];
+        let mut last_ping = Instant::now();
+
+        loop {
+            thread::sleep(poll_interval);
+
+            if terminate.load(Ordering::Relaxed) {
+                log!("Stopping");
+                reporter.notify("STOPPING=1");
+                stop_all(handles, &need_exit);
+                #[cfg(unix)]
+                std::fs::remove_file(&address).ok();
+                runtime_state::remove(&state_path);
+                return Ok(());
+            }
+
+            if reload.swap(false, Ordering::Relaxed) {
+                log!("Reloading");
+                #[cfg(unix)]
+                reporter.notify(&format!(
+                    "RELOADING=1\nMONOTONIC_USEC={}",
+                    crate::sd_notify::monotonic_usec()
+                ));
+                stop_all(handles, &need_exit);
+                match proc_config::load() {
+                    Ok(reloaded) => config = reloaded,
+                    Err(err) => log!("Keeping the previous config: {}", err),
+                }
+                break;
+            }
+
+            #[cfg(unix)]
+            forward(&forwarded, &registry);
+
+            let processes = registry.status();
+            let pids: Vec<u32> = processes.iter().map(|process| process.pid).collect();
+            if pids != last_pids {
+                if let Err(err) = runtime_state::save(&state_path, &processes) {
+                    log!("Can't save {}: {:?}", state_path.display(), &err);
+                }
+                last_pids = pids;
+            }
+
+            let status = summarize(&handles);
+            if !ready && all_started(&handles) {
+                ready = true;
+                log!("Started: {}", status);
+                reporter.notify(&format!("READY=1\nSTATUS={}", status));
+                last_status = status;
+            } else if ready && status != last_status {
+                reporter.notify(&format!("STATUS={}", status));
+                last_status = status;
+            }
+
+            // Stop pinging if a supervision thread died, so systemd restarts us
+            let healthy = handles.iter().all(|handle| {
+                !handle.thread.is_finished()
+                    || handle.status.lock().unwrap().state == ProcessState::Disabled
+            });
+            if let Some(interval) = watchdog {
+                if healthy && last_ping.elapsed() >= interval {
+                    reporter.notify("WATCHDOG=1");
+                    last_ping = Instant::now();
+                }
+            }
+        }
+    }
+}
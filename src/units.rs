@@ -0,0 +1,251 @@
+use std::fmt::{self, Display};
+use std::str::FromStr;
+use std::time::Duration;
+
+use serde::de::{self, Deserializer, Visitor};
+use serde::{Deserialize, Serialize, Serializer};
+
+/// Duration written as `"500ms"`, `"30s"`, `"5m"`, `"2h"` or `"7d"`. Plain numbers are milliseconds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub struct HumanDuration(pub Duration);
+
+/// Size written as `"512B"`, `"64KB"`, `"10MB"` or `"1GB"` (powers of 1024). Plain numbers are bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub struct ByteSize(pub u64);
+
+const DURATION_UNITS: [(&str, u64); 5] = [
+    ("d", 86_400_000),
+    ("h", 3_600_000),
+    ("m", 60_000),
+    ("s", 1_000),
+    ("ms", 1),
+];
+
+const SIZE_UNITS: [(&str, u64); 4] = [("GB", 1 << 30), ("MB", 1 << 20), ("KB", 1 << 10), ("B", 1)];
+
+/// Splits `"10MB"` into `(10, "MB")`.
+fn split_number(text: &str) -> Option<(u64, &str)> {
+    let text = text.trim();
+    let end = text
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(text.len());
+    let number = text[..end].parse().ok()?;
+    Some((number, text[end..].trim()))
+}
+
+impl HumanDuration {
+    pub fn from_millis(millis: u64) -> HumanDuration {
+        HumanDuration(Duration::from_millis(millis))
+    }
+
+    pub fn from_secs(secs: u64) -> HumanDuration {
+        HumanDuration(Duration::from_secs(secs))
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.0.is_zero()
+    }
+}
+
+impl FromStr for HumanDuration {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let (number, unit) = split_number(text).ok_or_else(|| {
+            format!(
+                "invalid duration {:?}: expected a number followed by ms, s, m, h or d",
+                text
+            )
+        })?;
+        let multiplier = match unit {
+            "" => 1,
+            unit => DURATION_UNITS
+                .iter()
+                .find(|(name, _)| *name == unit)
+                .map(|(_, millis)| *millis)
+                .ok_or_else(|| {
+                    format!(
+                        "invalid duration {:?}: unknown unit {:?}, expected ms, s, m, h or d",
+                        text, unit
+                    )
+                })?,
+        };
+        number
+            .checked_mul(multiplier)
+            .map(HumanDuration::from_millis)
+            .ok_or_else(|| format!("invalid duration {:?}: too large", text))
+    }
+}
+
+impl Display for HumanDuration {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let millis = self.0.as_millis() as u64;
+        let (unit, size) = DURATION_UNITS
+            .iter()
+            .find(|(_, size)| millis.is_multiple_of(*size) && millis > 0)
+            .unwrap_or(&("ms", 1));
+        write!(f, "{}{}", millis / size, unit)
+    }
+}
+
+/// Rounded for people: `3d 4h`, `2h 05m`, `7m 30s` or `42s`.
+pub fn format_elapsed(elapsed: Duration) -> String {
+    let secs = elapsed.as_secs();
+    match secs {
+        0..=59 => format!("{}s", secs),
+        60..=3599 => format!("{}m {:02}s", secs / 60, secs % 60),
+        3600..=86399 => format!("{}h {:02}m", secs / 3600, secs % 3600 / 60),
+        _ => format!("{}d {}h", secs / 86400, secs % 86400 / 3600),
+    }
+}
+
+impl ByteSize {
+    pub fn bytes(&self) -> u64 {
+        self.0
+    }
+}
+
+impl FromStr for ByteSize {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let (number, unit) = split_number(text).ok_or_else(|| {
+            format!(
+                "invalid size {:?}: expected a number followed by B, KB, MB or GB",
+                text
+            )
+        })?;
+        let unit = unit.to_ascii_uppercase();
+        let unit = unit
+            .strip_suffix("IB")
+            .map(|u| format!("{}B", u))
+            .unwrap_or(unit);
+        let multiplier = match unit.as_str() {
+            "" => 1,
+            unit => SIZE_UNITS
+                .iter()
+                .find(|(name, _)| *name == unit || name.trim_end_matches('B') == unit)
+                .map(|(_, size)| *size)
+                .ok_or_else(|| {
+                    format!(
+                        "invalid size {:?}: unknown unit {:?}, expected B, KB, MB or GB",
+                        text, unit
+                    )
+                })?,
+        };
+        number
+            .checked_mul(multiplier)
+            .map(ByteSize)
+            .ok_or_else(|| format!("invalid size {:?}: too large", text))
+    }
+}
+
+impl Display for ByteSize {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (unit, size) = SIZE_UNITS
+            .iter()
+            .find(|(_, size)| self.0.is_multiple_of(*size) && self.0 > 0)
+            .unwrap_or(&("B", 1));
+        write!(f, "{}{}", self.0 / size, unit)
+    }
+}
+
+/// Accepts either a string with a unit or a bare integer.
+struct UnitVisitor<T>(&'static str, std::marker::PhantomData<T>);
+
+impl<'de, T> Visitor<'de> for UnitVisitor<T>
+where
+    T: FromStr<Err = String> + From<u64>,
+{
+    type Value = T;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.0)
+    }
+
+    fn visit_u64<E: de::Error>(self, value: u64) -> Result<T, E> {
+        Ok(T::from(value))
+    }
+
+    fn visit_i64<E: de::Error>(self, value: i64) -> Result<T, E> {
+        u64::try_from(value)
+            .map(T::from)
+            .map_err(|_| E::custom(format!("{} can't be negative", self.0)))
+    }
+
+    fn visit_str<E: de::Error>(self, value: &str) -> Result<T, E> {
+        value.parse().map_err(E::custom)
+    }
+}
+
+impl From<u64> for HumanDuration {
+    fn from(millis: u64) -> Self {
+        HumanDuration::from_millis(millis)
+    }
+}
+
+impl From<u64> for ByteSize {
+    fn from(bytes: u64) -> Self {
+        ByteSize(bytes)
+    }
+}
+
+impl<'de> Deserialize<'de> for HumanDuration {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(UnitVisitor(
+            "a duration like \"500ms\", \"30s\" or \"5m\"",
+            std::marker::PhantomData,
+        ))
+    }
+}
+
+impl<'de> Deserialize<'de> for ByteSize {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(UnitVisitor(
+            "a size like \"64KB\" or \"10MB\"",
+            std::marker::PhantomData,
+        ))
+    }
+}
+
+impl Serialize for HumanDuration {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl Serialize for ByteSize {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+#[test]
+fn test_units() {
+    assert_eq!("500ms".parse(), Ok(HumanDuration::from_millis(500)));
+    assert_eq!("30s".parse(), Ok(HumanDuration::from_secs(30)));
+    assert_eq!("5m".parse(), Ok(HumanDuration::from_secs(300)));
+    assert_eq!("250".parse(), Ok(HumanDuration::from_millis(250)));
+    assert_eq!(HumanDuration::from_secs(90).to_string(), "90s");
+    assert_eq!(format_elapsed(Duration::from_secs(3725)), "1h 02m");
+    assert_eq!(HumanDuration::from_secs(7200).to_string(), "2h");
+    assert!("5x"
+        .parse::<HumanDuration>()
+        .unwrap_err()
+        .contains("unknown unit"));
+    assert!("ms".parse::<HumanDuration>().is_err());
+
+    assert_eq!("10MB".parse(), Ok(ByteSize(10 << 20)));
+    assert_eq!("64kib".parse(), Ok(ByteSize(64 << 10)));
+    assert_eq!("4K".parse(), Ok(ByteSize(4 << 10)));
+    assert_eq!(ByteSize(10240).to_string(), "10KB");
+    assert!("10 parsecs".parse::<ByteSize>().is_err());
+
+    let parsed: Vec<HumanDuration> = serde_json::from_str(r#"["1s", 100]"#).unwrap();
+    assert_eq!(
+        parsed,
+        [HumanDuration::from_secs(1), HumanDuration::from_millis(100)]
+    );
+    let err = serde_json::from_str::<HumanDuration>(r#""1 fortnight""#).unwrap_err();
+    assert!(err.to_string().contains("line 1"), "{}", err);
+}
@@ -0,0 +1,227 @@
+//! The account a process runs as: `user`, `group` and `groups` looked up in the user and
+//! group databases. The switch itself is made between fork and exec by `limits`, after the
+//! limits that may need the supervisor's privileges.
+
+use std::path::PathBuf;
+
+use crate::proc_config::ProcessConfig;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Account {
+    pub name: String,
+    pub uid: u32,
+    pub gid: u32,
+    /// Supplementary groups, including `gid`.
+    pub groups: Vec<u32>,
+    pub home: PathBuf,
+}
+
+/// The account `process` runs as, `None` when it keeps the supervisor's.
+#[cfg(target_os = "linux")]
+pub fn account(process: &ProcessConfig) -> Result<Option<Account>, String> {
+    if process.user.is_none() && process.group.is_none() && process.groups.is_empty() {
+        return Ok(None);
+    }
+    let user = match &process.user {
+        Some(user) => linux::user(user)?,
+        None => linux::user(&unsafe { libc::geteuid() }.to_string())?,
+    };
+    let gid = match &process.group {
+        Some(group) => linux::group(group)?,
+        None => user.gid,
+    };
+    let mut groups = match process.groups.is_empty() {
+        true => linux::group_list(&user.name, gid)?,
+        false => process
+            .groups
+            .iter()
+            .map(|group| linux::group(group))
+            .collect::<Result<_, _>>()?,
+    };
+    if !groups.contains(&gid) {
+        groups.insert(0, gid);
+    }
+    Ok(Some(Account {
+        gid,
+        groups,
+        ..user
+    }))
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn account(process: &ProcessConfig) -> Result<Option<Account>, String> {
+    match process.user.is_none() && process.group.is_none() && process.groups.is_empty() {
+        true => Ok(None),
+        false => Err("user, group and groups are only supported on Linux".to_string()),
+    }
+}
+
+/// Fails unless the supervisor may switch to the account of `process`.
+pub fn check(process: &ProcessConfig) -> Result<(), String> {
+    #[cfg(target_os = "linux")]
+    if let Some(account) = account(process)? {
+        if unsafe { libc::geteuid() } != 0 && !linux::is_current(&account) {
+            return Err(format!(
+                "can't run as user {} (uid {}, gid {}): the supervisor isn't running as root",
+                account.name, account.uid, account.gid
+            ));
+        }
+    }
+    // Only fails if an account is configured at all
+    #[cfg(not(target_os = "linux"))]
+    account(process)?;
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+mod linux {
+    use std::ffi::{CStr, CString};
+    use std::os::unix::ffi::OsStrExt;
+    use std::path::PathBuf;
+
+    use super::Account;
+
+    /// Runs a `get*_r` lookup, growing `buffer` until the entry fits. The entry's strings
+    /// point into it.
+    fn lookup(
+        buffer: &mut Vec<libc::c_char>,
+        mut call: impl FnMut(&mut Vec<libc::c_char>) -> libc::c_int,
+    ) -> Result<(), String> {
+        buffer.resize(1024, 0);
+        loop {
+            match call(buffer) {
+                0 => return Ok(()),
+                libc::ERANGE if buffer.len() < 1 << 20 => buffer.resize(buffer.len() * 2, 0),
+                errno => return Err(std::io::Error::from_raw_os_error(errno).to_string()),
+            }
+        }
+    }
+
+    /// A user by name or uid, with its primary group and no supplementary ones yet.
+    pub fn user(user: &str) -> Result<Account, String> {
+        let name = CString::new(user).map_err(|_| format!("invalid user {:?}", user))?;
+        let mut entry: libc::passwd = unsafe { std::mem::zeroed() };
+        let mut found: *mut libc::passwd = std::ptr::null_mut();
+        let by_uid = user.parse::<u32>().ok();
+        let mut buffer = vec![];
+        lookup(&mut buffer, |buffer| unsafe {
+            match by_uid {
+                Some(uid) => libc::getpwuid_r(
+                    uid,
+                    &mut entry,
+                    buffer.as_mut_ptr(),
+                    buffer.len(),
+                    &mut found,
+                ),
+                None => libc::getpwnam_r(
+                    name.as_ptr(),
+                    &mut entry,
+                    buffer.as_mut_ptr(),
+                    buffer.len(),
+                    &mut found,
+                ),
+            }
+        })
+        .map_err(|err| format!("can't look up user {}: {}", user, err))?;
+        if found.is_null() {
+            return Err(format!("unknown user {}", user));
+        }
+        let (name, home) = unsafe { (CStr::from_ptr(entry.pw_name), CStr::from_ptr(entry.pw_dir)) };
+        Ok(Account {
+            name: name.to_string_lossy().into_owned(),
+            uid: entry.pw_uid,
+            gid: entry.pw_gid,
+            groups: vec![],
+            home: PathBuf::from(std::ffi::OsStr::from_bytes(home.to_bytes())),
+        })
+    }
+
+    /// A group by name or gid.
+    pub fn group(group: &str) -> Result<u32, String> {
+        if let Ok(gid) = group.parse::<u32>() {
+            return Ok(gid);
+        }
+        let name = CString::new(group).map_err(|_| format!("invalid group {:?}", group))?;
+        let mut entry: libc::group = unsafe { std::mem::zeroed() };
+        let mut found: *mut libc::group = std::ptr::null_mut();
+        lookup(&mut vec![], |buffer| unsafe {
+            libc::getgrnam_r(
+                name.as_ptr(),
+                &mut entry,
+                buffer.as_mut_ptr(),
+                buffer.len(),
+                &mut found,
+            )
+        })
+        .map_err(|err| format!("can't look up group {}: {}", group, err))?;
+        match found.is_null() {
+            true => Err(format!("unknown group {}", group)),
+            false => Ok(entry.gr_gid),
+        }
+    }
+
+    /// The groups `user` is a member of, as a login would set them up.
+    pub fn group_list(user: &str, gid: u32) -> Result<Vec<u32>, String> {
+        let name = CString::new(user).map_err(|_| format!("invalid user {:?}", user))?;
+        let mut groups: Vec<libc::gid_t> = vec![0; 64];
+        loop {
+            let mut count = groups.len() as libc::c_int;
+            let result =
+                unsafe { libc::getgrouplist(name.as_ptr(), gid, groups.as_mut_ptr(), &mut count) };
+            if result >= 0 {
+                groups.truncate(count as usize);
+                return Ok(groups);
+            }
+            // `count` is now the number needed
+            groups.resize((count as usize).max(groups.len() * 2), 0);
+        }
+    }
+
+    /// Already the supervisor's account, so there's nothing to switch.
+    pub fn is_current(account: &Account) -> bool {
+        let count = unsafe { libc::getgroups(0, std::ptr::null_mut()) };
+        let mut groups: Vec<libc::gid_t> = vec![0; count.max(0) as usize];
+        let count = unsafe { libc::getgroups(groups.len() as libc::c_int, groups.as_mut_ptr()) };
+        groups.truncate(count.max(0) as usize);
+        let (uid, gid) = unsafe { (libc::geteuid(), libc::getegid()) };
+        account.uid == uid
+            && account.gid == gid
+            && account
+                .groups
+                .iter()
+                .all(|group| *group == gid || groups.contains(group))
+    }
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn test_account() {
+    let root = ProcessConfig {
+        user: Some("root".to_string()),
+        ..Default::default()
+    };
+    let found = account(&root).unwrap().unwrap();
+    assert_eq!((found.uid, found.gid), (0, 0));
+    assert_eq!(found.home, PathBuf::from("/root"));
+    assert!(found.groups.contains(&0));
+
+    let numeric = ProcessConfig {
+        user: Some("0".to_string()),
+        group: Some("1".to_string()),
+        groups: vec!["0".to_string()],
+        ..Default::default()
+    };
+    let found = account(&numeric).unwrap().unwrap();
+    assert_eq!((found.name.as_str(), found.gid), ("root", 1));
+    assert_eq!(found.groups, [1, 0]);
+
+    let unknown = ProcessConfig {
+        user: Some("no-such-user-here".to_string()),
+        ..Default::default()
+    };
+    assert_eq!(
+        account(&unknown).unwrap_err(),
+        "unknown user no-such-user-here"
+    );
+    assert_eq!(account(&ProcessConfig::default()), Ok(None));
+}